@@ -0,0 +1,95 @@
+//! Compares flushing every reply with flushing once per pipeline.
+//!
+//! A client sends `--pipeline` PING commands at once and waits for all the
+//! replies, over a loopback connection served by `Connection`. In the
+//! `per-frame` mode, each reply is flushed as soon as it is written. In the
+//! `batched` mode, the replies to all buffered commands are written first and
+//! flushed once, as `MiniRedisServer` does.
+//!
+//! ```sh
+//! cargo run --release --example bench-pipeline -- --pipeline 1000 --seconds 5
+//! cargo run --release --example bench-pipeline -- --connection raw
+//! ```
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use my_mini_redis::connection::{self, ConnectionKind, ConnectionTrait};
+use my_mini_redis::connection_raw;
+use my_mini_redis::frame::Frame;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Number of commands sent at once
+    #[arg(long, default_value_t = 1000)]
+    pipeline: usize,
+
+    /// Duration of each mode in seconds
+    #[arg(long, default_value_t = 3)]
+    seconds: u64,
+
+    /// Connection implementation serving the client
+    #[arg(long, value_enum, default_value_t = ConnectionKind::Buffered)]
+    connection: ConnectionKind,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+
+    for batched in [false, true] {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let kind = args.connection;
+
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            socket.set_nodelay(true).unwrap();
+            match kind {
+                ConnectionKind::Buffered => serve::<connection::Connection>(socket, batched).await,
+                ConnectionKind::Raw => serve::<connection_raw::Connection>(socket, batched).await,
+            }
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.set_nodelay(true).unwrap();
+        let request = b"*1\r\n$4\r\nPING\r\n".repeat(args.pipeline);
+        let mut response = vec![0; b"+PONG\r\n".len() * args.pipeline];
+
+        let start = Instant::now();
+        let mut ops = 0;
+        while start.elapsed() < Duration::from_secs(args.seconds) {
+            client.write_all(&request).await.unwrap();
+            client.read_exact(&mut response).await.unwrap();
+            ops += args.pipeline;
+        }
+
+        let mode = if batched { "batched" } else { "per-frame" };
+        println!(
+            "{:>9} flush, pipeline of {}: {:.0} ops/s",
+            mode,
+            args.pipeline,
+            ops as f64 / start.elapsed().as_secs_f64()
+        );
+    }
+}
+
+/// Replies PONG to every frame received on `socket`.
+async fn serve<C: ConnectionTrait<TcpStream> + Send>(socket: TcpStream, batched: bool) {
+    let mut connection = C::new(socket);
+    let pong = Frame::Simple("PONG".to_string());
+
+    while let Ok(Some(_)) = connection.read_frame().await {
+        connection.write_frame(&pong).await.unwrap();
+
+        // Reply to the commands already buffered before flushing
+        if batched {
+            while let Ok(Some(_)) = connection.parse_frame() {
+                connection.write_frame(&pong).await.unwrap();
+            }
+        }
+
+        connection.flush().await.unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+//! Measures the throughput of a running my-mini-redis server.
+//!
+//! Each client task opens its own connection and alternates SET and GET on
+//! random keys. Compare the result while varying the number of shards and
+//! worker threads of the server, e.g.
+//!
+//! ```sh
+//! TOKIO_WORKER_THREADS=8 cargo run --release -- --shards 1
+//! TOKIO_WORKER_THREADS=8 cargo run --release -- --shards 64
+//! cargo run --release --example bench-throughput -- --clients 64 --seconds 10
+//! ```
+//!
+//! Large values stress copying rather than locking, e.g. 1 MB values:
+//!
+//! ```sh
+//! cargo run --release --example bench-throughput -- --clients 4 --keys 16 --value-size 1048576
+//! ```
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use clap::Parser;
+use mini_redis::client;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Address of the server
+    #[arg(long, default_value = "127.0.0.1:6379")]
+    addr: String,
+
+    /// Number of concurrent clients
+    #[arg(long, default_value_t = 32)]
+    clients: usize,
+
+    /// Number of distinct keys
+    #[arg(long, default_value_t = 10_000)]
+    keys: u64,
+
+    /// Size of the values in bytes
+    #[arg(long, default_value_t = 5)]
+    value_size: usize,
+
+    /// Duration of the benchmark in seconds
+    #[arg(long, default_value_t = 5)]
+    seconds: u64,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let ops = Arc::new(AtomicU64::new(0));
+    let deadline = Instant::now() + Duration::from_secs(args.seconds);
+    let value = Bytes::from(vec![b'x'; args.value_size]);
+
+    let tasks: Vec<_> = (0..args.clients as u64)
+        .map(|id| {
+            let addr = args.addr.clone();
+            let ops = ops.clone();
+            let keys = args.keys;
+            let value = value.clone();
+            tokio::spawn(async move {
+                let mut client = client::connect(addr).await.unwrap();
+                // xorshift で疑似乱数のキーを選ぶ
+                let mut seed = id * 2654435761 + 1;
+
+                while Instant::now() < deadline {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    let key = format!("key:{}", seed % keys);
+
+                    client.set(&key, value.clone()).await.unwrap();
+                    client.get(&key).await.unwrap();
+                    ops.fetch_add(2, Ordering::Relaxed);
+                }
+            })
+        })
+        .collect();
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let ops = ops.load(Ordering::Relaxed);
+    let ops_per_sec = ops as f64 / args.seconds as f64;
+    println!(
+        "{} clients, {} byte values: {} ops in {}s ({:.0} ops/s, {:.1} MB/s)",
+        args.clients,
+        args.value_size,
+        ops,
+        args.seconds,
+        ops_per_sec,
+        ops_per_sec * args.value_size as f64 / (1024.0 * 1024.0)
+    );
+}
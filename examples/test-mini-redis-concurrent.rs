@@ -1,7 +1,22 @@
+use bytes::Bytes;
 use mini_redis::client;
 use tokio::sync::{mpsc, oneshot};
 
-use my_mini_redis::command::Command;
+type Responder<T> = oneshot::Sender<mini_redis::Result<T>>;
+
+/// manager タスクへ送信するコマンド
+#[derive(Debug)]
+enum Command {
+    Get {
+        key: String,
+        resp: Responder<Option<Bytes>>,
+    },
+    Set {
+        key: String,
+        value: Bytes,
+        resp: Responder<()>,
+    },
+}
 
 #[tokio::main]
 async fn main() {
@@ -0,0 +1,493 @@
+//! Access control lists: users, their passwords, and the commands and keys
+//! they may access.
+//!
+//! Users are configured with rules in the same syntax as Redis, e.g.
+//! `on >password ~cache:* +@read`.
+
+use crate::command::Command;
+use crate::glob::glob_match;
+
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+use std::path::Path;
+use std::sync::RwLock;
+
+/// Commands known to the ACL, along with their categories.
+///
+/// `+@category` and `-@category` rules allow or deny all the commands of a
+/// category.
+const COMMANDS: &[(&str, &[&str])] = &[
+    ("acl", &["admin", "dangerous"]),
+    ("auth", &["connection"]),
+    ("bgrewriteaof", &["admin", "dangerous"]),
+    ("bgsave", &["admin", "dangerous"]),
+    ("config", &["admin", "dangerous"]),
+    ("debug", &["admin", "dangerous"]),
+    ("del", &["keyspace", "write"]),
+    ("dump", &["keyspace", "read"]),
+    ("expire", &["keyspace", "write"]),
+    ("expireat", &["keyspace", "write"]),
+    ("get", &["read", "string"]),
+    ("hello", &["connection"]),
+    ("info", &["dangerous"]),
+    ("keys", &["keyspace", "read", "dangerous"]),
+    ("lastsave", &["admin", "dangerous"]),
+    ("mget", &["read", "string"]),
+    ("migrate", &["keyspace", "write", "dangerous"]),
+    ("persist", &["keyspace", "write"]),
+    ("pexpire", &["keyspace", "write"]),
+    ("pexpireat", &["keyspace", "write"]),
+    ("ping", &["connection"]),
+    ("pttl", &["keyspace", "read"]),
+    ("rename", &["keyspace", "write"]),
+    ("restore", &["keyspace", "write", "dangerous"]),
+    ("save", &["admin", "dangerous"]),
+    ("scan", &["keyspace", "read"]),
+    ("set", &["string", "write"]),
+    ("ttl", &["keyspace", "read"]),
+    ("type", &["keyspace", "read"]),
+];
+
+/// Users of the server.
+///
+/// There is always a `default` user, which clients are authenticated as when
+/// they connect if it has the `nopass` flag. Initially, the `default` user may
+/// run any command on any key without a password.
+#[derive(Debug)]
+pub struct Acl {
+    users: RwLock<BTreeMap<String, User>>,
+}
+
+/// A user and its permissions.
+#[derive(Debug, Clone)]
+struct User {
+    /// whether clients may authenticate as the user
+    enabled: bool,
+    /// whether any password is accepted
+    nopass: bool,
+    /// SHA-256 hashes of the passwords, hex encoded
+    passwords: BTreeSet<String>,
+    /// whether commands are allowed unless listed in `exceptions`, or denied
+    /// unless listed
+    all_commands: bool,
+    exceptions: BTreeSet<&'static str>,
+    /// glob-style patterns of the keys the user may access
+    keys: Vec<String>,
+}
+
+/// Reason a command is rejected by `Acl::check`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Denied {
+    /// The user was deleted or disabled since the client authenticated.
+    User,
+    /// The user may not run the command.
+    Command,
+    /// The user may not access one of the keys of the command.
+    Key,
+}
+
+impl Acl {
+    /// Load users from an ACL file.
+    ///
+    /// Each line of the file defines a user as `user <name> <rules>...`. Empty
+    /// lines are ignored. Users are created with the `default` user already
+    /// defined, so lines for `default` modify it.
+    pub fn load(path: &Path) -> crate::Result<Acl> {
+        let acl = Acl::default();
+        let contents = std::fs::read_to_string(path)?;
+
+        for (i, line) in contents.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            let name = match (tokens.next(), tokens.next()) {
+                (None, _) => continue,
+                (Some("user"), Some(name)) => name,
+                _ => {
+                    return Err(format!(
+                        "{}:{}: expected `user <name> <rules>...`",
+                        path.display(),
+                        i + 1
+                    )
+                    .into())
+                }
+            };
+
+            let rules: Vec<&str> = tokens.collect();
+            acl.set_user(name, &rules)
+                .map_err(|err| format!("{}:{}: {}", path.display(), i + 1, err))?;
+        }
+
+        Ok(acl)
+    }
+
+    /// Create the user `name` if it does not exist, then apply `rules` to it.
+    ///
+    /// If a rule is invalid, an error describing it is returned and the user is
+    /// left unchanged.
+    pub fn set_user(&self, name: &str, rules: &[impl AsRef<str>]) -> Result<(), String> {
+        let mut users = self.users.write().unwrap();
+        let mut user = users.get(name).cloned().unwrap_or_default();
+
+        for rule in rules {
+            let rule = rule.as_ref();
+            user.apply(rule)
+                .map_err(|err| format!("Error in ACL SETUSER modifier '{}': {}", rule, err))?;
+        }
+
+        users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    /// Delete the users in `names`, returning how many existed.
+    pub(crate) fn del_users(&self, names: &[String]) -> Result<usize, String> {
+        if names.iter().any(|name| name == "default") {
+            return Err("The 'default' user cannot be removed".to_string());
+        }
+
+        let mut users = self.users.write().unwrap();
+        Ok(names
+            .iter()
+            .filter(|&name| users.remove(name).is_some())
+            .count())
+    }
+
+    /// Returns the names of all users, in alphabetical order.
+    pub(crate) fn usernames(&self) -> Vec<String> {
+        self.users.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Returns the flags, password hashes, command rules and key patterns of
+    /// the user `name`, as reported by `ACL GETUSER`.
+    pub(crate) fn describe(&self, name: &str) -> Option<UserInfo> {
+        let users = self.users.read().unwrap();
+        let user = users.get(name)?;
+
+        let mut flags = vec![if user.enabled { "on" } else { "off" }];
+        if user.nopass {
+            flags.push("nopass");
+        }
+
+        Some(UserInfo {
+            flags,
+            passwords: user.passwords.iter().cloned().collect(),
+            commands: user.command_rules(),
+            keys: user.key_rules(),
+        })
+    }
+
+    /// Returns the rules defining the user `name`, as listed by `ACL LIST`.
+    pub(crate) fn rules(&self, name: &str) -> Option<String> {
+        let users = self.users.read().unwrap();
+        users.get(name).map(|user| user.to_string())
+    }
+
+    /// Returns `true` if clients may authenticate as `name` with `password`.
+    ///
+    /// Users with the `nopass` flag are authenticated without a password.
+    pub(crate) fn authenticate(&self, name: &str, password: Option<&str>) -> bool {
+        let users = self.users.read().unwrap();
+        let user = match users.get(name) {
+            Some(user) if user.enabled => user,
+            _ => return false,
+        };
+
+        user.nopass || password.is_some_and(|password| user.passwords.contains(&hash(password)))
+    }
+
+    /// Check that the user `name` may run `cmd` on its keys.
+    pub(crate) fn check(&self, name: &str, cmd: &Command) -> Result<(), Denied> {
+        let users = self.users.read().unwrap();
+        let user = match users.get(name) {
+            Some(user) if user.enabled => user,
+            _ => return Err(Denied::User),
+        };
+
+        if !user.allows(cmd.get_name()) {
+            return Err(Denied::Command);
+        }
+
+        // `~*` は全てのキーにマッチするので、コマンドのキーを調べるまでもない
+        if user.keys.iter().all(|pattern| pattern != "*") {
+            let allowed = |key: &str| {
+                user.keys
+                    .iter()
+                    .any(|pattern| glob_match(pattern.as_bytes(), key.as_bytes()))
+            };
+            if !cmd.keys().into_iter().all(allowed) {
+                return Err(Denied::Key);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Acl {
+    fn default() -> Acl {
+        let default = User {
+            enabled: true,
+            nopass: true,
+            all_commands: true,
+            keys: vec!["*".to_string()],
+            ..User::default()
+        };
+
+        Acl {
+            users: RwLock::new(BTreeMap::from([("default".to_string(), default)])),
+        }
+    }
+}
+
+/// Description of a user returned by `Acl::describe`.
+pub(crate) struct UserInfo {
+    pub(crate) flags: Vec<&'static str>,
+    pub(crate) passwords: Vec<String>,
+    pub(crate) commands: String,
+    pub(crate) keys: String,
+}
+
+impl User {
+    /// Apply a single rule to the user.
+    fn apply(&mut self, rule: &str) -> Result<(), &'static str> {
+        match rule {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.apply("~*")?,
+            "resetkeys" => self.keys.clear(),
+            "allcommands" => self.apply("+@all")?,
+            "nocommands" => self.apply("-@all")?,
+            "reset" => *self = User::default(),
+            _ => {
+                let (prefix, arg) = rule.split_at(rule.chars().next().map_or(0, char::len_utf8));
+                match prefix {
+                    ">" => {
+                        self.nopass = false;
+                        self.passwords.insert(hash(arg));
+                    }
+                    "<" => {
+                        if !self.passwords.remove(&hash(arg)) {
+                            return Err("no such password");
+                        }
+                    }
+                    "#" => {
+                        if arg.len() != 64 || !arg.bytes().all(|b| b.is_ascii_hexdigit()) {
+                            return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters");
+                        }
+                        self.nopass = false;
+                        self.passwords.insert(arg.to_ascii_lowercase());
+                    }
+                    "!" => {
+                        if !self.passwords.remove(&arg.to_ascii_lowercase()) {
+                            return Err("no such password");
+                        }
+                    }
+                    "~" => {
+                        if !self.keys.iter().any(|pattern| pattern == arg) {
+                            self.keys.push(arg.to_string());
+                        }
+                    }
+                    "+" => self.set_commands(arg, true)?,
+                    "-" => self.set_commands(arg, false)?,
+                    _ => return Err("Syntax error"),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Allow or deny the command, or the commands of the `@category`, `arg`.
+    fn set_commands(&mut self, arg: &str, allow: bool) -> Result<(), &'static str> {
+        if arg == "@all" {
+            self.all_commands = allow;
+            self.exceptions.clear();
+            return Ok(());
+        }
+
+        let commands: Vec<&'static str> = match arg.strip_prefix('@') {
+            Some(category) => {
+                let commands: Vec<_> = COMMANDS
+                    .iter()
+                    .filter(|(_, categories)| categories.contains(&category))
+                    .map(|(name, _)| *name)
+                    .collect();
+                if commands.is_empty() {
+                    return Err("Unknown command or category name in ACL");
+                }
+                commands
+            }
+            None => {
+                let arg = arg.to_lowercase();
+                match COMMANDS.iter().find(|(name, _)| *name == arg) {
+                    Some((name, _)) => vec![*name],
+                    None => return Err("Unknown command or category name in ACL"),
+                }
+            }
+        };
+
+        for name in commands {
+            // `all_commands` と同じ向きなら例外から外し、逆向きなら例外に加える
+            if allow == self.all_commands {
+                self.exceptions.remove(name);
+            } else {
+                self.exceptions.insert(name);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if the user may run the command `name`.
+    fn allows(&self, name: &str) -> bool {
+        self.all_commands != self.exceptions.contains(name)
+    }
+
+    /// Rules giving the user its command permissions, e.g. `+@all -del`.
+    fn command_rules(&self) -> String {
+        let (base, exception) = if self.all_commands {
+            ("+@all", '-')
+        } else {
+            ("-@all", '+')
+        };
+
+        let mut rules = base.to_string();
+        for name in &self.exceptions {
+            let _ = write!(rules, " {}{}", exception, name);
+        }
+        rules
+    }
+
+    /// Rules giving the user its key patterns, e.g. `~cache:* ~session:*`.
+    fn key_rules(&self) -> String {
+        let patterns: Vec<_> = self
+            .keys
+            .iter()
+            .map(|pattern| format!("~{}", pattern))
+            .collect();
+        patterns.join(" ")
+    }
+}
+
+impl Default for User {
+    /// A new user is disabled, has no password and may not run any command.
+    fn default() -> User {
+        User {
+            enabled: false,
+            nopass: false,
+            passwords: BTreeSet::new(),
+            all_commands: false,
+            exceptions: BTreeSet::new(),
+            keys: vec![],
+        }
+    }
+}
+
+impl fmt::Display for User {
+    /// Format the user as the rules recreating it, e.g.
+    /// `on #<hash> ~cache:* -@all +get`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.enabled { "on" } else { "off" })?;
+        if self.nopass {
+            f.write_str(" nopass")?;
+        }
+        for password in &self.passwords {
+            write!(f, " #{}", password)?;
+        }
+        if !self.keys.is_empty() {
+            write!(f, " {}", self.key_rules())?;
+        }
+        write!(f, " {}", self.command_rules())
+    }
+}
+
+/// Returns `true` if the command `name` belongs to `category`, e.g. `write`.
+pub(crate) fn in_category(name: &str, category: &str) -> bool {
+    COMMANDS
+        .iter()
+        .any(|(command, categories)| *command == name && categories.contains(&category))
+}
+
+/// Hash `password` as stored in `User::passwords`.
+fn hash(password: &str) -> String {
+    let digest = Sha256::digest(password.as_bytes());
+    digest
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Del, Get, Set};
+
+    #[test]
+    fn rules_restrict_commands_and_keys() {
+        let acl = Acl::default();
+        acl.set_user("dashboard", &["on", ">secret", "~cache:*", "+@read"])
+            .unwrap();
+        acl.set_user("ingester", &["on", "nopass", "allkeys", "+@all", "-@read"])
+            .unwrap();
+
+        assert!(acl.authenticate("dashboard", Some("secret")));
+        assert!(!acl.authenticate("dashboard", Some("wrong")));
+        assert!(acl.authenticate("ingester", None));
+        assert!(!acl.authenticate("nobody", None));
+
+        let get = |key| Command::Get(Get::new(key));
+        let set = |key| Command::Set(Set::new(key, "v".into(), None));
+        assert_eq!(acl.check("dashboard", &get("cache:1")), Ok(()));
+        assert_eq!(acl.check("dashboard", &get("session:1")), Err(Denied::Key));
+        assert_eq!(
+            acl.check("dashboard", &set("cache:1")),
+            Err(Denied::Command)
+        );
+        assert_eq!(acl.check("ingester", &set("session:1")), Ok(()));
+        assert_eq!(
+            acl.check("ingester", &get("session:1")),
+            Err(Denied::Command)
+        );
+
+        let del = Command::Del(Del::new(vec![
+            "cache:1".to_string(),
+            "session:1".to_string(),
+        ]));
+        acl.set_user("dashboard", &["+del"]).unwrap();
+        assert_eq!(acl.check("dashboard", &del), Err(Denied::Key));
+
+        assert_eq!(
+            acl.rules("dashboard").unwrap(),
+            format!(
+                "on #{} ~cache:* -@all +del +dump +get +keys +mget +pttl +scan +ttl +type",
+                hash("secret")
+            )
+        );
+        assert_eq!(acl.rules("default").unwrap(), "on nopass ~* +@all");
+
+        acl.set_user("dashboard", &["off"]).unwrap();
+        assert_eq!(acl.check("dashboard", &get("cache:1")), Err(Denied::User));
+    }
+
+    #[test]
+    fn invalid_rules_leave_the_user_unchanged() {
+        let acl = Acl::default();
+        let err = acl
+            .set_user("default", &["off", "+nosuchcommand"])
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "Error in ACL SETUSER modifier '+nosuchcommand': Unknown command or category name in ACL"
+        );
+        assert_eq!(acl.rules("default").unwrap(), "on nopass ~* +@all");
+    }
+}
@@ -0,0 +1,540 @@
+//! Append-only file persistence.
+//!
+//! Every write to the keyspace is appended to the AOF as the RESP array of the
+//! command, and replaying the file on startup rebuilds the keyspace. Commands
+//! that leave a key with a deadline are followed by a `PEXPIREAT` giving the
+//! deadline as a Unix time, so that replaying them later does not extend it.
+//!
+//! The log only grows, so `BGREWRITEAOF` replaces it with the shortest list of
+//! commands producing the current keyspace: a `SET` per key.
+
+use crate::acl::Acl;
+use crate::clock;
+use crate::command::Command;
+use crate::config::Config;
+use crate::frame::{self, Frame};
+use crate::session::Session;
+use crate::stats::Stats;
+use crate::storage::Storage;
+
+use bytes::Bytes;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+use tokio::time::{self, Instant};
+
+/// When the AOF is flushed to disk, as set by `appendfsync`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FsyncPolicy {
+    /// After every write: no write is lost, but each one waits for the disk
+    Always,
+    /// Once per second, by a background task
+    EverySec,
+    /// When the OS decides to
+    No,
+}
+
+/// The append-only file, shared by all connections.
+///
+/// Write commands are appended to an in-memory buffer, which a background
+/// task writes to the file and flushes to disk as set by `appendfsync`, so
+/// that clients do not wait for the disk while holding the lock.
+#[derive(Debug)]
+pub(crate) struct Aof {
+    path: PathBuf,
+    /// `appendfsync` is read on every write, so that it can be changed with
+    /// `CONFIG SET`
+    config: Arc<Config>,
+    pending: Mutex<Pending>,
+    /// Only the background task and the end of a rewrite write to the file.
+    /// It is locked before `pending` when both are needed.
+    file: Mutex<FileState>,
+    /// Wakes up the background task when commands are appended.
+    appended: Arc<Notify>,
+    /// Sequence number of the last command written to the file, and flushed
+    /// to disk if `appendfsync` is `always`.
+    written: watch::Sender<u64>,
+}
+
+/// Commands appended but not yet written to the file.
+#[derive(Debug)]
+struct Pending {
+    buf: Vec<u8>,
+    /// sequence number of the last command appended
+    seq: u64,
+    /// Writes made while `BGREWRITEAOF` writes the new file. They are
+    /// appended to it before it replaces the current file.
+    rewrite_buffer: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct FileState {
+    file: File,
+    /// whether data was written since the last fsync
+    unsynced: bool,
+    last_sync: std::time::Instant,
+}
+
+/// Exclusive access to the AOF, see `Aof::lock`.
+pub(crate) struct AofGuard<'a> {
+    aof: &'a Aof,
+    pending: MutexGuard<'a, Pending>,
+}
+
+impl Aof {
+    /// Open the AOF at `path` for appending.
+    ///
+    /// If the file does not exist, it is created with the current content of
+    /// `db`, e.g. loaded from a snapshot. A background task writing the
+    /// appended commands runs until the `Aof` is dropped, so this must be
+    /// called from within a Tokio runtime.
+    pub(crate) fn open(
+        path: PathBuf,
+        config: Arc<Config>,
+        db: &dyn Storage,
+    ) -> io::Result<Arc<Aof>> {
+        if !path.exists() {
+            write_base(&path, db)?;
+        }
+        let file = OpenOptions::new().append(true).open(&path)?;
+
+        let aof = Arc::new(Aof {
+            path,
+            config,
+            pending: Mutex::new(Pending {
+                buf: Vec::new(),
+                seq: 0,
+                rewrite_buffer: None,
+            }),
+            file: Mutex::new(FileState {
+                file,
+                unsynced: false,
+                last_sync: std::time::Instant::now(),
+            }),
+            appended: Arc::new(Notify::new()),
+            written: watch::channel(0).0,
+        });
+        tokio::spawn(write_in_background(
+            Arc::downgrade(&aof),
+            aof.appended.clone(),
+        ));
+        Ok(aof)
+    }
+
+    /// Lock the AOF.
+    ///
+    /// Write commands are applied while holding the lock, then appended
+    /// through the guard, so that commands are logged in the order they
+    /// modified the keyspace. Appending only copies the command to memory:
+    /// the file is written and flushed after the lock is released.
+    pub(crate) fn lock(&self) -> AofGuard<'_> {
+        AofGuard {
+            aof: self,
+            pending: lock(&self.pending),
+        }
+    }
+
+    /// Wait until the command appended with the sequence number `seq` is
+    /// flushed to disk, if `appendfsync` is `always`.
+    pub(crate) async fn wait(&self, seq: u64) {
+        if self.fsync_policy() != FsyncPolicy::Always {
+            return;
+        }
+        let mut written = self.written.subscribe();
+        let _ = written.wait_for(|&written| written >= seq).await;
+    }
+
+    /// Write the appended commands to the file, and flush it to disk, e.g.
+    /// before shutting down.
+    pub(crate) fn sync(&self) -> io::Result<()> {
+        self.write_pending(true)
+    }
+
+    /// Write the appended commands to the file, and flush it to disk if `sync`
+    /// is set or as `appendfsync` requires.
+    fn write_pending(&self, sync: bool) -> io::Result<()> {
+        let mut state = lock(&self.file);
+        let (buf, seq) = {
+            let mut pending = lock(&self.pending);
+            (std::mem::take(&mut pending.buf), pending.seq)
+        };
+
+        let sync = sync
+            || match self.fsync_policy() {
+                FsyncPolicy::Always => true,
+                FsyncPolicy::EverySec => state.last_sync.elapsed() >= Duration::from_secs(1),
+                FsyncPolicy::No => false,
+            };
+        let result = (|| {
+            if !buf.is_empty() {
+                state.file.write_all(&buf)?;
+                state.unsynced = true;
+            }
+            if sync && state.unsynced {
+                state.file.sync_data()?;
+                state.unsynced = false;
+                state.last_sync = std::time::Instant::now();
+            }
+            Ok(())
+        })();
+        drop(state);
+
+        // 書き込みに失敗した場合も、待っているクライアントには応答させる
+        self.written_up_to(seq);
+        result
+    }
+
+    fn written_up_to(&self, seq: u64) {
+        self.written.send_if_modified(|written| {
+            let modified = seq > *written;
+            *written = (*written).max(seq);
+            modified
+        });
+    }
+
+    /// Rewrite the AOF from the current content of `db` in the background.
+    ///
+    /// The keyspace is copied before returning. The new file is then written
+    /// on a blocking thread while clients keep running commands, which are
+    /// logged to both files. Only one rewrite runs at a time.
+    pub(crate) fn bgrewrite(
+        self: &Arc<Self>,
+        db: &dyn Storage,
+        stats: &Arc<Stats>,
+    ) -> Result<(), &'static str> {
+        if stats.aof_rewrite_in_progress.swap(true, Ordering::SeqCst) {
+            return Err("Background append only file rewriting already in progress");
+        }
+
+        // AOF のロック中は書き込みコマンドが実行されないので、コピーと記録の開始が同じ時点になる
+        let mut pending = lock(&self.pending);
+        let entries = db.snapshot();
+        pending.rewrite_buffer = Some(Vec::new());
+        drop(pending);
+
+        let aof = self.clone();
+        let stats = stats.clone();
+        tokio::task::spawn_blocking(move || {
+            match aof.rewrite(&entries) {
+                Ok(()) => tracing::info!("Background AOF rewrite terminated with success"),
+                Err(err) => {
+                    lock(&aof.pending).rewrite_buffer = None;
+                    tracing::error!("Background AOF rewrite error: {}", err);
+                }
+            }
+            stats.aof_rewrite_in_progress.store(false, Ordering::SeqCst);
+        });
+
+        Ok(())
+    }
+
+    /// Write `entries` to a new file, followed by the writes made since they
+    /// were copied, and replace the AOF with it.
+    fn rewrite(&self, entries: &[(String, Bytes, Option<Instant>)]) -> io::Result<()> {
+        let tmp = self
+            .path
+            .with_file_name(format!("temp-rewriteaof-{}.aof", std::process::id()));
+        let result = self.replace_with(&tmp, entries);
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        result
+    }
+
+    fn replace_with(
+        &self,
+        tmp: &Path,
+        entries: &[(String, Bytes, Option<Instant>)],
+    ) -> io::Result<()> {
+        let mut file = File::create(tmp)?;
+        file.write_all(&encode_entries(entries))?;
+        file.sync_data()?;
+
+        // 書き出しの間に追記されたコマンドを加えてから、ファイルを置き換える
+        // まだ書き込まれていないコマンドは、コピーか rewrite_buffer のどちらかに含まれている
+        let mut state = lock(&self.file);
+        let mut pending = lock(&self.pending);
+        let buffer = pending.rewrite_buffer.take().unwrap_or_default();
+        file.write_all(&buffer)?;
+        file.sync_data()?;
+        std::fs::rename(tmp, &self.path)?;
+
+        state.file = OpenOptions::new().append(true).open(&self.path)?;
+        state.unsynced = false;
+        pending.buf.clear();
+        let seq = pending.seq;
+        drop(pending);
+        drop(state);
+
+        self.written_up_to(seq);
+        Ok(())
+    }
+
+    fn fsync_policy(&self) -> FsyncPolicy {
+        match &self.config.get_as::<String>("appendfsync")[..] {
+            "always" => FsyncPolicy::Always,
+            "no" => FsyncPolicy::No,
+            _ => FsyncPolicy::EverySec,
+        }
+    }
+}
+
+impl Drop for Aof {
+    fn drop(&mut self) {
+        if let Err(err) = self.write_pending(true) {
+            tracing::error!("Error writing to the AOF: {}", err);
+        }
+    }
+}
+
+impl AofGuard<'_> {
+    /// Append `command`, which modified `keys` of `db`, returning its
+    /// sequence number, see `Aof::wait`.
+    pub(crate) fn append(&mut self, command: &Frame, keys: &[String], db: &dyn Storage) -> u64 {
+        let mut buf = Vec::new();
+        if let Frame::Array(args) = command {
+            encode_command(&mut buf, args.iter().map(arg_bytes));
+        }
+        for key in keys {
+            if let Some(Some(when)) = db.expires_at(key) {
+                encode_pexpireat(&mut buf, key, when);
+            }
+        }
+
+        if let Some(rewrite_buffer) = &mut self.pending.rewrite_buffer {
+            rewrite_buffer.extend_from_slice(&buf);
+        }
+        self.pending.buf.extend_from_slice(&buf);
+        self.pending.seq += 1;
+        self.aof.appended.notify_one();
+        self.pending.seq
+    }
+}
+
+/// Replay the AOF at `path` against `db`, returning the number of commands
+/// replayed.
+///
+/// A command cut short at the end of the file, e.g. by a crash while it was
+/// appended, is discarded and the file truncated before it.
+pub(crate) fn load(db: &dyn Storage, path: &Path) -> crate::Result<usize> {
+    let data = Bytes::from(std::fs::read(path)?);
+    let mut session = Session::new(
+        Arc::new(Acl::default()),
+        Arc::new(Config::default()),
+        Arc::new(Stats::default()),
+    );
+
+    let mut pos = 0;
+    let mut replayed = 0;
+    while pos < data.len() {
+        let mut cursor = Cursor::new(&data[pos..]);
+        match Frame::check(&mut cursor) {
+            Ok(()) => {}
+            Err(frame::Error::Incomplete) => {
+                tracing::warn!(
+                    "AOF {} is truncated, discarding the last {} bytes",
+                    path.display(),
+                    data.len() - pos
+                );
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)?
+                    .set_len(pos as u64)?;
+                break;
+            }
+            Err(frame::Error::Other(err)) => {
+                return Err(format!(
+                    "{}: bad file format at offset {}: {}",
+                    path.display(),
+                    pos,
+                    err
+                )
+                .into())
+            }
+        }
+        let len = cursor.position() as usize;
+        let frame = Frame::parse(&mut Cursor::new(&data.slice(pos..pos + len)))?;
+        pos += len;
+
+        match Command::from_frame(frame)? {
+            Command::Unknown(cmd) => {
+                return Err(format!(
+                    "{}: unknown command '{}' at offset {}",
+                    path.display(),
+                    cmd.get_name(),
+                    pos - len
+                )
+                .into())
+            }
+            cmd => {
+                cmd.apply(db, &mut session);
+            }
+        }
+        replayed += 1;
+    }
+
+    Ok(replayed)
+}
+
+/// Create the AOF at `path` with the current content of `db`.
+fn write_base(path: &Path, db: &dyn Storage) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(&encode_entries(&db.snapshot()))?;
+    file.sync_data()
+}
+
+/// Write the commands appended to the AOF as they come, and at least every
+/// second so that it is flushed to disk if `appendfsync` is `everysec`, until
+/// it is dropped.
+async fn write_in_background(aof: Weak<Aof>, appended: Arc<Notify>) {
+    loop {
+        let _ = time::timeout(Duration::from_secs(1), appended.notified()).await;
+        let aof = match aof.upgrade() {
+            Some(aof) => aof,
+            None => return,
+        };
+
+        // ファイルへの書き込みと fsync はブロックするので、専用のスレッドで行う
+        let result = tokio::task::spawn_blocking(move || aof.write_pending(false))
+            .await
+            .unwrap_or_else(|err| Err(io::Error::other(err)));
+        if let Err(err) = result {
+            tracing::error!("Error writing to the AOF: {}", err);
+        }
+    }
+}
+
+/// Lock `mutex`, even if a thread panicked while holding it.
+///
+/// Commands are only appended once applied, so a command panicking while the
+/// AOF is locked leaves nothing half written.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Encode a `SET` for each of `entries`, followed by a `PEXPIREAT` for those
+/// with a deadline.
+fn encode_entries(entries: &[(String, Bytes, Option<Instant>)]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for (key, value, expires_at) in entries {
+        encode_command(
+            &mut buf,
+            [
+                Bytes::from_static(b"SET"),
+                Bytes::copy_from_slice(key.as_bytes()),
+                value.clone(),
+            ],
+        );
+        if let Some(when) = expires_at {
+            encode_pexpireat(&mut buf, key, *when);
+        }
+    }
+    buf
+}
+
+fn encode_pexpireat(buf: &mut Vec<u8>, key: &str, when: Instant) {
+    encode_command(
+        buf,
+        [
+            Bytes::from_static(b"PEXPIREAT"),
+            Bytes::copy_from_slice(key.as_bytes()),
+            Bytes::from(clock::to_unix_ms(when).to_string()),
+        ],
+    );
+}
+
+/// Encode a command as a RESP array of bulk strings.
+fn encode_command(buf: &mut Vec<u8>, args: impl IntoIterator<Item = Bytes>) {
+    let args: Vec<_> = args.into_iter().collect();
+    let _ = write!(buf, "*{}\r\n", args.len());
+    for arg in args {
+        let _ = write!(buf, "${}\r\n", arg.len());
+        buf.extend_from_slice(&arg);
+        buf.extend_from_slice(b"\r\n");
+    }
+}
+
+fn arg_bytes(arg: &Frame) -> Bytes {
+    match arg {
+        Frame::Bulk(data) => data.clone(),
+        Frame::Simple(s) => Bytes::copy_from_slice(s.as_bytes()),
+        frame => Bytes::from(frame.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::storage::SetCondition;
+
+    fn command(args: &[&str]) -> Frame {
+        let mut frame = Frame::array();
+        for arg in args {
+            frame.push_bulk(Bytes::copy_from_slice(arg.as_bytes()));
+        }
+        frame
+    }
+
+    #[tokio::test]
+    async fn replay_rewrite_and_truncated_tail() {
+        let dir = std::env::temp_dir().join(format!("mini-redis-aof-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("appendonly.aof");
+
+        let db = Db::new(1);
+        let aof = Aof::open(path.clone(), Arc::new(Config::default()), &db).unwrap();
+        let mut session = Session::new(Default::default(), Default::default(), Default::default());
+        for args in [
+            &["SET", "a", "1"][..],
+            &["SET", "a", "2"],
+            &["SET", "b", "x", "EX", "3600"],
+            &["DEL", "c"],
+        ] {
+            let cmd = Command::from_frame(command(args)).unwrap();
+            let keys: Vec<_> = cmd.keys().iter().map(|key| key.to_string()).collect();
+            let mut log = aof.lock();
+            cmd.apply(&db, &mut session);
+            log.append(&command(args), &keys, &db);
+        }
+
+        // A command panicking while the AOF is locked does not prevent
+        // appending the following ones
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _log = aof.lock();
+            panic!("command panicked");
+        }));
+        aof.sync().unwrap();
+
+        // SET b ... EX 3600 is followed by PEXPIREAT
+        let replayed = Db::new(1);
+        assert_eq!(load(&replayed, &path).unwrap(), 5);
+        assert_eq!(replayed.get("a"), Some("2".into()));
+        assert!(matches!(replayed.expires_at("b"), Some(Some(_))));
+
+        // Rewriting keeps one SET per key, along with writes made meanwhile
+        let stats = Arc::new(Stats::default());
+        aof.bgrewrite(&db, &stats).unwrap();
+        aof.lock().append(&command(&["SET", "c", "3"]), &[], &db);
+        db.set("c".to_string(), "3".into(), None, SetCondition::Always);
+        while stats.aof_rewrite_in_progress.load(Ordering::SeqCst) {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        let replayed = Db::new(1);
+        assert_eq!(load(&replayed, &path).unwrap(), 4);
+        assert_eq!(replayed.get("c"), Some("3".into()));
+
+        // A command cut short by a crash is dropped
+        let len = std::fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nd").unwrap();
+        assert_eq!(load(&Db::new(1), &path).unwrap(), 4);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
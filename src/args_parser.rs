@@ -1,13 +1,175 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
 
+use crate::config::Config;
+use crate::connection::ConnectionKind;
+
+/// Command line options.
+///
+/// Options override the parameters of the same name read from the config
+/// file. Parameters set by neither keep their default value.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct ArgsParser {
-    /// IP address to bind to
-    #[arg(short, long, default_value = "127.0.0.1")]
-    pub ip: String,
+    /// redis.conf-style file to read the configuration from
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// IP address to bind to [default: 127.0.0.1]
+    #[arg(short, long)]
+    pub ip: Option<String>,
+
+    /// Port number to bind to, 0 to not listen on TCP [default: 6379]
+    #[arg(short, long)]
+    pub port: Option<u16>,
+
+    /// Maximum number of clients connected at the same time [default: 10000]
+    #[arg(long)]
+    pub maxclients: Option<usize>,
+
+    /// Connection implementation used to serve clients [default: buffered]
+    #[arg(long, value_enum)]
+    pub connection: Option<ConnectionKind>,
+
+    /// Number of shards the keyspace is split into [default: 16]
+    #[arg(long)]
+    pub shards: Option<NonZeroUsize>,
+
+    /// Password clients must send with `AUTH` before running commands
+    #[arg(long)]
+    pub requirepass: Option<String>,
+
+    /// File defining the ACL users, one `user <name> <rules>...` per line
+    ///
+    /// The password of the `default` user is set in the file, so this cannot
+    /// be combined with `--requirepass`.
+    #[arg(long)]
+    pub aclfile: Option<PathBuf>,
+
+    /// Directory the dump file is written to and loaded from [default: .]
+    #[arg(long)]
+    pub dir: Option<PathBuf>,
+
+    /// Name of the dump file in `--dir` [default: dump.rdb]
+    #[arg(long)]
+    pub dbfilename: Option<String>,
+
+    /// Allow `CONFIG SET` to change `dir` and `dbfilename` [default: no]
+    #[arg(long, value_parser = ["yes", "no"])]
+    pub enable_protected_configs: Option<String>,
+
+    /// Save points, e.g. "3600 1 300 100" to save after an hour if at least
+    /// one key changed, or after 5 minutes if at least 100 changed
+    #[arg(long)]
+    pub save: Option<String>,
+
+    /// Log every write to an append-only file in `--dir`, replayed on startup
+    /// [default: no]
+    #[arg(long, value_parser = ["yes", "no"])]
+    pub appendonly: Option<String>,
+
+    /// Name of the append-only file in `--dir` [default: appendonly.aof]
+    #[arg(long)]
+    pub appendfilename: Option<String>,
+
+    /// When the append-only file is flushed to disk [default: everysec]
+    #[arg(long, value_parser = ["always", "everysec", "no"])]
+    pub appendfsync: Option<String>,
+
+    /// RDB file, e.g. written by Redis, whose keys are loaded into the
+    /// keyspace on startup
+    #[arg(long)]
+    pub import_rdb: Option<PathBuf>,
+
+    /// Refuse to start if the file of `--import-rdb` holds keys it cannot
+    /// load, lists, sets, sorted sets and hashes, instead of skipping them
+    #[arg(long, requires = "import_rdb")]
+    pub import_strict: bool,
+
+    /// Path of a Unix domain socket to also serve clients on
+    #[arg(long)]
+    pub unixsocket: Option<PathBuf>,
+
+    /// Permissions of the Unix domain socket in octal, e.g. 770
+    #[arg(long, value_parser = parse_octal)]
+    pub unixsocketperm: Option<u32>,
+
+    /// Port number to serve TLS clients on
+    #[arg(long)]
+    pub tls_port: Option<u16>,
+
+    /// Certificate chain presented to TLS clients, in PEM format
+    #[arg(long)]
+    pub tls_cert_file: Option<PathBuf>,
+
+    /// Private key of the TLS certificate, in PEM format
+    #[arg(long)]
+    pub tls_key_file: Option<PathBuf>,
+
+    /// CA certificates used to verify client certificates, in PEM format
+    ///
+    /// When set, TLS clients must present a certificate signed by one of
+    /// these CAs.
+    #[arg(long)]
+    pub tls_ca_cert_file: Option<PathBuf>,
+}
+
+impl ArgsParser {
+    /// Load the config file, if any, and apply the options to it.
+    pub fn load_config(&self) -> crate::Result<Config> {
+        let config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+
+        let path = |path: &PathBuf| path.display().to_string();
+        let options = [
+            ("bind", self.ip.clone()),
+            ("port", self.port.map(|port| port.to_string())),
+            ("maxclients", self.maxclients.map(|n| n.to_string())),
+            (
+                "connection",
+                self.connection
+                    .and_then(|kind| kind.to_possible_value())
+                    .map(|value| value.get_name().to_string()),
+            ),
+            ("shards", self.shards.map(|n| n.to_string())),
+            ("requirepass", self.requirepass.clone()),
+            ("aclfile", self.aclfile.as_ref().map(path)),
+            ("dir", self.dir.as_ref().map(path)),
+            ("dbfilename", self.dbfilename.clone()),
+            (
+                "enable-protected-configs",
+                self.enable_protected_configs.clone(),
+            ),
+            ("save", self.save.clone()),
+            ("appendonly", self.appendonly.clone()),
+            ("appendfilename", self.appendfilename.clone()),
+            ("appendfsync", self.appendfsync.clone()),
+            ("unixsocket", self.unixsocket.as_ref().map(path)),
+            (
+                "unixsocketperm",
+                self.unixsocketperm.map(|perm| format!("{:o}", perm)),
+            ),
+            ("tls-port", self.tls_port.map(|port| port.to_string())),
+            ("tls-cert-file", self.tls_cert_file.as_ref().map(path)),
+            ("tls-key-file", self.tls_key_file.as_ref().map(path)),
+            ("tls-ca-cert-file", self.tls_ca_cert_file.as_ref().map(path)),
+        ];
+
+        for (name, value) in options {
+            if let Some(value) = value {
+                config
+                    .set(name, value)
+                    .map_err(|err| format!("invalid {}: {}", name, err))?;
+            }
+        }
+
+        Ok(config)
+    }
+}
 
-    /// Port number to bind to
-    #[arg(short, long, default_value = "6379")]
-    pub port: u16,
+fn parse_octal(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 8).map_err(|_| format!("`{}` is not an octal number", s))
 }
@@ -0,0 +1,100 @@
+//! Exports the keys of a server, or of an RDB snapshot, to JSON Lines or CSV,
+//! and imports such an export back into a server.
+//!
+//! ```sh
+//! my-mini-redis-dump export --addr 127.0.0.1:6379 > keys.jsonl
+//! my-mini-redis-dump export --rdb dump.rdb --format csv -o keys.csv
+//! my-mini-redis-dump import --addr 127.0.0.1:6380 keys.jsonl
+//! ```
+//!
+//! See `my_mini_redis::dump` for the fields of the exported keys.
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use my_mini_redis::dump::{self, Format};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write every key of a server or of an RDB file
+    Export {
+        /// Address of the server to read the keys from
+        #[arg(long, default_value = "127.0.0.1:6379")]
+        addr: String,
+
+        /// RDB file to read the keys from, instead of a server
+        #[arg(long)]
+        rdb: Option<PathBuf>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        format: Format,
+
+        /// File to write to [default: stdout]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Write the keys of an export to a server, replacing existing keys
+    Import {
+        /// Address of the server to write the keys to
+        #[arg(long, default_value = "127.0.0.1:6379")]
+        addr: String,
+
+        /// Input format
+        #[arg(long, value_enum, default_value_t)]
+        format: Format,
+
+        /// File to read from [default: stdin]
+        input: Option<PathBuf>,
+    },
+}
+
+#[tokio::main]
+async fn main() -> my_mini_redis::Result<()> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_writer(io::stderr)
+        .init();
+
+    match Args::parse().command {
+        Command::Export {
+            addr,
+            rdb,
+            format,
+            output,
+        } => {
+            let entries = match rdb {
+                Some(path) => dump::read_snapshot(&path)?,
+                None => dump::fetch(&addr).await?,
+            };
+            match output {
+                Some(path) => dump::write(File::create(path)?, format, &entries)?,
+                None => dump::write(io::stdout().lock(), format, &entries)?,
+            }
+            eprintln!("Exported {} keys", entries.len());
+        }
+        Command::Import {
+            addr,
+            format,
+            input,
+        } => {
+            let entries = match input {
+                Some(path) => dump::read(BufReader::new(File::open(path)?), format)?,
+                None => dump::read(io::stdin().lock(), format)?,
+            };
+            let written = dump::replay(&addr, &entries).await?;
+            eprintln!("Imported {} of {} keys", written, entries.len());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,31 @@
+//! Conversions between the monotonic `Instant`s held by the keyspace and the
+//! Unix timestamps written to disk or sent by clients.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+/// Current Unix time in seconds.
+pub(crate) fn unix_time() -> u64 {
+    unix_time_ms() / 1000
+}
+
+/// Current Unix time in milliseconds.
+pub(crate) fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Returns the Unix time in milliseconds corresponding to `when`.
+pub(crate) fn to_unix_ms(when: Instant) -> u64 {
+    let remaining = when.saturating_duration_since(Instant::now());
+    unix_time_ms().saturating_add(remaining.as_millis() as u64)
+}
+
+/// Returns the `Instant` corresponding to the Unix time `ms` in milliseconds,
+/// or `None` if it has already passed or cannot be represented.
+pub(crate) fn from_unix_ms(ms: u64) -> Option<Instant> {
+    let remaining = ms.checked_sub(unix_time_ms()).filter(|&ms| ms > 0)?;
+    Instant::now().checked_add(Duration::from_millis(remaining))
+}
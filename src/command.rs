@@ -1,17 +1,544 @@
-use bytes::Bytes;
-use tokio::sync::oneshot;
+//! Commands served by `MiniRedisServer`.
+//!
+//! Each command is a struct in its own module, parsed from a received frame
+//! with `parse_frames` and run against a `Storage` with `apply`. `Command`
+//! dispatches a frame to the command it names.
 
-type Responder<T> = oneshot::Sender<mini_redis::Result<T>>;
+mod acl;
+pub use acl::Acl;
 
+mod auth;
+pub use auth::Auth;
+
+mod bgrewriteaof;
+pub use bgrewriteaof::Bgrewriteaof;
+
+mod config;
+pub use config::Config;
+
+mod debug;
+pub use debug::Debug;
+
+mod del;
+pub use del::Del;
+
+mod dump;
+pub use dump::Dump;
+
+mod expire;
+pub use expire::Expire;
+
+mod get;
+pub use get::Get;
+
+mod hello;
+pub use hello::Hello;
+
+mod info;
+pub use info::Info;
+
+mod key_type;
+pub use key_type::Type;
+
+mod keys;
+pub use keys::Keys;
+
+mod lastsave;
+pub use lastsave::Lastsave;
+
+mod mget;
+pub use mget::Mget;
+
+mod migrate;
+pub use migrate::Migrate;
+
+mod persist;
+pub use persist::Persist;
+
+mod ping;
+pub use ping::Ping;
+
+mod rename;
+pub use rename::Rename;
+
+mod restore;
+pub use restore::Restore;
+
+mod save;
+pub use save::Save;
+
+mod scan;
+pub use scan::Scan;
+
+mod set;
+pub use set::Set;
+
+mod ttl;
+pub use ttl::Ttl;
+
+mod unknown;
+pub use unknown::Unknown;
+
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::session::Session;
+use crate::storage::Storage;
+
+use std::time::Duration;
+
+/// Enumeration of supported Redis commands.
+///
+/// Methods called on `Command` are delegated to the command implementation.
 #[derive(Debug)]
 pub enum Command {
-    Get {
-        key: String,
-        resp: Responder<Option<Bytes>>,
-    },
-    Set {
-        key: String,
-        value: Bytes,
-        resp: Responder<()>,
-    },
+    Acl(Acl),
+    Auth(Auth),
+    Bgrewriteaof(Bgrewriteaof),
+    Config(Config),
+    Debug(Debug),
+    Del(Del),
+    Dump(Dump),
+    Expire(Expire),
+    Get(Get),
+    Hello(Hello),
+    Info(Info),
+    Keys(Keys),
+    Lastsave(Lastsave),
+    Mget(Mget),
+    Migrate(Migrate),
+    Persist(Persist),
+    Ping(Ping),
+    Rename(Rename),
+    Restore(Restore),
+    Save(Save),
+    Scan(Scan),
+    Set(Set),
+    Ttl(Ttl),
+    Type(Type),
+    Unknown(Unknown),
+}
+
+/// Unit in which a command takes or reports a time span, e.g. `EXPIRE` and
+/// `TTL` use seconds while `PEXPIRE` and `PTTL` use milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+}
+
+impl Command {
+    /// Parse a command from a received frame.
+    ///
+    /// The `Frame` must be the array variant, holding the name of one of the
+    /// commands of `Command` followed by its arguments.
+    ///
+    /// # Returns
+    ///
+    /// On success, the command value is returned, otherwise, `Err` is returned.
+    /// A missing or surplus argument is reported as a "wrong number of
+    /// arguments" error so it can be sent back to the client as `-ERR`.
+    pub fn from_frame(frame: Frame) -> crate::Result<Command> {
+        // The frame value is decorated with `Parse`. `Parse` provides a
+        // "cursor" like API which makes parsing the command easier.
+        //
+        // The frame value must be an array variant. Any other frame variants
+        // result in an error being returned.
+        let mut parse = Parse::new(frame)?;
+
+        // All redis commands begin with the command name as a string. The name
+        // is read and converted to lower cases in order to do case sensitive
+        // matching.
+        let name = match parse.next_string() {
+            Ok(name) => name,
+            Err(ParseError::EndOfStream) => return Err("empty command".into()),
+            Err(ParseError::Other(err)) => return Err(err),
+        };
+        let command_name = name.to_lowercase();
+
+        // Match the command name, delegating the rest of the parsing to the
+        // specific command.
+        let result =
+            match &command_name[..] {
+                "acl" => Acl::parse_frames(&mut parse).map(Command::Acl),
+                "auth" => Auth::parse_frames(&mut parse).map(Command::Auth),
+                "bgrewriteaof" => Bgrewriteaof::parse_frames(&mut parse).map(Command::Bgrewriteaof),
+                "bgsave" => Save::parse_frames(&mut parse, true).map(Command::Save),
+                "config" => Config::parse_frames(&mut parse).map(Command::Config),
+                "debug" => Debug::parse_frames(&mut parse).map(Command::Debug),
+                "del" => Del::parse_frames(&mut parse).map(Command::Del),
+                "dump" => Dump::parse_frames(&mut parse).map(Command::Dump),
+                "expire" => {
+                    Expire::parse_frames(&mut parse, TimeUnit::Seconds, false).map(Command::Expire)
+                }
+                "expireat" => {
+                    Expire::parse_frames(&mut parse, TimeUnit::Seconds, true).map(Command::Expire)
+                }
+                "get" => Get::parse_frames(&mut parse).map(Command::Get),
+                "hello" => Hello::parse_frames(&mut parse).map(Command::Hello),
+                "info" => Info::parse_frames(&mut parse).map(Command::Info),
+                "keys" => Keys::parse_frames(&mut parse).map(Command::Keys),
+                "lastsave" => Lastsave::parse_frames(&mut parse).map(Command::Lastsave),
+                "mget" => Mget::parse_frames(&mut parse).map(Command::Mget),
+                "migrate" => Migrate::parse_frames(&mut parse).map(Command::Migrate),
+                "persist" => Persist::parse_frames(&mut parse).map(Command::Persist),
+                "pexpire" => Expire::parse_frames(&mut parse, TimeUnit::Milliseconds, false)
+                    .map(Command::Expire),
+                "pexpireat" => Expire::parse_frames(&mut parse, TimeUnit::Milliseconds, true)
+                    .map(Command::Expire),
+                "ping" => Ping::parse_frames(&mut parse).map(Command::Ping),
+                "pttl" => Ttl::parse_frames(&mut parse, TimeUnit::Milliseconds).map(Command::Ttl),
+                "rename" => Rename::parse_frames(&mut parse).map(Command::Rename),
+                "restore" => Restore::parse_frames(&mut parse).map(Command::Restore),
+                "save" => Save::parse_frames(&mut parse, false).map(Command::Save),
+                "scan" => Scan::parse_frames(&mut parse).map(Command::Scan),
+                "set" => Set::parse_frames(&mut parse).map(Command::Set),
+                "ttl" => Ttl::parse_frames(&mut parse, TimeUnit::Seconds).map(Command::Ttl),
+                "type" => Type::parse_frames(&mut parse).map(Command::Type),
+                _ => {
+                    // The command is not recognized and an Unknown command is
+                    // returned.
+                    //
+                    // `return` is called here to skip the `finish()` call below. As
+                    // the command is not recognized, there is most likely
+                    // unconsumed fields remaining in the `Parse` instance.
+                    return Ok(Command::Unknown(Unknown::new(name)));
+                }
+            };
+
+        // Check if there is any remaining unconsumed fields in the `Parse`
+        // value. If fields remain, this indicates an unexpected frame format
+        // and an error is returned.
+        match result {
+            Ok(command) if parse.finish().is_ok() => Ok(command),
+            Ok(_) | Err(ParseError::EndOfStream) => {
+                Err(format!("wrong number of arguments for '{}' command", command_name).into())
+            }
+            Err(ParseError::Other(err)) => Err(err),
+        }
+    }
+
+    /// Apply the command to the specified `Storage` and the `Session` of the
+    /// connection it was received on.
+    ///
+    /// The response is returned as a frame to be written to the client.
+    pub(crate) fn apply(self, db: &dyn Storage, session: &mut Session) -> Frame {
+        use Command::*;
+
+        match self {
+            Acl(cmd) => cmd.apply(session),
+            Auth(cmd) => cmd.apply(session),
+            Bgrewriteaof(cmd) => cmd.apply(db, session),
+            Config(cmd) => cmd.apply(session),
+            Debug(cmd) => cmd.apply(db, session),
+            Del(cmd) => cmd.apply(db),
+            Dump(cmd) => cmd.apply(db),
+            Expire(cmd) => cmd.apply(db),
+            Get(cmd) => cmd.apply(db),
+            Hello(cmd) => cmd.apply(session),
+            Info(cmd) => cmd.apply(session),
+            Keys(cmd) => cmd.apply(db),
+            Lastsave(cmd) => cmd.apply(session),
+            Mget(cmd) => cmd.apply(db),
+            // MIGRATE は移行先の応答を待つので、MiniRedisServer が Migrate::execute で実行する
+            Migrate(_) => Frame::Error("ERR MIGRATE is not allowed here".to_string()),
+            Persist(cmd) => cmd.apply(db),
+            Ping(cmd) => cmd.apply(),
+            Rename(cmd) => cmd.apply(db),
+            Restore(cmd) => cmd.apply(db),
+            Save(cmd) => cmd.apply(db, session),
+            Scan(cmd) => cmd.apply(db),
+            Set(cmd) => cmd.apply(db),
+            Ttl(cmd) => cmd.apply(db),
+            Type(cmd) => cmd.apply(db),
+            Unknown(cmd) => cmd.apply(),
+        }
+    }
+
+    /// Returns the keys the command accesses, which are checked against the
+    /// key patterns of the ACL user running it.
+    pub(crate) fn keys(&self) -> Vec<&str> {
+        use Command::*;
+
+        match self {
+            Del(cmd) => cmd.keys().iter().map(String::as_str).collect(),
+            Mget(cmd) => cmd.keys().iter().map(String::as_str).collect(),
+            Migrate(cmd) => cmd.keys().iter().map(String::as_str).collect(),
+            Dump(cmd) => vec![cmd.key()],
+            Expire(cmd) => vec![cmd.key()],
+            Get(cmd) => vec![cmd.key()],
+            Persist(cmd) => vec![cmd.key()],
+            Rename(cmd) => vec![cmd.key(), cmd.new_key()],
+            Restore(cmd) => vec![cmd.key()],
+            Set(cmd) => vec![cmd.key()],
+            Ttl(cmd) => vec![cmd.key()],
+            Type(cmd) => vec![cmd.key()],
+            Acl(_) | Auth(_) | Bgrewriteaof(_) | Config(_) | Debug(_) | Hello(_) | Info(_)
+            | Keys(_) | Lastsave(_) | Ping(_) | Save(_) | Scan(_) | Unknown(_) => vec![],
+        }
+    }
+
+    /// Returns `true` if the command modifies the keyspace, i.e. it is in the
+    /// `@write` ACL category.
+    pub(crate) fn is_write(&self) -> bool {
+        crate::acl::in_category(self.get_name(), "write")
+    }
+
+    /// Returns the command name
+    pub(crate) fn get_name(&self) -> &str {
+        match self {
+            Command::Acl(_) => "acl",
+            Command::Auth(_) => "auth",
+            Command::Bgrewriteaof(_) => "bgrewriteaof",
+            Command::Config(_) => "config",
+            Command::Debug(_) => "debug",
+            Command::Del(_) => "del",
+            Command::Dump(_) => "dump",
+            Command::Expire(cmd) => cmd.get_name(),
+            Command::Get(_) => "get",
+            Command::Hello(_) => "hello",
+            Command::Info(_) => "info",
+            Command::Keys(_) => "keys",
+            Command::Lastsave(_) => "lastsave",
+            Command::Mget(_) => "mget",
+            Command::Migrate(_) => "migrate",
+            Command::Persist(_) => "persist",
+            Command::Ping(_) => "ping",
+            Command::Rename(_) => "rename",
+            Command::Restore(_) => "restore",
+            Command::Save(cmd) => cmd.get_name(),
+            Command::Scan(_) => "scan",
+            Command::Set(_) => "set",
+            Command::Ttl(cmd) => cmd.get_name(),
+            Command::Type(_) => "type",
+            Command::Unknown(cmd) => cmd.get_name(),
+        }
+    }
+}
+
+impl TimeUnit {
+    /// Returns the duration of `n` units.
+    pub(crate) fn duration(self, n: u64) -> Duration {
+        match self {
+            TimeUnit::Seconds => Duration::from_secs(n),
+            TimeUnit::Milliseconds => Duration::from_millis(n),
+        }
+    }
+
+    /// Returns `duration` as a number of units, rounded to the nearest unit.
+    pub(crate) fn of(self, duration: Duration) -> i64 {
+        let millis = duration.as_millis() as i64;
+        match self {
+            TimeUnit::Seconds => (millis + 500) / 1000,
+            TimeUnit::Milliseconds => millis,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::SetCondition;
+    use bytes::Bytes;
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+    use tokio::time::Instant;
+
+    /// Minimal store without deadlines, checking commands only rely on the
+    /// `Storage` trait.
+    #[derive(Default)]
+    struct FakeStorage {
+        entries: Mutex<BTreeMap<String, Bytes>>,
+    }
+
+    impl Storage for FakeStorage {
+        fn get(&self, key: &str) -> Option<Bytes> {
+            self.entries.lock().unwrap().get(key).cloned()
+        }
+
+        fn set(&self, key: String, value: Bytes, _: Option<Instant>, _: SetCondition) -> bool {
+            self.entries.lock().unwrap().insert(key, value);
+            true
+        }
+
+        fn del(&self, keys: &[String]) -> usize {
+            let mut entries = self.entries.lock().unwrap();
+            keys.iter()
+                .filter(|key| entries.remove(*key).is_some())
+                .count()
+        }
+
+        fn del_if_unchanged(&self, key: &str, value: &Bytes, _: Option<Instant>) -> bool {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.get(key) {
+                Some(current) if current == value => entries.remove(key).is_some(),
+                _ => false,
+            }
+        }
+
+        fn rename(&self, key: &str, new_key: &str) -> bool {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.remove(key) {
+                Some(value) => {
+                    entries.insert(new_key.to_string(), value);
+                    true
+                }
+                None => false,
+            }
+        }
+
+        fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<String>) {
+            let entries = self.entries.lock().unwrap();
+            let keys: Vec<_> = entries
+                .keys()
+                .skip(cursor as usize)
+                .take(count)
+                .cloned()
+                .collect();
+            let next = cursor as usize + keys.len();
+            let next = if next >= entries.len() { 0 } else { next };
+            (next as u64, keys)
+        }
+
+        fn expire(&self, _: &str, _: Option<Instant>) -> bool {
+            false
+        }
+
+        fn expires_at(&self, key: &str) -> Option<Option<Instant>> {
+            self.entries.lock().unwrap().get(key).map(|_| None)
+        }
+    }
+
+    fn run(db: &dyn Storage, args: &[&str]) -> Frame {
+        Command::from_frame(command(args))
+            .unwrap()
+            .apply(db, &mut session())
+    }
+
+    fn session() -> Session {
+        Session::new(Default::default(), Default::default(), Default::default())
+    }
+
+    fn command(args: &[&str]) -> Frame {
+        let mut frame = Frame::array();
+        for arg in args {
+            frame.push_bulk(Bytes::copy_from_slice(arg.as_bytes()));
+        }
+        frame
+    }
+
+    #[test]
+    fn parses_known_commands_case_insensitively() {
+        let cmd = Command::from_frame(command(&["GeT", "key"])).unwrap();
+        assert!(matches!(cmd, Command::Get(ref get) if get.key() == "key"));
+
+        let cmd = Command::from_frame(command(&["set", "key", "value"])).unwrap();
+        assert!(matches!(cmd, Command::Set(ref set) if set.value() == "value"));
+    }
+
+    #[tokio::test]
+    async fn unknown_command_replies_with_error() {
+        let cmd = Command::from_frame(command(&["foo", "bar"])).unwrap();
+        let db = crate::db::Db::new(1);
+
+        match cmd.apply(&db, &mut session()) {
+            Frame::Error(msg) => assert_eq!(msg, "ERR unknown command 'foo'"),
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+    }
+
+    #[test]
+    fn wrong_arity_is_an_error() {
+        let err = Command::from_frame(command(&["get"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "wrong number of arguments for 'get' command"
+        );
+
+        let err = Command::from_frame(command(&["GET", "a", "b"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "wrong number of arguments for 'get' command"
+        );
+    }
+
+    #[tokio::test]
+    async fn expire_deletes_keys_whose_deadline_has_passed() {
+        let db = crate::db::Db::new(1);
+
+        for args in [
+            &["EXPIRE", "k", "0"][..],
+            &["PEXPIRE", "k", "-5"],
+            &["EXPIREAT", "k", "1"],
+        ] {
+            run(&db, &["SET", "k", "v"]);
+            assert_eq!(run(&db, args), Frame::Integer(1));
+            assert_eq!(run(&db, &["GET", "k"]), Frame::Null);
+        }
+
+        // Only existing keys are counted
+        assert_eq!(run(&db, &["EXPIRE", "k", "0"]), Frame::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn expire_rejects_overflowing_timeouts() {
+        let db = crate::db::Db::new(1);
+        run(&db, &["SET", "k", "v"]);
+
+        assert_eq!(
+            run(&db, &["EXPIRE", "k", &i64::MAX.to_string()]),
+            Frame::Error("ERR invalid expire time in 'expire' command".into())
+        );
+        assert_eq!(run(&db, &["TTL", "k"]), Frame::Integer(-1));
+    }
+
+    #[tokio::test]
+    async fn ttl_and_persist() {
+        let db = crate::db::Db::new(1);
+
+        assert_eq!(run(&db, &["TTL", "k"]), Frame::Integer(-2));
+        assert_eq!(run(&db, &["PERSIST", "k"]), Frame::Integer(0));
+
+        run(&db, &["SET", "k", "v"]);
+        assert_eq!(run(&db, &["TTL", "k"]), Frame::Integer(-1));
+        assert_eq!(run(&db, &["PERSIST", "k"]), Frame::Integer(0));
+
+        assert_eq!(run(&db, &["EXPIRE", "k", "100"]), Frame::Integer(1));
+        assert!(matches!(run(&db, &["TTL", "k"]), Frame::Integer(99..=100)));
+        assert_eq!(run(&db, &["PERSIST", "k"]), Frame::Integer(1));
+        assert_eq!(run(&db, &["TTL", "k"]), Frame::Integer(-1));
+    }
+
+    #[test]
+    fn commands_run_against_any_storage() {
+        let db = FakeStorage::default();
+
+        for key in ["user:1", "user:2", "session:1"] {
+            assert_eq!(run(&db, &["SET", key, "v"]), Frame::Simple("OK".into()));
+        }
+        assert_eq!(run(&db, &["GET", "user:1"]), Frame::Bulk("v".into()));
+        assert_eq!(run(&db, &["TTL", "user:1"]), Frame::Integer(-1));
+
+        let keys = run(&db, &["KEYS", "user:*"]);
+        assert_eq!(keys.to_string(), "user:1 user:2");
+
+        // Walk the keyspace one key at a time
+        let mut cursor = "0".to_string();
+        let mut seen = 0;
+        loop {
+            match run(&db, &["SCAN", &cursor, "COUNT", "1"]) {
+                Frame::Array(reply) => match &reply[..] {
+                    [Frame::Bulk(next), Frame::Array(keys)] => {
+                        seen += keys.len();
+                        cursor = String::from_utf8(next.to_vec()).unwrap();
+                    }
+                    _ => panic!("unexpected reply {:?}", reply),
+                },
+                frame => panic!("unexpected frame {:?}", frame),
+            }
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert_eq!(seen, 3);
+    }
 }
@@ -0,0 +1,156 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::session::Session;
+
+use bytes::Bytes;
+
+/// Inspects and modifies the ACL users of the server.
+///
+/// Changes apply to every connection: a client authenticated as a user that is
+/// deleted or disabled can no longer run commands.
+#[derive(Debug)]
+pub struct Acl {
+    subcommand: Subcommand,
+}
+
+#[derive(Debug)]
+enum Subcommand {
+    /// Create or modify a user
+    SetUser { name: String, rules: Vec<String> },
+    /// Describe a user
+    GetUser(String),
+    /// Delete users
+    DelUser(Vec<String>),
+    /// List the rules of every user
+    List,
+    /// Return the user of the connection
+    WhoAmI,
+}
+
+impl Acl {
+    /// Parse an `Acl` instance from a received frame.
+    ///
+    /// The `ACL` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing `ACL`, a subcommand and its
+    /// arguments.
+    ///
+    /// ```text
+    /// ACL SETUSER username [rule ...]
+    /// ACL GETUSER username
+    /// ACL DELUSER username [username ...]
+    /// ACL LIST
+    /// ACL WHOAMI
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Acl, ParseError> {
+        let name = parse.next_string()?;
+
+        let subcommand = match &name.to_lowercase()[..] {
+            "setuser" => Subcommand::SetUser {
+                name: parse.next_string()?,
+                rules: rest(parse)?,
+            },
+            "getuser" => Subcommand::GetUser(parse.next_string()?),
+            "deluser" => {
+                let mut names = vec![parse.next_string()?];
+                names.extend(rest(parse)?);
+                Subcommand::DelUser(names)
+            }
+            "list" => Subcommand::List,
+            "whoami" => Subcommand::WhoAmI,
+            _ => {
+                return Err(format!("unknown subcommand '{}'. Try ACL HELP.", name).into());
+            }
+        };
+
+        Ok(Acl { subcommand })
+    }
+
+    /// Apply the `Acl` command to the users shared through the `Session`.
+    pub(crate) fn apply(self, session: &mut Session) -> Frame {
+        let bulk = |s: String| Frame::Bulk(Bytes::from(s));
+
+        match self.subcommand {
+            Subcommand::SetUser { name, rules } => match session.acl.set_user(&name, &rules) {
+                Ok(()) => Frame::Simple("OK".to_string()),
+                Err(err) => Frame::Error(format!("ERR {}", err)),
+            },
+            Subcommand::GetUser(name) => {
+                let info = match session.acl.describe(&name) {
+                    Some(info) => info,
+                    None => return Frame::Null,
+                };
+                let field = |name: &'static str| Frame::Bulk(Bytes::from_static(name.as_bytes()));
+                let flags = info.flags.into_iter().map(field).collect();
+                let passwords = info.passwords.into_iter().map(bulk).collect();
+
+                Frame::Map(vec![
+                    (field("flags"), Frame::Array(flags)),
+                    (field("passwords"), Frame::Array(passwords)),
+                    (field("commands"), bulk(info.commands)),
+                    (field("keys"), bulk(info.keys)),
+                ])
+            }
+            Subcommand::DelUser(names) => match session.acl.del_users(&names) {
+                Ok(n) => Frame::Integer(n as i64),
+                Err(err) => Frame::Error(format!("ERR {}", err)),
+            },
+            Subcommand::List => {
+                let users = session
+                    .acl
+                    .usernames()
+                    .into_iter()
+                    .filter_map(|name| {
+                        let rules = session.acl.rules(&name)?;
+                        Some(bulk(format!("user {} {}", name, rules)))
+                    })
+                    .collect();
+                Frame::Array(users)
+            }
+            Subcommand::WhoAmI => match &session.user {
+                Some(user) => bulk(user.clone()),
+                None => Frame::Null,
+            },
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("acl".as_bytes()));
+
+        let args = match self.subcommand {
+            Subcommand::SetUser { name, rules } => {
+                let mut args = vec!["setuser".to_string(), name];
+                args.extend(rules);
+                args
+            }
+            Subcommand::GetUser(name) => vec!["getuser".to_string(), name],
+            Subcommand::DelUser(names) => {
+                let mut args = vec!["deluser".to_string()];
+                args.extend(names);
+                args
+            }
+            Subcommand::List => vec!["list".to_string()],
+            Subcommand::WhoAmI => vec!["whoami".to_string()],
+        };
+        for arg in args {
+            frame.push_bulk(Bytes::from(arg.into_bytes()));
+        }
+        frame
+    }
+}
+
+/// Read the remaining entries of the frame as strings.
+fn rest(parse: &mut Parse) -> Result<Vec<String>, ParseError> {
+    let mut args = vec![];
+    loop {
+        match parse.next_string() {
+            Ok(arg) => args.push(arg),
+            Err(ParseError::EndOfStream) => return Ok(args),
+            Err(e) => return Err(e),
+        }
+    }
+}
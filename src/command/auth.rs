@@ -0,0 +1,78 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::session::Session;
+
+use bytes::Bytes;
+
+/// Authenticates the connection as an ACL user.
+///
+/// Without a username, the client authenticates as the `default` user, whose
+/// password is set by `--requirepass`.
+#[derive(Debug)]
+pub struct Auth {
+    /// user to authenticate as, `default` if not given
+    username: Option<String>,
+    /// password of the user
+    password: String,
+}
+
+impl Auth {
+    /// Create a new `Auth` command authenticating `username` with `password`.
+    pub fn new(username: Option<String>, password: String) -> Auth {
+        Auth { username, password }
+    }
+
+    /// Parse an `Auth` instance from a received frame.
+    ///
+    /// The `AUTH` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing `AUTH`, an optional username and a
+    /// password.
+    ///
+    /// ```text
+    /// AUTH [username] password
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Auth, ParseError> {
+        let first = parse.next_string()?;
+
+        match parse.next_string() {
+            Ok(password) => Ok(Auth::new(Some(first), password)),
+            Err(ParseError::EndOfStream) => Ok(Auth::new(None, first)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Apply the `Auth` command to the connection's `Session`.
+    pub(crate) fn apply(self, session: &mut Session) -> Frame {
+        // パスワードなしで認証される default ユーザーに AUTH <password> を送るのは、設定の誤りの可能性が高い
+        if self.username.is_none() && session.acl.authenticate("default", None) {
+            return Frame::Error(
+                "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?"
+                    .to_string(),
+            );
+        }
+
+        let username = self.username.unwrap_or_else(|| "default".to_string());
+        if session.acl.authenticate(&username, Some(&self.password)) {
+            session.user = Some(username);
+            Frame::Simple("OK".to_string())
+        } else {
+            Frame::Error(
+                "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+            )
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("auth".as_bytes()));
+        if let Some(username) = self.username {
+            frame.push_bulk(Bytes::from(username.into_bytes()));
+        }
+        frame.push_bulk(Bytes::from(self.password.into_bytes()));
+        frame
+    }
+}
@@ -0,0 +1,49 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::session::Session;
+use crate::storage::Storage;
+
+use bytes::Bytes;
+
+/// Rewrite the append-only file in the background, replacing the commands it
+/// holds with the shortest list of commands producing the current keyspace.
+#[derive(Debug, Default)]
+pub struct Bgrewriteaof;
+
+impl Bgrewriteaof {
+    /// Parse a `Bgrewriteaof` instance from a received frame.
+    ///
+    /// The `BGREWRITEAOF` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing a single entry.
+    ///
+    /// ```text
+    /// BGREWRITEAOF
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> Result<Bgrewriteaof, ParseError> {
+        Ok(Bgrewriteaof)
+    }
+
+    /// Apply the `Bgrewriteaof` command to the specified `Storage` and the
+    /// append-only file shared through the `Session`.
+    pub(crate) fn apply(self, db: &dyn Storage, session: &mut Session) -> Frame {
+        let aof = match &session.aof {
+            Some(aof) => aof,
+            None => return Frame::Error("ERR Append only file is disabled".to_string()),
+        };
+
+        match aof.bgrewrite(db, &session.stats) {
+            Ok(()) => Frame::Simple("Background append only file rewriting started".to_string()),
+            Err(err) => Frame::Error(format!("ERR {}", err)),
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("bgrewriteaof".as_bytes()));
+        frame
+    }
+}
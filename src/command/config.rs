@@ -0,0 +1,160 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::session::Session;
+
+use bytes::Bytes;
+
+/// Reads and changes the configuration of the server at runtime.
+#[derive(Debug)]
+pub struct Config {
+    subcommand: Subcommand,
+}
+
+#[derive(Debug)]
+enum Subcommand {
+    /// Return the parameters matching any of the glob-style patterns
+    Get(Vec<String>),
+    /// Set parameters
+    Set(Vec<(String, String)>),
+    /// Write the configuration to the config file
+    Rewrite,
+    /// Reset the statistics reported by `INFO`
+    ResetStat,
+}
+
+impl Config {
+    /// Parse a `Config` instance from a received frame.
+    ///
+    /// The `CONFIG` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing `CONFIG`, a subcommand and its
+    /// arguments.
+    ///
+    /// ```text
+    /// CONFIG GET pattern [pattern ...]
+    /// CONFIG SET parameter value [parameter value ...]
+    /// CONFIG REWRITE
+    /// CONFIG RESETSTAT
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Config, ParseError> {
+        let name = parse.next_string()?;
+
+        let subcommand = match &name.to_lowercase()[..] {
+            "get" => {
+                let mut patterns = vec![parse.next_string()?];
+                loop {
+                    match parse.next_string() {
+                        Ok(pattern) => patterns.push(pattern),
+                        Err(ParseError::EndOfStream) => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+                Subcommand::Get(patterns)
+            }
+            "set" => {
+                let mut params = vec![(parse.next_string()?, parse.next_string()?)];
+                loop {
+                    let name = match parse.next_string() {
+                        Ok(name) => name,
+                        Err(ParseError::EndOfStream) => break,
+                        Err(e) => return Err(e),
+                    };
+                    params.push((name, parse.next_string()?));
+                }
+                Subcommand::Set(params)
+            }
+            "rewrite" => Subcommand::Rewrite,
+            "resetstat" => Subcommand::ResetStat,
+            _ => {
+                return Err(format!("unknown subcommand '{}'. Try CONFIG HELP.", name).into());
+            }
+        };
+
+        Ok(Config { subcommand })
+    }
+
+    /// Apply the `Config` command to the configuration shared through the
+    /// `Session`.
+    pub(crate) fn apply(self, session: &mut Session) -> Frame {
+        match self.subcommand {
+            Subcommand::Get(patterns) => {
+                let mut params: Vec<_> = patterns
+                    .iter()
+                    .flat_map(|pattern| session.config.matching(pattern))
+                    .collect();
+                params.sort();
+                params.dedup();
+
+                let params = params
+                    .into_iter()
+                    .map(|(name, value)| {
+                        (
+                            Frame::Bulk(Bytes::from_static(name.as_bytes())),
+                            Frame::Bulk(Bytes::from(value)),
+                        )
+                    })
+                    .collect();
+                Frame::Map(params)
+            }
+            Subcommand::Set(params) => {
+                if let Err(err) = session.config.set_at_runtime(&params) {
+                    return Frame::Error(format!("ERR {}", err));
+                }
+
+                // requirepass は default ユーザーのパスワードとして ACL に反映する
+                let requirepass = params
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("requirepass"));
+                if let Some((_, password)) = requirepass {
+                    let rules = match &password[..] {
+                        "" => vec!["nopass".to_string()],
+                        password => vec!["resetpass".to_string(), format!(">{}", password)],
+                    };
+                    // どちらのルールも常に有効なので、失敗することはない
+                    session.acl.set_user("default", &rules).unwrap();
+                }
+
+                Frame::Simple("OK".to_string())
+            }
+            Subcommand::Rewrite => match session.config.rewrite() {
+                Ok(()) => Frame::Simple("OK".to_string()),
+                Err(err) => Frame::Error(format!("ERR Rewriting config file: {}", err)),
+            },
+            Subcommand::ResetStat => {
+                session.stats.reset();
+                Frame::Simple("OK".to_string())
+            }
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("config".as_bytes()));
+
+        let args = match self.subcommand {
+            Subcommand::Get(patterns) => {
+                let mut args = vec!["get".to_string()];
+                args.extend(patterns);
+                args
+            }
+            Subcommand::Set(params) => {
+                let mut args = vec!["set".to_string()];
+                for (name, value) in params {
+                    args.push(name);
+                    args.push(value);
+                }
+                args
+            }
+            Subcommand::Rewrite => vec!["rewrite".to_string()],
+            Subcommand::ResetStat => vec!["resetstat".to_string()],
+        };
+        for arg in args {
+            frame.push_bulk(Bytes::from(arg.into_bytes()));
+        }
+        frame
+    }
+}
@@ -0,0 +1,110 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::rdb;
+use crate::session::Session;
+use crate::storage::Storage;
+
+use bytes::Bytes;
+
+/// Debugging helpers. Only `DEBUG RELOAD` is supported.
+///
+/// `DEBUG RELOAD` saves the keyspace to the dump file, empties it and loads
+/// the dump file back, which checks that every key survives a round trip.
+/// With `NOSAVE`, the dump file is loaded as is, e.g. to import a file
+/// written by Redis. With `NOFLUSH`, the keys it holds are merged into the
+/// keyspace instead of replacing it. The keyspace is left untouched if the
+/// dump file cannot be read.
+#[derive(Debug)]
+pub struct Debug {
+    /// whether the keyspace is saved before being reloaded
+    save: bool,
+    /// whether the keyspace is emptied before being reloaded
+    flush: bool,
+}
+
+impl Debug {
+    /// Create a new `DEBUG RELOAD` command.
+    pub fn reload(save: bool, flush: bool) -> Debug {
+        Debug { save, flush }
+    }
+
+    /// Parse a `Debug` instance from a received frame.
+    ///
+    /// The `DEBUG` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing `DEBUG RELOAD` and options.
+    ///
+    /// ```text
+    /// DEBUG RELOAD [NOSAVE] [NOFLUSH]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Debug, ParseError> {
+        let name = parse.next_string()?;
+        if !name.eq_ignore_ascii_case("reload") {
+            return Err(format!("unknown subcommand '{}'. Try DEBUG HELP.", name).into());
+        }
+
+        let mut debug = Debug::reload(true, true);
+        loop {
+            match parse.next_string() {
+                Ok(option) if option.eq_ignore_ascii_case("nosave") => debug.save = false,
+                Ok(option) if option.eq_ignore_ascii_case("noflush") => debug.flush = false,
+                Ok(_) => return Err("syntax error".into()),
+                Err(ParseError::EndOfStream) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(debug)
+    }
+
+    /// Apply the `Debug` command to the specified `Storage`.
+    pub(crate) fn apply(self, db: &dyn Storage, session: &mut Session) -> Frame {
+        if self.save {
+            if let Err(err) = rdb::save(db, &session.config, &session.stats) {
+                tracing::error!("Failed saving the DB: {}", err);
+                return Frame::Error("ERR Error trying to save the DB".to_string());
+            }
+        }
+
+        // 読み込めなかった場合にキーを失わないように、空にする前にファイルを読み込む
+        let path = session.config.dump_path();
+        let records = match rdb::read(&path) {
+            Ok(records) => records,
+            Err(err) => {
+                tracing::error!("Failed loading the DB: {}", err);
+                return Frame::Error("ERR Error trying to load the RDB dump".to_string());
+            }
+        };
+
+        if self.flush {
+            let keys: Vec<String> = db.snapshot().into_iter().map(|(key, ..)| key).collect();
+            db.del(&keys);
+        }
+        let loaded = rdb::insert(db, records, &path);
+        tracing::info!("DB reloaded by DEBUG RELOAD: {} keys", loaded);
+
+        // 読み込んだキーは AOF に記録されていないので、AOF を書き直す
+        if let Some(aof) = &session.aof {
+            if let Err(err) = aof.bgrewrite(db, &session.stats) {
+                tracing::warn!("AOF not rewritten after DEBUG RELOAD: {}", err);
+            }
+        }
+
+        Frame::Simple("OK".to_string())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("debug".as_bytes()));
+        frame.push_bulk(Bytes::from("reload".as_bytes()));
+        if !self.save {
+            frame.push_bulk(Bytes::from("nosave".as_bytes()));
+        }
+        if !self.flush {
+            frame.push_bulk(Bytes::from("noflush".as_bytes()));
+        }
+        frame
+    }
+}
@@ -0,0 +1,55 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::storage::Storage;
+
+/// Removes the specified keys. A key is ignored if it does not exist.
+///
+/// Replies with the number of keys that were removed.
+#[derive(Debug)]
+pub struct Del {
+    /// the keys to remove
+    keys: Vec<String>,
+}
+
+impl Del {
+    /// Create a new `Del` command which removes `keys`.
+    pub fn new(keys: Vec<String>) -> Del {
+        Del { keys }
+    }
+
+    /// Get the keys
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Parse a `Del` instance from a received frame.
+    ///
+    /// The `DEL` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing at least two entries.
+    ///
+    /// ```text
+    /// DEL key [key ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Del, ParseError> {
+        // At least one key is required
+        let mut keys = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(key) => keys.push(key),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Del { keys })
+    }
+
+    /// Apply the `Del` command to the specified `Storage`.
+    pub(crate) fn apply(self, db: &dyn Storage) -> Frame {
+        Frame::Integer(db.del(&self.keys) as i64)
+    }
+}
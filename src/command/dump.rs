@@ -0,0 +1,64 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::rdb::{self, Value};
+use crate::storage::Storage;
+
+use bytes::Bytes;
+
+/// Serialize the value stored at `key`, to be recreated with `RESTORE`.
+///
+/// The payload holds the value in the RDB format, followed by the RDB version
+/// and a CRC64 checksum, so it can also be restored by Redis. Replies nil if
+/// the key does not exist.
+#[derive(Debug)]
+pub struct Dump {
+    /// the lookup key
+    key: String,
+}
+
+impl Dump {
+    /// Create a new `Dump` command which serializes the value of `key`.
+    pub fn new(key: impl ToString) -> Dump {
+        Dump {
+            key: key.to_string(),
+        }
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Parse a `Dump` instance from a received frame.
+    ///
+    /// The `DUMP` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing two entries.
+    ///
+    /// ```text
+    /// DUMP key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Dump, ParseError> {
+        let key = parse.next_string()?;
+
+        Ok(Dump::new(key))
+    }
+
+    /// Apply the `Dump` command to the specified `Storage`.
+    pub(crate) fn apply(self, db: &dyn Storage) -> Frame {
+        match db.get(&self.key) {
+            Some(value) => Frame::Bulk(Bytes::from(rdb::dump_payload(&Value::String(value)))),
+            None => Frame::Null,
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("dump".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame
+    }
+}
@@ -0,0 +1,134 @@
+use crate::clock;
+use crate::command::TimeUnit;
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::storage::Storage;
+
+use tokio::time::Instant;
+
+/// Set a timeout on `key`. After the timeout has expired, the key will
+/// automatically be deleted.
+///
+/// `EXPIRE` takes the timeout in seconds and `PEXPIRE` in milliseconds. A
+/// non-positive timeout deletes the key immediately.
+///
+/// `EXPIREAT` and `PEXPIREAT` take the Unix time at which the key expires
+/// instead. A time in the past deletes the key immediately.
+#[derive(Debug)]
+pub struct Expire {
+    /// the lookup key
+    key: String,
+
+    /// the timeout, or the Unix time if `absolute` is set, expressed in `unit`
+    timeout: i64,
+
+    /// unit of `timeout`
+    unit: TimeUnit,
+
+    /// whether `timeout` is a Unix time rather than a duration
+    absolute: bool,
+}
+
+impl Expire {
+    /// Create a new `Expire` command which expires `key` after `timeout`
+    /// `unit`s.
+    pub fn new(key: impl ToString, timeout: i64, unit: TimeUnit) -> Expire {
+        Expire {
+            key: key.to_string(),
+            timeout,
+            unit,
+            absolute: false,
+        }
+    }
+
+    /// Create a new `Expire` command which expires `key` at the Unix time
+    /// `timestamp`, expressed in `unit`s.
+    pub fn at(key: impl ToString, timestamp: i64, unit: TimeUnit) -> Expire {
+        Expire {
+            absolute: true,
+            ..Expire::new(key, timestamp, unit)
+        }
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get the unit of the timeout
+    pub fn unit(&self) -> TimeUnit {
+        self.unit
+    }
+
+    /// Returns the command name
+    pub(crate) fn get_name(&self) -> &str {
+        match (self.unit, self.absolute) {
+            (TimeUnit::Seconds, false) => "expire",
+            (TimeUnit::Milliseconds, false) => "pexpire",
+            (TimeUnit::Seconds, true) => "expireat",
+            (TimeUnit::Milliseconds, true) => "pexpireat",
+        }
+    }
+
+    /// Parse an `Expire` instance from a received frame.
+    ///
+    /// The command name has already been consumed. `absolute` is set for
+    /// `EXPIREAT` and `PEXPIREAT`.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing three entries.
+    ///
+    /// ```text
+    /// EXPIRE key seconds
+    /// PEXPIRE key milliseconds
+    /// EXPIREAT key unix-time-seconds
+    /// PEXPIREAT key unix-time-milliseconds
+    /// ```
+    pub(crate) fn parse_frames(
+        parse: &mut Parse,
+        unit: TimeUnit,
+        absolute: bool,
+    ) -> Result<Expire, ParseError> {
+        let key = parse.next_string()?;
+        let timeout = parse.next_int()?;
+
+        match absolute {
+            false => Ok(Expire::new(key, timeout, unit)),
+            true => Ok(Expire::at(key, timeout, unit)),
+        }
+    }
+
+    /// Apply the `Expire` command to the specified `Storage`.
+    ///
+    /// Replies `1` if the timeout was set and `0` if the key does not exist.
+    pub(crate) fn apply(self, db: &dyn Storage) -> Frame {
+        if self.timeout <= 0 {
+            return Frame::Integer(db.del(&[self.key]) as i64);
+        }
+
+        let duration = self.unit.duration(self.timeout as u64);
+        let when = if self.absolute {
+            let ms = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+            // 過去の時刻を指定された場合は、すぐにキーを削除する
+            if ms <= clock::unix_time_ms() {
+                return Frame::Integer(db.del(&[self.key]) as i64);
+            }
+            clock::from_unix_ms(ms)
+        } else {
+            Instant::now().checked_add(duration)
+        };
+
+        let when = match when {
+            Some(when) => when,
+            None => {
+                return Frame::Error(format!(
+                    "ERR invalid expire time in '{}' command",
+                    self.get_name()
+                ))
+            }
+        };
+
+        Frame::Integer(db.expire(&self.key, Some(when)) as i64)
+    }
+}
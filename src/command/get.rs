@@ -0,0 +1,80 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::storage::Storage;
+
+use bytes::Bytes;
+
+/// Get the value of key.
+///
+/// If the key does not exist the special value nil is returned.
+#[derive(Debug)]
+pub struct Get {
+    /// Name of the key to get
+    key: String,
+}
+
+impl Get {
+    /// Create a new `Get` command which fetches `key`.
+    pub fn new(key: impl ToString) -> Get {
+        Get {
+            key: key.to_string(),
+        }
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Parse a `Get` instance from a received frame.
+    ///
+    /// The `Parse` argument provides a cursor-like API to read fields from the
+    /// `Frame`. At this point, the entire frame has already been received from
+    /// the socket.
+    ///
+    /// The `GET` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing two entries.
+    ///
+    /// ```text
+    /// GET key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Get, ParseError> {
+        // The `GET` string has already been consumed. The next value is the
+        // name of the key to get. If the next value is not a string or the
+        // input is fully consumed, then an error is returned.
+        let key = parse.next_string()?;
+
+        Ok(Get { key })
+    }
+
+    /// Apply the `Get` command to the specified `Storage`.
+    ///
+    /// The response is returned as a frame to be written to the client.
+    pub(crate) fn apply(self, db: &dyn Storage) -> Frame {
+        // DB から値を取り出す
+        match db.get(&self.key) {
+            Some(value) => {
+                tracing::debug!("GET: key={:?}, value={:?}", self.key, value);
+                Frame::Bulk(value)
+            }
+            None => {
+                tracing::debug!("GET: No value found for key {:?}", self.key);
+                Frame::Null
+            }
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Get` command to send to
+    /// the server.
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("get".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame
+    }
+}
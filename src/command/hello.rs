@@ -0,0 +1,77 @@
+use crate::frame::{Frame, Protocol};
+use crate::parse::{Parse, ParseError};
+use crate::session::Session;
+
+use bytes::Bytes;
+
+/// Switches the protocol version of the connection and returns information
+/// about the server.
+///
+/// Without a version, the protocol is left unchanged. The reply is a map, which
+/// is sent as a flat array of keys and values when speaking RESP2.
+#[derive(Debug, Default)]
+pub struct Hello {
+    /// requested protocol version
+    protover: Option<i64>,
+}
+
+impl Hello {
+    /// Create a new `Hello` command, switching to `protover` if given.
+    pub fn new(protover: Option<i64>) -> Hello {
+        Hello { protover }
+    }
+
+    /// Parse a `Hello` instance from a received frame.
+    ///
+    /// The `HELLO` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing `HELLO` and an optional version.
+    ///
+    /// ```text
+    /// HELLO [protover]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Hello, ParseError> {
+        match parse.next_int() {
+            Ok(protover) => Ok(Hello::new(Some(protover))),
+            Err(ParseError::EndOfStream) => Ok(Hello::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Apply the `Hello` command to the connection's `Session`.
+    pub(crate) fn apply(self, session: &mut Session) -> Frame {
+        match self.protover {
+            None => {}
+            Some(2) => session.protocol = Protocol::Resp2,
+            Some(3) => session.protocol = Protocol::Resp3,
+            Some(_) => return Frame::Error("NOPROTO unsupported protocol version".to_string()),
+        }
+
+        let proto = match session.protocol {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        };
+        let field = |name: &'static str| Frame::Bulk(Bytes::from_static(name.as_bytes()));
+
+        Frame::Map(vec![
+            (field("server"), field(env!("CARGO_PKG_NAME"))),
+            (field("version"), field(env!("CARGO_PKG_VERSION"))),
+            (field("proto"), Frame::Integer(proto)),
+            (field("mode"), field("standalone")),
+            (field("role"), field("master")),
+            (field("modules"), Frame::Array(vec![])),
+        ])
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("hello".as_bytes()));
+        if let Some(protover) = self.protover {
+            frame.push_bulk(Bytes::from(protover.to_string()));
+        }
+        frame
+    }
+}
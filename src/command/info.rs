@@ -0,0 +1,156 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::session::Session;
+
+use bytes::Bytes;
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+
+/// Returns information and statistics about the server, as `field:value`
+/// lines grouped in sections.
+#[derive(Debug, Default)]
+pub struct Info {
+    /// section to return, all of them if not given
+    section: Option<String>,
+}
+
+impl Info {
+    /// Create a new `Info` command returning `section`, or all sections.
+    pub fn new(section: Option<String>) -> Info {
+        Info { section }
+    }
+
+    /// Parse an `Info` instance from a received frame.
+    ///
+    /// The `INFO` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing `INFO` and an optional section.
+    ///
+    /// ```text
+    /// INFO [section]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Info, ParseError> {
+        match parse.next_string() {
+            Ok(section) => Ok(Info::new(Some(section.to_lowercase()))),
+            Err(ParseError::EndOfStream) => Ok(Info::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Apply the `Info` command, reading the statistics shared through the
+    /// `Session`.
+    pub(crate) fn apply(self, session: &mut Session) -> Frame {
+        let stats = &session.stats;
+        let sections = [
+            (
+                "Server",
+                vec![
+                    ("mini_redis_version", env!("CARGO_PKG_VERSION").to_string()),
+                    ("tcp_port", session.config.get_as::<u16>("port").to_string()),
+                ],
+            ),
+            (
+                "Clients",
+                vec![
+                    (
+                        "connected_clients",
+                        stats.connected_clients.load(Ordering::Relaxed).to_string(),
+                    ),
+                    ("maxclients", session.config.maxclients().to_string()),
+                ],
+            ),
+            (
+                "Persistence",
+                vec![
+                    (
+                        "rdb_changes_since_last_save",
+                        stats
+                            .changes_since_last_save
+                            .load(Ordering::Relaxed)
+                            .to_string(),
+                    ),
+                    (
+                        "rdb_bgsave_in_progress",
+                        (stats.bgsave_in_progress.load(Ordering::Relaxed) as u8).to_string(),
+                    ),
+                    (
+                        "rdb_last_save_time",
+                        stats.last_save_time.load(Ordering::Relaxed).to_string(),
+                    ),
+                    (
+                        "rdb_last_bgsave_status",
+                        match stats.last_bgsave_ok.load(Ordering::Relaxed) {
+                            true => "ok",
+                            false => "err",
+                        }
+                        .to_string(),
+                    ),
+                    ("aof_enabled", (session.aof.is_some() as u8).to_string()),
+                    (
+                        "aof_rewrite_in_progress",
+                        (stats.aof_rewrite_in_progress.load(Ordering::Relaxed) as u8).to_string(),
+                    ),
+                ],
+            ),
+            (
+                "Stats",
+                vec![
+                    (
+                        "total_connections_received",
+                        stats
+                            .total_connections_received
+                            .load(Ordering::Relaxed)
+                            .to_string(),
+                    ),
+                    (
+                        "total_commands_processed",
+                        stats
+                            .total_commands_processed
+                            .load(Ordering::Relaxed)
+                            .to_string(),
+                    ),
+                    (
+                        "rejected_connections",
+                        stats
+                            .rejected_connections
+                            .load(Ordering::Relaxed)
+                            .to_string(),
+                    ),
+                ],
+            ),
+        ];
+
+        let mut info = String::new();
+        for (name, fields) in sections {
+            let wanted = match &self.section {
+                None => true,
+                Some(section) => section == "all" || section.eq_ignore_ascii_case(name),
+            };
+            if !wanted {
+                continue;
+            }
+
+            if !info.is_empty() {
+                info.push_str("\r\n");
+            }
+            let _ = write!(info, "# {}\r\n", name);
+            for (field, value) in fields {
+                let _ = write!(info, "{}:{}\r\n", field, value);
+            }
+        }
+
+        Frame::Verbatim("txt".to_string(), Bytes::from(info))
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("info".as_bytes()));
+        if let Some(section) = self.section {
+            frame.push_bulk(Bytes::from(section.into_bytes()));
+        }
+        frame
+    }
+}
@@ -0,0 +1,50 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::storage::Storage;
+
+/// Returns the type of the value stored at `key`: `string`, or `none` if the
+/// key does not exist, as the keyspace only holds strings.
+#[derive(Debug)]
+pub struct Type {
+    /// the lookup key
+    key: String,
+}
+
+impl Type {
+    /// Create a new `Type` command which queries the type of `key`.
+    pub fn new(key: impl ToString) -> Type {
+        Type {
+            key: key.to_string(),
+        }
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Parse a `Type` instance from a received frame.
+    ///
+    /// The `TYPE` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing two entries.
+    ///
+    /// ```text
+    /// TYPE key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Type, ParseError> {
+        let key = parse.next_string()?;
+
+        Ok(Type::new(key))
+    }
+
+    /// Apply the `Type` command to the specified `Storage`.
+    pub(crate) fn apply(self, db: &dyn Storage) -> Frame {
+        match db.get(&self.key) {
+            Some(_) => Frame::Simple("string".to_string()),
+            None => Frame::Simple("none".to_string()),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use crate::frame::Frame;
+use crate::glob::glob_match;
+use crate::parse::{Parse, ParseError};
+use crate::storage::Storage;
+
+use bytes::Bytes;
+
+/// Number of keys fetched from the store per `Storage::scan` call.
+const BATCH: usize = 1024;
+
+/// Returns all keys matching a glob-style pattern.
+///
+/// The whole keyspace is walked before replying, so `SCAN` should be preferred
+/// on large keyspaces.
+#[derive(Debug)]
+pub struct Keys {
+    /// glob-style pattern the keys must match
+    pattern: String,
+}
+
+impl Keys {
+    /// Create a new `Keys` command which returns the keys matching `pattern`.
+    pub fn new(pattern: impl ToString) -> Keys {
+        Keys {
+            pattern: pattern.to_string(),
+        }
+    }
+
+    /// Get the pattern
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Parse a `Keys` instance from a received frame.
+    ///
+    /// The `KEYS` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing two entries.
+    ///
+    /// ```text
+    /// KEYS pattern
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Keys, ParseError> {
+        let pattern = parse.next_string()?;
+
+        Ok(Keys { pattern })
+    }
+
+    /// Apply the `Keys` command to the specified `Storage`.
+    pub(crate) fn apply(self, db: &dyn Storage) -> Frame {
+        let mut response = Frame::array();
+        let mut cursor = 0;
+
+        loop {
+            let (next, keys) = db.scan(cursor, BATCH);
+
+            for key in keys {
+                if glob_match(self.pattern.as_bytes(), key.as_bytes()) {
+                    response.push_bulk(Bytes::from(key));
+                }
+            }
+
+            if next == 0 {
+                return response;
+            }
+            cursor = next;
+        }
+    }
+}
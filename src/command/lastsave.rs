@@ -0,0 +1,41 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::session::Session;
+
+use bytes::Bytes;
+use std::sync::atomic::Ordering;
+
+/// Returns the Unix time in seconds of the last successful save of the
+/// keyspace, or of the server startup if it was never saved.
+#[derive(Debug, Default)]
+pub struct Lastsave;
+
+impl Lastsave {
+    /// Parse a `Lastsave` instance from a received frame.
+    ///
+    /// The `LASTSAVE` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing a single entry.
+    ///
+    /// ```text
+    /// LASTSAVE
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> Result<Lastsave, ParseError> {
+        Ok(Lastsave)
+    }
+
+    /// Apply the `Lastsave` command, reading the statistics shared through the
+    /// `Session`.
+    pub(crate) fn apply(self, session: &mut Session) -> Frame {
+        Frame::Integer(session.stats.last_save_time.load(Ordering::Relaxed) as i64)
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("lastsave".as_bytes()));
+        frame
+    }
+}
@@ -0,0 +1,66 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::storage::Storage;
+
+/// Returns the values of all specified keys.
+///
+/// For every key that does not exist, the special value nil is returned.
+#[derive(Debug)]
+pub struct Mget {
+    /// the keys to get
+    keys: Vec<String>,
+}
+
+impl Mget {
+    /// Create a new `Mget` command which fetches `keys`.
+    pub fn new(keys: Vec<String>) -> Mget {
+        Mget { keys }
+    }
+
+    /// Get the keys
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Parse a `Mget` instance from a received frame.
+    ///
+    /// The `MGET` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing at least two entries.
+    ///
+    /// ```text
+    /// MGET key [key ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Mget, ParseError> {
+        // At least one key is required
+        let mut keys = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(key) => keys.push(key),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Mget { keys })
+    }
+
+    /// Apply the `Mget` command to the specified `Storage`.
+    ///
+    /// The values are read from a consistent view of the keyspace.
+    pub(crate) fn apply(self, db: &dyn Storage) -> Frame {
+        let values = db
+            .get_many(&self.keys)
+            .into_iter()
+            .map(|value| match value {
+                Some(value) => Frame::Bulk(value),
+                None => Frame::Null,
+            })
+            .collect();
+
+        Frame::Array(values)
+    }
+}
@@ -0,0 +1,331 @@
+use crate::connection::{Connection, ConnectionTrait};
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::rdb::{self, Value};
+use crate::session::Session;
+use crate::storage::Storage;
+
+use bytes::Bytes;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::{self, Instant};
+
+/// Move keys to another instance, which recreates them with `RESTORE`.
+///
+/// The keys are removed once the target instance has restored them, unless
+/// `COPY` is given. A key modified meanwhile is left on both instances, and
+/// the command replies with an error naming it. The command blocks the
+/// connection until the target replies or `timeout` milliseconds pass, while
+/// other connections keep being served. Replies `NOKEY` if none of the keys
+/// exist.
+#[derive(Debug)]
+pub struct Migrate {
+    /// host of the target instance
+    host: String,
+    /// port of the target instance
+    port: u16,
+    /// keys to move
+    keys: Vec<String>,
+    /// database of the target instance, only 0 is supported
+    db: i64,
+    /// timeout of each operation with the target instance, in milliseconds
+    timeout: u64,
+    /// whether the keys are kept
+    copy: bool,
+    /// whether existing keys of the target instance are replaced
+    replace: bool,
+    /// username, if any, and password to authenticate to the target instance
+    auth: Option<(Option<String>, String)>,
+}
+
+impl Migrate {
+    /// Create a new `Migrate` command which moves `keys` to `host:port`.
+    pub fn new(host: impl ToString, port: u16, keys: Vec<String>, timeout: u64) -> Migrate {
+        Migrate {
+            host: host.to_string(),
+            port,
+            keys,
+            db: 0,
+            timeout,
+            copy: false,
+            replace: false,
+            auth: None,
+        }
+    }
+
+    /// Get the keys
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Parse a `Migrate` instance from a received frame.
+    ///
+    /// The `MIGRATE` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing at least six entries. `key` is
+    /// empty when the keys are given with `KEYS`.
+    ///
+    /// ```text
+    /// MIGRATE host port key destination-db timeout [COPY] [REPLACE]
+    ///     [AUTH password | AUTH2 username password] [KEYS key [key ...]]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Migrate, ParseError> {
+        let host = parse.next_string()?;
+        let port = u16::try_from(parse.next_int()?).map_err(|_| "invalid port")?;
+        let key = parse.next_string()?;
+        let db = parse.next_int()?;
+        let timeout = u64::try_from(parse.next_int()?).map_err(|_| "invalid timeout")?;
+
+        let mut migrate = Migrate::new(host, port, vec![], timeout);
+        migrate.db = db;
+        loop {
+            let option = match parse.next_string() {
+                Ok(option) => option.to_lowercase(),
+                Err(ParseError::EndOfStream) => break,
+                Err(e) => return Err(e),
+            };
+            match &option[..] {
+                "copy" => migrate.copy = true,
+                "replace" => migrate.replace = true,
+                "auth" => migrate.auth = Some((None, parse.next_string()?)),
+                "auth2" => migrate.auth = Some((Some(parse.next_string()?), parse.next_string()?)),
+                "keys" => {
+                    if !key.is_empty() {
+                        return Err("When using MIGRATE KEYS option, the key argument must be set to the empty string".into());
+                    }
+                    loop {
+                        match parse.next_string() {
+                            Ok(key) => migrate.keys.push(key),
+                            Err(ParseError::EndOfStream) => break,
+                            Err(e) => return Err(e),
+                        }
+                    }
+                }
+                _ => return Err("syntax error".into()),
+            }
+        }
+        if migrate.keys.is_empty() {
+            migrate.keys.push(key);
+        }
+
+        Ok(migrate)
+    }
+
+    /// Execute the `Migrate` command against the specified `Storage`.
+    ///
+    /// Unlike other commands, the keys are sent to the target instance
+    /// asynchronously, without holding the AOF lock, so that other clients
+    /// keep running commands meanwhile. A key is then removed only if it was
+    /// not modified during the transfer, and its removal is logged to the AOF
+    /// as a `DEL`, since replaying `MIGRATE` would move the keys again. Keys
+    /// modified during the transfer are reported in an error reply, since
+    /// they were not moved.
+    pub(crate) async fn execute(self, db: &dyn Storage, session: &mut Session) -> Frame {
+        if self.db != 0 {
+            return Frame::Error("ERR DB index is out of range".to_string());
+        }
+
+        // 送るキーの値と有効期限をまとめて読み出す。存在しないキーは送らない
+        let entries: Vec<_> = self
+            .keys
+            .iter()
+            .filter_map(|key| {
+                db.get_entry(key)
+                    .map(|(value, expires_at)| (key, value, expires_at))
+            })
+            .collect();
+        if entries.is_empty() {
+            return Frame::Simple("NOKEY".to_string());
+        }
+
+        let replies = match self.transfer(&entries).await {
+            Ok(replies) => replies,
+            Err(err) => return Frame::Error(err),
+        };
+
+        // 移行先が復元したキーだけを削除する
+        let mut error = None;
+        let mut moved = vec![];
+        for (entry, reply) in entries.iter().zip(replies) {
+            match reply {
+                Frame::Error(err) => {
+                    error.get_or_insert(err);
+                }
+                _ => moved.push(entry),
+            }
+        }
+        let mut left = vec![];
+        if !self.copy {
+            let (seq, modified) = remove(&moved, db, session);
+            if let (Some(seq), Some(aof)) = (seq, &session.aof) {
+                aof.wait(seq).await;
+            }
+            left = modified;
+        }
+
+        match error {
+            Some(err) => Frame::Error(format!("ERR Target instance replied with error: {}", err)),
+            None if !left.is_empty() => Frame::Error(format!(
+                "ERR Keys modified during MIGRATE were left on both instances: {}",
+                left.join(", ")
+            )),
+            None => Frame::Simple("OK".to_string()),
+        }
+    }
+
+    /// Send `RESTORE` commands recreating `entries` to the target instance,
+    /// and return its reply to each of them.
+    async fn transfer(
+        &self,
+        entries: &[(&String, Bytes, Option<Instant>)],
+    ) -> Result<Vec<Frame>, String> {
+        // Redis と同じく、0 は 1 秒として扱う
+        let timeout = match self.timeout {
+            0 => Duration::from_secs(1),
+            ms => Duration::from_millis(ms),
+        };
+
+        let addr = (self.host.as_str(), self.port);
+        let socket = match time::timeout(timeout, TcpStream::connect(addr)).await {
+            Ok(Ok(socket)) => socket,
+            result => {
+                let err = result.map(|res| res.err()).unwrap_or(None);
+                tracing::warn!(
+                    "MIGRATE: failed connecting to {}:{}: {}",
+                    self.host,
+                    self.port,
+                    err.map_or("timed out".to_string(), |err| err.to_string())
+                );
+                return Err("IOERR error or timeout connecting to the client".to_string());
+            }
+        };
+        let mut connection = Connection::new(socket);
+
+        let mut commands = vec![];
+        if let Some((user, password)) = &self.auth {
+            let mut frame = Frame::array();
+            frame.push_bulk(Bytes::from("auth"));
+            if let Some(user) = user {
+                frame.push_bulk(Bytes::from(user.clone()));
+            }
+            frame.push_bulk(Bytes::from(password.clone()));
+            commands.push(frame);
+        }
+        // RESTORE では 0 が有効期限なしを表すので、期限の迫ったキーは 1 ミリ秒とする
+        let now = Instant::now();
+        for (key, value, expires_at) in entries {
+            let ttl = match expires_at {
+                Some(when) => (when.saturating_duration_since(now).as_millis() as u64).max(1),
+                None => 0,
+            };
+            let mut frame = Frame::array();
+            frame.push_bulk(Bytes::from("restore"));
+            frame.push_bulk(Bytes::from(key.to_string()));
+            frame.push_bulk(Bytes::from(ttl.to_string()));
+            frame.push_bulk(Bytes::from(rdb::dump_payload(&Value::String(
+                value.clone(),
+            ))));
+            if self.replace {
+                frame.push_bulk(Bytes::from("replace"));
+            }
+            commands.push(frame);
+        }
+
+        // コマンドをまとめて送ってから、応答を順に読む
+        let exchange = async {
+            for frame in &commands {
+                time::timeout(timeout, connection.write_frame(frame)).await??;
+            }
+            time::timeout(timeout, connection.flush()).await??;
+
+            let mut replies = vec![];
+            for _ in &commands {
+                match time::timeout(timeout, connection.read_frame()).await?? {
+                    Some(frame) => replies.push(frame),
+                    None => return Err("connection closed by the target instance".into()),
+                }
+            }
+            Ok::<_, crate::Error>(replies)
+        };
+        let mut replies = exchange.await.map_err(|err| {
+            tracing::warn!("MIGRATE: {}:{}: {}", self.host, self.port, err);
+            "IOERR error or timeout reading to target instance".to_string()
+        })?;
+
+        if self.auth.is_some() {
+            if let Frame::Error(err) = replies.remove(0) {
+                return Err(format!("ERR Target instance replied with error: {}", err));
+            }
+        }
+        Ok(replies)
+    }
+}
+
+/// Remove the keys of `moved` which still hold the value and deadline that
+/// were sent, and log their removal to the AOF.
+///
+/// Returns the sequence number of the AOF entry, if any, and the keys which
+/// were modified meanwhile and are kept.
+fn remove<'a>(
+    moved: &[&(&'a String, Bytes, Option<Instant>)],
+    db: &dyn Storage,
+    session: &mut Session,
+) -> (Option<u64>, Vec<&'a str>) {
+    // 書き込みコマンドと同じく、AOF をロックしたまま削除して記録する
+    let aof = session.aof.clone();
+    let mut log = aof.as_ref().map(|aof| aof.lock());
+
+    let mut del = Frame::array();
+    del.push_bulk(Bytes::from("del".as_bytes()));
+    let mut removed = 0;
+    let mut modified = vec![];
+    for (key, value, expires_at) in moved {
+        // 転送中に書き換えられたキーは削除しない
+        if db.del_if_unchanged(key, value, *expires_at) {
+            del.push_bulk(Bytes::from(key.to_string()));
+            removed += 1;
+        } else {
+            modified.push(key.as_str());
+        }
+    }
+    session
+        .stats
+        .changes_since_last_save
+        .fetch_add(removed, Ordering::Relaxed);
+
+    let seq = match (&mut log, removed) {
+        (Some(log), 1..) => Some(log.append(&del, &[], db)),
+        _ => None,
+    };
+    (seq, modified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::storage::SetCondition;
+
+    #[tokio::test]
+    async fn modified_keys_are_kept() {
+        let db = Db::default();
+        db.set("a".to_string(), "1".into(), None, SetCondition::Always);
+        db.set("b".to_string(), "2".into(), None, SetCondition::Always);
+        let (a, b) = ("a".to_string(), "b".to_string());
+        let sent = [(&a, Bytes::from("1"), None), (&b, Bytes::from("2"), None)];
+
+        // b が転送中に書き換えられた
+        db.set("b".to_string(), "3".into(), None, SetCondition::Always);
+        let mut session = Session::new(Default::default(), Default::default(), Default::default());
+        let moved: Vec<_> = sent.iter().collect();
+        let (seq, modified) = remove(&moved, &db, &mut session);
+
+        assert_eq!(seq, None);
+        assert_eq!(modified, ["b"]);
+        assert_eq!(db.get("a"), None);
+        assert_eq!(db.get("b"), Some("3".into()));
+    }
+}
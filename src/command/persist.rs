@@ -0,0 +1,50 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::storage::Storage;
+
+/// Remove the existing timeout on `key`, turning the key from volatile to
+/// persistent.
+///
+/// Replies `1` if the timeout was removed and `0` if the key does not exist
+/// or does not have an associated timeout.
+#[derive(Debug)]
+pub struct Persist {
+    /// the lookup key
+    key: String,
+}
+
+impl Persist {
+    /// Create a new `Persist` command which removes the timeout of `key`.
+    pub fn new(key: impl ToString) -> Persist {
+        Persist {
+            key: key.to_string(),
+        }
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Parse a `Persist` instance from a received frame.
+    ///
+    /// The `PERSIST` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing two entries.
+    ///
+    /// ```text
+    /// PERSIST key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Persist, ParseError> {
+        let key = parse.next_string()?;
+
+        Ok(Persist::new(key))
+    }
+
+    /// Apply the `Persist` command to the specified `Storage`.
+    pub(crate) fn apply(self, db: &dyn Storage) -> Frame {
+        Frame::Integer(db.expire(&self.key, None) as i64)
+    }
+}
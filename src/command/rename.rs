@@ -0,0 +1,63 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::storage::Storage;
+
+/// Renames `key` to `newkey`. It returns an error when `key` does not exist.
+///
+/// If `newkey` already exists it is overwritten. The time to live of `key` is
+/// transferred to `newkey`.
+#[derive(Debug)]
+pub struct Rename {
+    /// the key to rename
+    key: String,
+
+    /// the new name of the key
+    new_key: String,
+}
+
+impl Rename {
+    /// Create a new `Rename` command which renames `key` to `new_key`.
+    pub fn new(key: impl ToString, new_key: impl ToString) -> Rename {
+        Rename {
+            key: key.to_string(),
+            new_key: new_key.to_string(),
+        }
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get the new key
+    pub fn new_key(&self) -> &str {
+        &self.new_key
+    }
+
+    /// Parse a `Rename` instance from a received frame.
+    ///
+    /// The `RENAME` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing three entries.
+    ///
+    /// ```text
+    /// RENAME key newkey
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Rename, ParseError> {
+        let key = parse.next_string()?;
+        let new_key = parse.next_string()?;
+
+        Ok(Rename { key, new_key })
+    }
+
+    /// Apply the `Rename` command to the specified `Storage`.
+    pub(crate) fn apply(self, db: &dyn Storage) -> Frame {
+        if db.rename(&self.key, &self.new_key) {
+            Frame::Simple("OK".to_string())
+        } else {
+            Frame::Error("ERR no such key".to_string())
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::clock;
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::rdb::{self, Value};
+use crate::storage::{SetCondition, Storage};
+
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Create a key from a payload returned by `DUMP`.
+///
+/// `ttl` is the time to live of the key in milliseconds, or its Unix time in
+/// milliseconds with `ABSTTL`. 0 creates a key without deadline. An error is
+/// returned if the key already exists, unless `REPLACE` is given.
+#[derive(Debug)]
+pub struct Restore {
+    /// the key to create
+    key: String,
+    /// time to live or Unix time in milliseconds, 0 for none
+    ttl: u64,
+    /// payload returned by `DUMP`
+    payload: Bytes,
+    /// whether an existing key is replaced
+    replace: bool,
+    /// whether `ttl` is a Unix time
+    absttl: bool,
+}
+
+impl Restore {
+    /// Create a new `Restore` command which creates `key` from `payload`.
+    pub fn new(key: impl ToString, ttl: u64, payload: Bytes) -> Restore {
+        Restore {
+            key: key.to_string(),
+            ttl,
+            payload,
+            replace: false,
+            absttl: false,
+        }
+    }
+
+    /// Replace the key if it already exists.
+    pub fn replace(mut self) -> Restore {
+        self.replace = true;
+        self
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Parse a `Restore` instance from a received frame.
+    ///
+    /// The `RESTORE` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing at least four entries.
+    ///
+    /// ```text
+    /// RESTORE key ttl serialized-value [REPLACE] [ABSTTL]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Restore, ParseError> {
+        let key = parse.next_string()?;
+        let ttl =
+            u64::try_from(parse.next_int()?).map_err(|_| "Invalid TTL value, must be >= 0")?;
+        let mut restore = Restore::new(key, ttl, parse.next_bytes()?);
+
+        loop {
+            match parse.next_string() {
+                Ok(s) if s.eq_ignore_ascii_case("replace") => restore.replace = true,
+                Ok(s) if s.eq_ignore_ascii_case("absttl") => restore.absttl = true,
+                Ok(_) => return Err("syntax error".into()),
+                Err(ParseError::EndOfStream) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(restore)
+    }
+
+    /// Apply the `Restore` command to the specified `Storage`.
+    pub(crate) fn apply(self, db: &dyn Storage) -> Frame {
+        let busy = || Frame::Error("BUSYKEY Target key name already exists.".to_string());
+        if !self.replace && db.get(&self.key).is_some() {
+            return busy();
+        }
+
+        let value = match rdb::restore_payload(&self.payload) {
+            Ok(Value::String(value)) => value,
+            Ok(value) => {
+                return Frame::Error(format!(
+                    "ERR values of type {} are not supported",
+                    value.type_name()
+                ))
+            }
+            Err(err) => return Frame::Error(format!("ERR {}", err)),
+        };
+
+        let expires_at = match (self.ttl, self.absttl) {
+            (0, _) => None,
+            (ttl, false) => match Instant::now().checked_add(Duration::from_millis(ttl)) {
+                Some(when) => Some(when),
+                None => {
+                    return Frame::Error("ERR invalid expire time in 'restore' command".to_string())
+                }
+            },
+            (ttl, true) => match clock::from_unix_ms(ttl) {
+                Some(when) => Some(when),
+                // 期限切れのキーは作らず、置き換える場合は既存のキーを削除する
+                None => {
+                    db.del(&[self.key]);
+                    return Frame::Simple("OK".to_string());
+                }
+            },
+        };
+
+        let condition = match self.replace {
+            true => SetCondition::Always,
+            false => SetCondition::IfNotExists,
+        };
+        match db.set(self.key, value, expires_at, condition) {
+            true => Frame::Simple("OK".to_string()),
+            false => busy(),
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("restore".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame.push_bulk(Bytes::from(self.ttl.to_string()));
+        frame.push_bulk(self.payload);
+        if self.replace {
+            frame.push_bulk(Bytes::from("replace".as_bytes()));
+        }
+        if self.absttl {
+            frame.push_bulk(Bytes::from("absttl".as_bytes()));
+        }
+        frame
+    }
+}
@@ -0,0 +1,76 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::rdb;
+use crate::session::Session;
+use crate::storage::Storage;
+
+use bytes::Bytes;
+
+/// Write a snapshot of the keyspace to the dump file, `dbfilename` in `dir`.
+///
+/// `SAVE` replies once the snapshot is written, and is refused while a
+/// `BGSAVE` is running. `BGSAVE` copies the keyspace, replies immediately and
+/// writes the snapshot in the background.
+#[derive(Debug)]
+pub struct Save {
+    /// whether the snapshot is written in the background
+    background: bool,
+}
+
+impl Save {
+    /// Create a new `Save` command, writing the snapshot in the background if
+    /// `background` is set.
+    pub fn new(background: bool) -> Save {
+        Save { background }
+    }
+
+    /// Returns the command name
+    pub(crate) fn get_name(&self) -> &str {
+        if self.background {
+            "bgsave"
+        } else {
+            "save"
+        }
+    }
+
+    /// Parse a `Save` instance from a received frame.
+    ///
+    /// The `SAVE` or `BGSAVE` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing a single entry.
+    ///
+    /// ```text
+    /// SAVE
+    /// BGSAVE
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse, background: bool) -> Result<Save, ParseError> {
+        Ok(Save::new(background))
+    }
+
+    /// Apply the `Save` command to the specified `Storage`.
+    pub(crate) fn apply(self, db: &dyn Storage, session: &mut Session) -> Frame {
+        if self.background {
+            return match rdb::bgsave(db, &session.config, &session.stats) {
+                Ok(()) => Frame::Simple("Background saving started".to_string()),
+                Err(err) => Frame::Error(format!("ERR {}", err)),
+            };
+        }
+
+        match rdb::save(db, &session.config, &session.stats) {
+            Ok(()) => Frame::Simple("OK".to_string()),
+            Err(err) => {
+                tracing::error!("Failed saving the DB: {}", err);
+                Frame::Error(format!("ERR {}", err))
+            }
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from(self.get_name().to_string()));
+        frame
+    }
+}
@@ -0,0 +1,99 @@
+use crate::frame::Frame;
+use crate::glob::glob_match;
+use crate::parse::{Parse, ParseError};
+use crate::storage::Storage;
+
+use bytes::Bytes;
+
+/// Number of keys `SCAN` visits per call when no `COUNT` is given.
+const DEFAULT_COUNT: usize = 10;
+
+/// Incrementally iterate over the keys.
+///
+/// Each call returns the cursor to pass to the next call along with a batch of
+/// keys. The iteration is complete once the returned cursor is `0`.
+#[derive(Debug)]
+pub struct Scan {
+    /// cursor returned by the previous call, `0` to start a new iteration
+    cursor: u64,
+
+    /// only return keys matching this glob-style pattern
+    pattern: Option<String>,
+
+    /// hint of how many keys to visit
+    count: usize,
+}
+
+impl Scan {
+    /// Create a new `Scan` command continuing the iteration at `cursor`.
+    pub fn new(cursor: u64) -> Scan {
+        Scan {
+            cursor,
+            pattern: None,
+            count: DEFAULT_COUNT,
+        }
+    }
+
+    /// Get the cursor
+    pub fn cursor(&self) -> u64 {
+        self.cursor
+    }
+
+    /// Parse a `Scan` instance from a received frame.
+    ///
+    /// The `SCAN` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing at least two entries.
+    ///
+    /// ```text
+    /// SCAN cursor [MATCH pattern] [COUNT count]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Scan, ParseError> {
+        let cursor = parse
+            .next_string()?
+            .parse::<u64>()
+            .map_err(|_| "invalid cursor")?;
+        let mut scan = Scan::new(cursor);
+
+        loop {
+            match parse.next_string() {
+                Ok(s) if s.eq_ignore_ascii_case("match") => {
+                    scan.pattern = Some(parse.next_string()?);
+                }
+                Ok(s) if s.eq_ignore_ascii_case("count") => {
+                    scan.count = match parse.next_int()? {
+                        count if count > 0 => count as usize,
+                        _ => return Err("syntax error".into()),
+                    };
+                }
+                Ok(_) => return Err("syntax error".into()),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(scan)
+    }
+
+    /// Apply the `Scan` command to the specified `Storage`.
+    ///
+    /// The pattern is applied after the keys have been retrieved, so a call
+    /// may return no keys even though the iteration is not complete.
+    pub(crate) fn apply(self, db: &dyn Storage) -> Frame {
+        let (cursor, keys) = db.scan(self.cursor, self.count);
+
+        let mut batch = Frame::array();
+        for key in keys {
+            if let Some(pattern) = &self.pattern {
+                if !glob_match(pattern.as_bytes(), key.as_bytes()) {
+                    continue;
+                }
+            }
+            batch.push_bulk(Bytes::from(key));
+        }
+
+        Frame::Array(vec![Frame::Bulk(Bytes::from(cursor.to_string())), batch])
+    }
+}
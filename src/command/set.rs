@@ -0,0 +1,195 @@
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::storage::{SetCondition, Storage};
+
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Set `key` to hold the string `value`.
+///
+/// If `key` already holds a value, it is overwritten, regardless of its type.
+/// Any previous time to live associated with the key is discarded on
+/// successful SET operation.
+///
+/// # Options
+///
+/// Currently, the following options are supported:
+///
+/// * EX `seconds` -- Set the specified expire time, in seconds.
+/// * PX `milliseconds` -- Set the specified expire time, in milliseconds.
+/// * NX -- Only set the key if it does not already exist.
+/// * XX -- Only set the key if it already exists.
+#[derive(Debug)]
+pub struct Set {
+    /// the lookup key
+    key: String,
+
+    /// the value to be stored
+    value: Bytes,
+
+    /// When to expire the key
+    expire: Option<Duration>,
+
+    /// Whether the key must (not) exist for the value to be stored
+    condition: SetCondition,
+}
+
+impl Set {
+    /// Create a new `Set` command which sets `key` to `value`.
+    ///
+    /// If `expire` is `Some`, the value should expire after the specified
+    /// duration.
+    pub fn new(key: impl ToString, value: Bytes, expire: Option<Duration>) -> Set {
+        Set {
+            key: key.to_string(),
+            value,
+            expire,
+            condition: SetCondition::Always,
+        }
+    }
+
+    /// Only store the value when `condition` holds (`NX` / `XX`).
+    pub fn with_condition(mut self, condition: SetCondition) -> Set {
+        self.condition = condition;
+        self
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get the value
+    pub fn value(&self) -> &Bytes {
+        &self.value
+    }
+
+    /// Get the expire
+    pub fn expire(&self) -> Option<Duration> {
+        self.expire
+    }
+
+    /// Get the condition
+    pub fn condition(&self) -> SetCondition {
+        self.condition
+    }
+
+    /// Parse a `Set` instance from a received frame.
+    ///
+    /// The `Parse` argument provides a cursor-like API to read fields from the
+    /// `Frame`. At this point, the entire frame has already been received from
+    /// the socket.
+    ///
+    /// The `SET` string has already been consumed.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Set` value on success. If the frame is malformed, `Err` is
+    /// returned.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing at least 3 entries.
+    ///
+    /// ```text
+    /// SET key value [EX seconds|PX milliseconds] [NX|XX]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Set, ParseError> {
+        // Read the key to set. This is a required field
+        let key = parse.next_string()?;
+
+        // Read the value to set. This is a required field.
+        let value = parse.next_bytes()?;
+
+        let mut set = Set::new(key, value, None);
+
+        // The options may be given in any order. A missing option argument is
+        // a syntax error rather than a wrong number of arguments.
+        loop {
+            let option = match parse.next_string() {
+                Ok(option) => option.to_uppercase(),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            };
+
+            match &option[..] {
+                "EX" | "PX" if set.expire.is_none() => {
+                    let timeout = match parse.next_int() {
+                        Ok(timeout) => timeout,
+                        Err(ParseError::EndOfStream) => return Err("syntax error".into()),
+                        Err(err) => return Err(err),
+                    };
+
+                    if timeout <= 0 {
+                        return Err("invalid expire time in 'set' command".into());
+                    }
+
+                    set.expire = Some(if option == "EX" {
+                        Duration::from_secs(timeout as u64)
+                    } else {
+                        Duration::from_millis(timeout as u64)
+                    });
+                }
+                "NX" if set.condition != SetCondition::IfExists => {
+                    set.condition = SetCondition::IfNotExists;
+                }
+                "XX" if set.condition != SetCondition::IfNotExists => {
+                    set.condition = SetCondition::IfExists;
+                }
+                _ => return Err("syntax error".into()),
+            }
+        }
+
+        Ok(set)
+    }
+
+    /// Apply the `Set` command to the specified `Storage`.
+    ///
+    /// The response is returned as a frame to be written to the client. A
+    /// `Null` reply means the `NX` / `XX` condition did not hold.
+    pub(crate) fn apply(self, db: &dyn Storage) -> Frame {
+        let expires_at = match self.expire {
+            Some(expire) => match Instant::now().checked_add(expire) {
+                Some(when) => Some(when),
+                None => {
+                    return Frame::Error("ERR invalid expire time in 'set' command".to_string())
+                }
+            },
+            None => None,
+        };
+
+        // DB に値をセットする
+        tracing::debug!("SET: key={:?}, value={:?}", self.key, self.value);
+        if db.set(self.key, self.value, expires_at, self.condition) {
+            Frame::Simple("OK".to_string())
+        } else {
+            Frame::Null
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Set` command to send to
+    /// the server.
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("set".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame.push_bulk(self.value);
+        if let Some(ms) = self.expire {
+            // Expirations in Redis protocol can be specified in two ways
+            // 1. SET key value EX seconds
+            // 2. SET key value PX milliseconds
+            // We choose the second option because it allows greater precision.
+            frame.push_bulk(Bytes::from("px".as_bytes()));
+            frame.push_int(ms.as_millis() as i64);
+        }
+        match self.condition {
+            SetCondition::Always => {}
+            SetCondition::IfNotExists => frame.push_bulk(Bytes::from("nx".as_bytes())),
+            SetCondition::IfExists => frame.push_bulk(Bytes::from("xx".as_bytes())),
+        }
+        frame
+    }
+}
@@ -0,0 +1,80 @@
+use crate::command::TimeUnit;
+use crate::frame::Frame;
+use crate::parse::{Parse, ParseError};
+use crate::storage::Storage;
+
+use tokio::time::Instant;
+
+/// Returns the remaining time to live of a key that has a timeout.
+///
+/// `TTL` replies in seconds and `PTTL` in milliseconds. `-2` is returned if
+/// the key does not exist and `-1` if the key exists but has no associated
+/// expire.
+#[derive(Debug)]
+pub struct Ttl {
+    /// the lookup key
+    key: String,
+
+    /// unit of the reply
+    unit: TimeUnit,
+}
+
+impl Ttl {
+    /// Create a new `Ttl` command which queries the time to live of `key`.
+    pub fn new(key: impl ToString, unit: TimeUnit) -> Ttl {
+        Ttl {
+            key: key.to_string(),
+            unit,
+        }
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get the unit of the reply
+    pub fn unit(&self) -> TimeUnit {
+        self.unit
+    }
+
+    /// Returns the command name
+    pub(crate) fn get_name(&self) -> &str {
+        match self.unit {
+            TimeUnit::Seconds => "ttl",
+            TimeUnit::Milliseconds => "pttl",
+        }
+    }
+
+    /// Parse a `Ttl` instance from a received frame.
+    ///
+    /// The `TTL` or `PTTL` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing two entries.
+    ///
+    /// ```text
+    /// TTL key
+    /// PTTL key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse, unit: TimeUnit) -> Result<Ttl, ParseError> {
+        let key = parse.next_string()?;
+
+        Ok(Ttl::new(key, unit))
+    }
+
+    /// Apply the `Ttl` command to the specified `Storage`.
+    pub(crate) fn apply(self, db: &dyn Storage) -> Frame {
+        let ttl = match db.expires_at(&self.key) {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(when)) => {
+                let remaining = when.saturating_duration_since(Instant::now());
+                self.unit.of(remaining)
+            }
+        };
+
+        Frame::Integer(ttl)
+    }
+}
@@ -0,0 +1,597 @@
+//! Server configuration.
+//!
+//! Parameters are read from a redis.conf-style file, overridden by the command
+//! line, and may be changed at runtime with `CONFIG SET`. Subsystems read their
+//! parameters from the shared `Config` when they need them, so changes apply
+//! without restarting the server.
+
+use crate::frame;
+use crate::glob::glob_match;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::RwLock;
+
+/// Values a parameter accepts.
+enum Kind {
+    /// An integer in the inclusive range
+    Integer(i64, i64),
+    /// File permissions, as an octal number
+    Octal,
+    /// One of the listed strings
+    Enum(&'static [&'static str]),
+    /// Any string, an empty string meaning unset
+    String,
+    /// Pairs of integers, as in `save <seconds> <changes> ...`
+    SavePoints,
+}
+
+/// A configuration parameter.
+struct Param {
+    name: &'static str,
+    default: &'static str,
+    kind: Kind,
+    /// whether the parameter can be changed with `CONFIG SET`
+    mutable: bool,
+    /// whether `CONFIG SET` also requires `enable-protected-configs`, for
+    /// parameters which let clients write files anywhere
+    protected: bool,
+}
+
+/// Parameters of the server, in alphabetical order.
+///
+/// Parameters that only take effect at startup, such as the listening
+/// addresses, are immutable. Parameters choosing where files are written are
+/// protected.
+const PARAMS: &[Param] = &[
+    Param {
+        name: "aclfile",
+        default: "",
+        kind: Kind::String,
+        mutable: false,
+        protected: false,
+    },
+    Param {
+        name: "appendfilename",
+        default: "appendonly.aof",
+        kind: Kind::String,
+        mutable: false,
+        protected: false,
+    },
+    Param {
+        name: "appendfsync",
+        default: "everysec",
+        kind: Kind::Enum(&["always", "everysec", "no"]),
+        mutable: true,
+        protected: false,
+    },
+    Param {
+        name: "appendonly",
+        default: "no",
+        kind: Kind::Enum(&["no", "yes"]),
+        mutable: false,
+        protected: false,
+    },
+    Param {
+        name: "bind",
+        default: "127.0.0.1",
+        kind: Kind::String,
+        mutable: false,
+        protected: false,
+    },
+    Param {
+        name: "connection",
+        default: "buffered",
+        kind: Kind::Enum(&["buffered", "raw"]),
+        mutable: false,
+        protected: false,
+    },
+    Param {
+        name: "dbfilename",
+        default: "dump.rdb",
+        kind: Kind::String,
+        mutable: true,
+        protected: true,
+    },
+    Param {
+        name: "dir",
+        default: ".",
+        kind: Kind::String,
+        mutable: true,
+        protected: true,
+    },
+    Param {
+        name: "enable-protected-configs",
+        default: "no",
+        kind: Kind::Enum(&["no", "yes"]),
+        mutable: false,
+        protected: false,
+    },
+    Param {
+        name: "maxclients",
+        default: "10000",
+        kind: Kind::Integer(1, u32::MAX as i64),
+        mutable: true,
+        protected: false,
+    },
+    Param {
+        name: "port",
+        default: "6379",
+        kind: Kind::Integer(0, u16::MAX as i64),
+        mutable: false,
+        protected: false,
+    },
+    Param {
+        name: "requirepass",
+        default: "",
+        kind: Kind::String,
+        mutable: true,
+        protected: false,
+    },
+    Param {
+        name: "save",
+        default: "",
+        kind: Kind::SavePoints,
+        mutable: true,
+        protected: false,
+    },
+    Param {
+        name: "shards",
+        default: "16",
+        kind: Kind::Integer(1, u16::MAX as i64),
+        mutable: false,
+        protected: false,
+    },
+    Param {
+        name: "tls-ca-cert-file",
+        default: "",
+        kind: Kind::String,
+        mutable: false,
+        protected: false,
+    },
+    Param {
+        name: "tls-cert-file",
+        default: "",
+        kind: Kind::String,
+        mutable: false,
+        protected: false,
+    },
+    Param {
+        name: "tls-key-file",
+        default: "",
+        kind: Kind::String,
+        mutable: false,
+        protected: false,
+    },
+    Param {
+        name: "tls-port",
+        default: "0",
+        kind: Kind::Integer(0, u16::MAX as i64),
+        mutable: false,
+        protected: false,
+    },
+    Param {
+        name: "unixsocket",
+        default: "",
+        kind: Kind::String,
+        mutable: false,
+        protected: false,
+    },
+    Param {
+        name: "unixsocketperm",
+        default: "0",
+        kind: Kind::Octal,
+        mutable: false,
+        protected: false,
+    },
+];
+
+/// Runtime configuration of the server, shared by all connections.
+#[derive(Debug)]
+pub struct Config {
+    values: RwLock<BTreeMap<&'static str, String>>,
+    /// file the configuration was loaded from, rewritten by `CONFIG REWRITE`
+    path: Option<PathBuf>,
+}
+
+impl Config {
+    /// Load the configuration from a redis.conf-style file.
+    ///
+    /// Each line holds a parameter name followed by its value, e.g.
+    /// `maxclients 100`. Values containing spaces can be quoted. Empty lines
+    /// and lines starting with `#` are ignored. Parameters that are not in the
+    /// file keep their default value.
+    pub fn load(path: &Path) -> crate::Result<Config> {
+        let mut config = Config::default();
+        let contents = std::fs::read_to_string(path)?;
+
+        let mut save_points = String::new();
+        for (i, line) in contents.lines().enumerate() {
+            let err = |msg: String| format!("{}:{}: {}", path.display(), i + 1, msg);
+
+            let (name, mut value) = match parse_line(line).map_err(err)? {
+                Some(param) => param,
+                None => continue,
+            };
+            // redis.conf と同様に、save 行はそれぞれ保存条件を 1 つ追加する
+            if name.eq_ignore_ascii_case("save") {
+                save_points = format!("{} {}", save_points, value).trim().to_string();
+                value = save_points.clone();
+            }
+            config.set(&name, value).map_err(err)?;
+        }
+
+        config.path = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    /// Returns the value of the parameter `name`, or `None` if there is no such
+    /// parameter.
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.values.read().unwrap().get(name).cloned()
+    }
+
+    /// Returns the value of the parameter `name` converted to `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such parameter or if its values cannot be
+    /// converted to `T`, which is a bug of the caller.
+    pub fn get_as<T: FromStr>(&self, name: &str) -> T {
+        let value = self.get(name).expect("unknown config parameter");
+        match value.parse() {
+            Ok(value) => value,
+            Err(_) => panic!(
+                "config parameter `{}` is not a `{}`",
+                name,
+                std::any::type_name::<T>()
+            ),
+        }
+    }
+
+    /// Returns the value of the file parameter `name`, or `None` if it is not
+    /// set.
+    pub fn get_path(&self, name: &str) -> Option<PathBuf> {
+        self.get(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    }
+
+    /// Returns the value of the octal parameter `name`, or `None` if it is 0.
+    pub fn get_octal(&self, name: &str) -> Option<u32> {
+        let value = self.get(name).expect("unknown config parameter");
+        u32::from_str_radix(&value, 8)
+            .ok()
+            .filter(|&value| value != 0)
+    }
+
+    /// Maximum number of clients connected at the same time.
+    pub fn maxclients(&self) -> usize {
+        self.get_as("maxclients")
+    }
+
+    /// Path of the RDB file snapshots are written to and loaded from, i.e.
+    /// `dbfilename` in `dir`.
+    pub fn dump_path(&self) -> PathBuf {
+        Path::new(&self.get_as::<String>("dir")).join(self.get_as::<String>("dbfilename"))
+    }
+
+    /// Path of the append-only file, i.e. `appendfilename` in `dir`, if
+    /// `appendonly` is enabled.
+    pub fn aof_path(&self) -> Option<PathBuf> {
+        (self.get_as::<String>("appendonly") == "yes").then(|| {
+            Path::new(&self.get_as::<String>("dir")).join(self.get_as::<String>("appendfilename"))
+        })
+    }
+
+    /// Save points as `(seconds, changes)` pairs: a snapshot is written once
+    /// `seconds` have elapsed since the last one if at least `changes` writes
+    /// were made meanwhile.
+    pub(crate) fn save_points(&self) -> Vec<(u64, u64)> {
+        let value = self.get_as::<String>("save");
+        let numbers: Vec<u64> = value
+            .split_whitespace()
+            .map(|n| n.parse().expect("invalid save points"))
+            .collect();
+        numbers.chunks(2).map(|pair| (pair[0], pair[1])).collect()
+    }
+
+    /// Set the parameter `name` to `value`, including immutable parameters,
+    /// e.g. from the command line.
+    pub fn set(&self, name: &str, value: String) -> Result<(), String> {
+        let param = find(name).ok_or("Bad directive or wrong number of arguments")?;
+        validate(param, &value)?;
+
+        self.values.write().unwrap().insert(param.name, value);
+        Ok(())
+    }
+
+    /// Set the parameters in `params` as `CONFIG SET` does.
+    ///
+    /// Either all the parameters are set, or none if one of them is unknown,
+    /// immutable or given an invalid value.
+    pub(crate) fn set_at_runtime(&self, params: &[(String, String)]) -> Result<(), String> {
+        for (name, value) in params {
+            let param = find(name).ok_or_else(|| {
+                format!(
+                    "Unknown option or number of arguments for CONFIG SET - '{}'",
+                    name
+                )
+            })?;
+            let failed = |msg: &str| {
+                format!(
+                    "CONFIG SET failed (possibly related to argument '{}') - {}",
+                    name, msg
+                )
+            };
+
+            if !param.mutable {
+                return Err(failed("can't set immutable config"));
+            }
+            if param.protected && self.get_as::<String>("enable-protected-configs") != "yes" {
+                return Err(failed("can't set protected config"));
+            }
+            validate(param, value).map_err(|err| failed(&err))?;
+            match param.name {
+                "dir" if !Path::new(value).is_dir() => {
+                    return Err(failed("No such file or directory"))
+                }
+                "dbfilename" if Path::new(value).file_name() != Some(value.as_ref()) => {
+                    return Err(failed("dbfilename can't be a path, just a filename"))
+                }
+                _ => {}
+            }
+        }
+
+        let mut values = self.values.write().unwrap();
+        for (name, value) in params {
+            values.insert(find(name).unwrap().name, value.clone());
+        }
+        Ok(())
+    }
+
+    /// Returns the parameters whose name matches the glob-style `pattern`,
+    /// along with their values.
+    pub(crate) fn matching(&self, pattern: &str) -> Vec<(&'static str, String)> {
+        let pattern = pattern.to_lowercase();
+        let values = self.values.read().unwrap();
+        values
+            .iter()
+            .filter(|(name, _)| glob_match(pattern.as_bytes(), name.as_bytes()))
+            .map(|(&name, value)| (name, value.clone()))
+            .collect()
+    }
+
+    /// Write the current configuration to the file it was loaded from.
+    ///
+    /// Comments and the order of the lines are kept. Lines of parameters are
+    /// updated with their current value, and parameters missing from the file
+    /// are appended if they differ from their default value.
+    pub(crate) fn rewrite(&self) -> crate::Result<()> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or("The server is running without a config file")?;
+        let contents = std::fs::read_to_string(path)?;
+        let values = self.values.read().unwrap();
+
+        let mut rewritten = String::new();
+        let mut written = vec![];
+        for line in contents.lines() {
+            match parse_line(line) {
+                Ok(Some((name, _))) => {
+                    let param = find(&name).unwrap();
+                    // 同じパラメータが複数行にある場合は、最初の行だけを残す
+                    if !written.contains(&param.name) {
+                        written.push(param.name);
+                        let _ =
+                            writeln!(rewritten, "{} {}", param.name, quote(&values[param.name]));
+                    }
+                }
+                _ => {
+                    rewritten.push_str(line);
+                    rewritten.push('\n');
+                }
+            }
+        }
+
+        for param in PARAMS {
+            let value = &values[param.name];
+            if !written.contains(&param.name) && value != param.default {
+                let _ = writeln!(rewritten, "{} {}", param.name, quote(value));
+            }
+        }
+
+        // 書き込みの途中で失敗しても元のファイルが壊れないように、一時ファイルに書いてから置き換える
+        let tmp = path.with_extension("rewrite.tmp");
+        std::fs::write(&tmp, rewritten)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    /// Configuration with every parameter set to its default value.
+    fn default() -> Config {
+        let values = PARAMS
+            .iter()
+            .map(|param| (param.name, param.default.to_string()))
+            .collect();
+
+        Config {
+            values: RwLock::new(values),
+            path: None,
+        }
+    }
+}
+
+fn find(name: &str) -> Option<&'static Param> {
+    PARAMS
+        .iter()
+        .find(|param| param.name.eq_ignore_ascii_case(name))
+}
+
+fn validate(param: &Param, value: &str) -> Result<(), String> {
+    match param.kind {
+        Kind::Integer(min, max) => match value.parse::<i64>() {
+            Ok(n) if (min..=max).contains(&n) => Ok(()),
+            Ok(_) => Err(format!(
+                "argument must be between {} and {} inclusive",
+                min, max
+            )),
+            Err(_) => Err("argument couldn't be parsed into an integer".to_string()),
+        },
+        Kind::Octal => match u32::from_str_radix(value, 8) {
+            Ok(n) if n <= 0o777 => Ok(()),
+            _ => Err("argument must be an octal number between 0 and 777".to_string()),
+        },
+        Kind::Enum(values) if values.contains(&value) => Ok(()),
+        Kind::Enum(values) => Err(format!(
+            "argument(s) must be one of the following: {}",
+            values.join(", ")
+        )),
+        Kind::String => Ok(()),
+        Kind::SavePoints => {
+            let numbers: Vec<_> = value.split_whitespace().collect();
+            if numbers.len() % 2 == 0 && numbers.iter().all(|n| n.parse::<u64>().is_ok()) {
+                Ok(())
+            } else {
+                Err("Invalid save parameters".to_string())
+            }
+        }
+    }
+}
+
+/// Parse a line of a config file into a parameter name and value.
+///
+/// Returns `None` for empty lines and comments.
+fn parse_line(line: &str) -> Result<Option<(String, String)>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let args = frame::split_inline_args(line.as_bytes()).map_err(|err| err.to_string())?;
+    let args: Vec<_> = args
+        .iter()
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    match &args[..] {
+        [name, values @ ..] if find(name).is_some() => Ok(Some((name.clone(), values.join(" ")))),
+        _ => Err("Bad directive or wrong number of arguments".to_string()),
+    }
+}
+
+/// Quote `value` if needed for `parse_line` to read it back.
+fn quote(value: &str) -> String {
+    if !value.is_empty()
+        && !value.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '\\')
+    {
+        return value.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::DEFAULT_SHARDS;
+    use crate::server::MAX_CLIENTS;
+
+    #[test]
+    fn defaults_match_the_server_constants() {
+        let config = Config::default();
+        assert_eq!(config.maxclients(), MAX_CLIENTS);
+        assert_eq!(config.get_as::<usize>("shards"), DEFAULT_SHARDS);
+    }
+
+    #[test]
+    fn protected_configs() {
+        let set = |config: &Config, name: &str, value: &str| {
+            config.set_at_runtime(&[(name.to_string(), value.to_string())])
+        };
+
+        let config = Config::default();
+        assert_eq!(
+            set(&config, "dir", "/tmp").unwrap_err(),
+            "CONFIG SET failed (possibly related to argument 'dir') - can't set protected config"
+        );
+
+        config
+            .set("enable-protected-configs", "yes".to_string())
+            .unwrap();
+        let dir = std::env::temp_dir().display().to_string();
+        set(&config, "dir", &dir).unwrap();
+        assert_eq!(config.get("dir").unwrap(), dir);
+        assert_eq!(
+            set(&config, "dir", "/no/such/dir").unwrap_err(),
+            "CONFIG SET failed (possibly related to argument 'dir') - No such file or directory"
+        );
+        assert_eq!(
+            set(&config, "dbfilename", "../dump.rdb").unwrap_err(),
+            "CONFIG SET failed (possibly related to argument 'dbfilename') - dbfilename can't be a path, just a filename"
+        );
+        set(&config, "dbfilename", "backup.rdb").unwrap();
+    }
+
+    #[test]
+    fn load_set_and_rewrite() {
+        let path = std::env::temp_dir().join(format!("mini-redis-{}.conf", std::process::id()));
+        std::fs::write(&path, "# limits\nmaxclients 100\n\nport 7000\nport 7001\n").unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.maxclients(), 100);
+        assert_eq!(config.get("port").unwrap(), "7001");
+        assert!(config.save_points().is_empty());
+
+        let set = |name: &str, value: &str| {
+            config.set_at_runtime(&[(name.to_string(), value.to_string())])
+        };
+        assert_eq!(
+            set("port", "7002").unwrap_err(),
+            "CONFIG SET failed (possibly related to argument 'port') - can't set immutable config"
+        );
+        assert_eq!(
+            set("maxclients", "many").unwrap_err(),
+            "CONFIG SET failed (possibly related to argument 'maxclients') - argument couldn't be parsed into an integer"
+        );
+        set("MAXCLIENTS", "200").unwrap();
+        set("requirepass", "two words").unwrap();
+        assert_eq!(
+            set("save", "60").unwrap_err(),
+            "CONFIG SET failed (possibly related to argument 'save') - Invalid save parameters"
+        );
+        set("save", "3600 1 60 100").unwrap();
+        assert_eq!(config.save_points(), [(3600, 1), (60, 100)]);
+
+        config.rewrite().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            contents,
+            "# limits\nmaxclients 200\n\nport 7001\nrequirepass \"two words\"\nsave \"3600 1 60 100\"\n"
+        );
+        assert_eq!(
+            Config::load(&path).unwrap().get("requirepass").unwrap(),
+            "two words"
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
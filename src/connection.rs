@@ -1,34 +1,57 @@
 use std::io::{self, Cursor};
 
+use crate::frame::{self, Error, Frame, Protocol};
+use crate::Result;
 use async_trait::async_trait;
 use bytes::{Buf, BytesMut};
-use mini_redis::{
-    frame::{Error, Frame},
-    Result,
-};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
 use tokio::net::TcpStream;
 
+/// A byte stream frames can be read from and written to, e.g. a `TcpStream`,
+/// a `UnixStream` or an in-memory `tokio::io::DuplexStream`.
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Stream for T {}
+
 #[async_trait]
-pub trait ConnectionTrait {
-    fn new(stream: TcpStream) -> Self;
+pub trait ConnectionTrait<S: Stream> {
+    fn new(stream: S) -> Self;
     async fn read_frame(&mut self) -> Result<Option<Frame>>;
     async fn write_frame(&mut self, frame: &Frame) -> Result<()>;
+
+    /// Send the frames written by `write_frame` that are still buffered.
+    async fn flush(&mut self) -> io::Result<()>;
     fn parse_frame(&mut self) -> Result<Option<Frame>>;
     async fn write_decimal(&mut self, val: u64) -> io::Result<()>;
+
+    /// Select the protocol version used by `write_frame`.
+    fn set_protocol(&mut self, protocol: Protocol);
 }
 
-pub struct Connection {
-    stream: BufWriter<TcpStream>,
+/// Selects the `ConnectionTrait` implementation used to serve clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ConnectionKind {
+    /// `connection::Connection`, backed by `BytesMut` and `BufWriter`
+    #[default]
+    Buffered,
+
+    /// `connection_raw::Connection`, backed by a manually managed `Vec<u8>`
+    Raw,
+}
+
+pub struct Connection<S = TcpStream> {
+    stream: BufWriter<S>,
     buffer: BytesMut,
+    protocol: Protocol,
 }
 
 #[async_trait]
-impl ConnectionTrait for Connection {
-    fn new(stream: TcpStream) -> Self {
+impl<S: Stream> ConnectionTrait<S> for Connection<S> {
+    fn new(stream: S) -> Self {
         Self {
             stream: BufWriter::new(stream),
             buffer: BytesMut::with_capacity(4096), // 4KB のキャパシティを持つバッファを確保する
+            protocol: Protocol::default(),
         }
     }
 
@@ -69,54 +92,51 @@ impl ConnectionTrait for Connection {
     /// バッファされた write を実装するため、BufWrite 構造体 を利用する。
     /// この構造体は AsyncWrite トレイトを実装する型 T によって初期化され、BufWriter 自身も AsyncWrite を実装しています。BufWriter に対して write が呼び出されると、内部の writer へと直接書き込むのではなく、バッファへと書き込みを行います。バッファがいっぱいになったら、コンテンツは内部の writer へと「流され」[1]、内部バッファのデータは消去されます。特定のケースにおいて、バッファをバイパスすることを可能にする最適化も存在しています。
     async fn write_frame(&mut self, frame: &Frame) -> Result<()> {
-        match frame {
-            Frame::Simple(val) => {
-                self.stream.write_u8(b'+').await?;
-                self.stream.write_all(val.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Error(val) => {
-                self.stream.write_u8(b'-').await?;
-                self.stream.write_all(val.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Integer(val) => {
-                self.stream.write_u8(b':').await?;
-                self.write_decimal(*val).await?;
-            }
-            Frame::Null => {
-                self.stream.write_all(b"$-1\r\n").await?;
-            }
-            Frame::Bulk(val) => {
-                let len = val.len();
-
-                self.stream.write_u8(b'$').await?;
-                self.write_decimal(len as u64).await?;
-                self.stream.write_all(val).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Array(_val) => unimplemented!(),
-        }
+        // フレームをバッファにエンコードしてから BufWriter に書き込む
+        // エンコードされたフレームは BufWriter に蓄えられ、flush() でまとめてソケットに書き込まれる
+        let mut encoded = BytesMut::new();
+        frame::encode(frame, self.protocol, &mut encoded);
+        self.stream.write_all(&encoded).await?;
 
         /*
-        最後に self.stream.flush().await を呼び出している。
+        write_frame() の中では flush() を呼び出さない。
         BufWriter は中間バッファに書き込みを蓄えるため、write を呼び出してもデータがソケットへと書き込まれることは保証されていない。
-        return する前にフレームがソケットへと書き込まれてほしいので、flush() を呼んでいる。
-        flush() を呼ぶことで、バッファの中で保留状態となっているデータがすべてソケットへと書き込まれる。
+        代わりに、flush() 関数を ConnectionTrait のメソッドとして提供している。
 
-        write_frame() 内で flush() を 呼び出さないという代替案がある。
-        代わりに、flush() 関数を Connection のメソッドとして提供する。
         こうすることで、呼び出し側が write バッファへと複数の小さいフレームを書き込んで、それからまとめてソケットへと書き込む、といったことができるようになる。
-        こうすると write システムコールが 1 回で済む。
-        しかし、このような実装は Connection API を複雑化させてしまう。Mini-Redis の目標の 1 つに「シンプルさ」というのがあるため、
-        ここでは fn write_frame() の中で flush().await を呼び出すという実装にすることにした。
+        例えば、クライアントが 1000 個のコマンドをパイプラインで送ってきた場合でも、応答を 1 つずつ flush すると write システムコールが 1000 回呼ばれるが、
+        まとめて flush すれば数回で済む。
+        その代わり、呼び出し側はフレームを書き込んだ後に flush() を呼び出す必要がある。
          */
-        let _ = self.stream.flush().await;
 
         Ok(())
     }
 
+    /// BufWriter に蓄えられているフレームをソケットに書き込む
+    async fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush().await
+    }
+
     fn parse_frame(&mut self) -> Result<Option<Frame>> {
+        // telnet や nc から手入力されたインラインコマンドは、コマンドの配列フレームに変換する
+        // 空行は読み捨てて、続くデータのパースを試みる
+        while Frame::is_inline(&self.buffer) {
+            let mut buf = Cursor::new(&self.buffer[..]);
+
+            match Frame::parse_inline(&mut buf) {
+                Ok(frame) => {
+                    let len = buf.position() as usize;
+                    self.buffer.advance(len);
+
+                    if frame != Frame::Array(vec![]) {
+                        return Ok(Some(frame));
+                    }
+                }
+                Err(Error::Incomplete) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
         // Buf 型を作る
         let mut buf = Cursor::new(&self.buffer[..]);
 
@@ -127,14 +147,12 @@ impl ConnectionTrait for Connection {
                 // フレームのバイト長を取得する
                 let len = buf.position() as usize;
 
-                // `parse` を呼び出すため、内部カーソルをリセットする
-                buf.set_position(0);
+                // バッファからフレーム分を切り出す
+                // 切り出した `Bytes` は read バッファとメモリを共有するので、Bulk の値はコピーされない
+                let data = self.buffer.split_to(len).freeze();
 
                 // フレームをパースする
-                let frame = Frame::parse(&mut buf)?;
-
-                // バッファからフレーム分を読み捨てる
-                self.buffer.advance(len);
+                let frame = Frame::parse(&mut Cursor::new(&data))?;
 
                 // 呼び出し側にフレームを返す
                 Ok(Some(frame))
@@ -151,8 +169,8 @@ impl ConnectionTrait for Connection {
     async fn write_decimal(&mut self, val: u64) -> io::Result<()> {
         use std::io::Write;
 
-        // Convert the value to a string
-        let mut buf = [0u8; 12];
+        // Convert the value to a string, `u64::MAX` has 20 digits
+        let mut buf = [0u8; 20];
         let mut buf = Cursor::new(&mut buf[..]);
         write!(&mut buf, "{}", val)?;
 
@@ -162,4 +180,97 @@ impl ConnectionTrait for Connection {
 
         Ok(())
     }
+
+    fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use tokio::io::{duplex, DuplexStream};
+
+    fn pair() -> (Connection<DuplexStream>, DuplexStream) {
+        let (client, server) = duplex(4096);
+        (Connection::new(server), client)
+    }
+
+    #[tokio::test]
+    async fn write_nested_and_null_arrays() {
+        let (mut connection, mut client) = pair();
+
+        let frame = Frame::Array(vec![
+            Frame::Bulk(Bytes::from("a")),
+            Frame::Array(vec![
+                Frame::Integer(-1),
+                Frame::Integer(i64::MIN),
+                Frame::Null,
+            ]),
+            Frame::NullArray,
+            Frame::Array(vec![]),
+        ]);
+        connection.write_frame(&frame).await.unwrap();
+        connection.flush().await.unwrap();
+        drop(connection);
+
+        let mut written = Vec::new();
+        client.read_to_end(&mut written).await.unwrap();
+        assert_eq!(
+            written,
+            b"*4\r\n$1\r\na\r\n*3\r\n:-1\r\n:-9223372036854775808\r\n$-1\r\n*-1\r\n*0\r\n"
+        );
+
+        // The encoded frame is read back as the same frame
+        let written = Bytes::from(written);
+        Frame::check(&mut Cursor::new(&written[..])).unwrap();
+        assert_eq!(Frame::parse(&mut Cursor::new(&written)).unwrap(), frame);
+    }
+
+    #[tokio::test]
+    async fn write_resp3_frames_in_both_protocols() {
+        let frame = Frame::Map(vec![
+            (Frame::Simple("d".into()), Frame::Double(1.5)),
+            (Frame::Boolean(true), Frame::Null),
+            (
+                Frame::BigNumber("-12345678901234567890".into()),
+                Frame::Verbatim("txt".into(), Bytes::from("hi")),
+            ),
+            (
+                Frame::Set(vec![Frame::Integer(1)]),
+                Frame::Push(vec![Frame::Attribute(
+                    vec![(Frame::Simple("ttl".into()), Frame::Integer(3600))],
+                    Box::new(Frame::Integer(2)),
+                )]),
+            ),
+        ]);
+
+        let mut written = Vec::new();
+        for protocol in [Protocol::Resp3, Protocol::Resp2] {
+            let (mut connection, mut client) = pair();
+            connection.set_protocol(protocol);
+            connection.write_frame(&frame).await.unwrap();
+            connection.flush().await.unwrap();
+            drop(connection);
+
+            let mut buf = Vec::new();
+            client.read_to_end(&mut buf).await.unwrap();
+            written.push(buf);
+        }
+
+        assert_eq!(
+            written[0],
+            b"%4\r\n+d\r\n,1.5\r\n#t\r\n_\r\n(-12345678901234567890\r\n=6\r\ntxt:hi\r\n~1\r\n:1\r\n>1\r\n|1\r\n+ttl\r\n:3600\r\n:2\r\n"
+        );
+        assert_eq!(
+            written[1],
+            b"*8\r\n+d\r\n$3\r\n1.5\r\n:1\r\n$-1\r\n$21\r\n-12345678901234567890\r\n$2\r\nhi\r\n*1\r\n:1\r\n*1\r\n:2\r\n"
+        );
+
+        // The RESP3 encoding is read back as the same frame
+        let written = Bytes::from(written.swap_remove(0));
+        Frame::check(&mut Cursor::new(&written[..])).unwrap();
+        assert_eq!(Frame::parse(&mut Cursor::new(&written)).unwrap(), frame);
+    }
 }
@@ -1,28 +1,38 @@
 use std::io::{self, Cursor};
 
+use crate::frame::{self, Error, Frame, Protocol};
+use crate::Result;
 use async_trait::async_trait;
-use mini_redis::{
-    frame::{Error, Frame},
-    Result,
-};
-use tokio::io::AsyncReadExt;
+use bytes::Bytes;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 
-use crate::connection::ConnectionTrait;
+use crate::connection::{ConnectionTrait, Stream};
 
-pub struct Connection {
-    stream: TcpStream,
+/// write バッファがこの大きさを超えたら、flush() を待たずにソケットに書き込む
+const WRITE_BUFFER_LIMIT: usize = 64 * 1024;
+
+/// `BytesMut` / `BufWriter` を使わず、`Vec<u8>` を手動で管理する `Connection` の実装
+///
+/// 読み取り用のバッファは `cursor` までが有効なデータで、それ以降はゼロ埋めされた空き領域である。
+/// 書き込み時は、フレームを `write_buffer` にエンコードしておき、`flush` で 1 回の `write_all` でソケットに書き込む。
+pub struct Connection<S = TcpStream> {
+    stream: S,
     buffer: Vec<u8>,
     cursor: usize,
+    write_buffer: Vec<u8>,
+    protocol: Protocol,
 }
 
 #[async_trait]
-impl ConnectionTrait for Connection {
-    fn new(stream: TcpStream) -> Self {
+impl<S: Stream> ConnectionTrait<S> for Connection<S> {
+    fn new(stream: S) -> Self {
         Self {
             stream,
             buffer: vec![0; 4096], // 4KB のキャパシティを持つバッファを確保する
             cursor: 0,
+            write_buffer: Vec::with_capacity(4096),
+            protocol: Protocol::default(),
         }
     }
 
@@ -60,13 +70,50 @@ impl ConnectionTrait for Connection {
     }
 
     /// コネクションにフレームを書き込む
+    /// フレーム全体を write バッファにエンコードする。ソケットへは flush() でまとめて書き込む
+    /// ただし、大きな応答が溜まり続けないように、write バッファが一定の大きさを超えたらその時点で書き込む
     async fn write_frame(&mut self, frame: &Frame) -> Result<()> {
-        unimplemented!()
+        frame::encode(frame, self.protocol, &mut self.write_buffer);
+
+        if self.write_buffer.len() >= WRITE_BUFFER_LIMIT {
+            self.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    /// write バッファに溜まっているフレームを 1 回の `write_all` でソケットに書き込む
+    async fn flush(&mut self) -> io::Result<()> {
+        self.stream.write_all(&self.write_buffer).await?;
+        self.write_buffer.clear();
+
+        Ok(())
     }
 
     fn parse_frame(&mut self) -> Result<Option<Frame>> {
+        // telnet や nc から手入力されたインラインコマンドは、コマンドの配列フレームに変換する
+        // 空行は読み捨てて、続くデータのパースを試みる
+        while Frame::is_inline(&self.buffer[..self.cursor]) {
+            let mut buf = Cursor::new(&self.buffer[..self.cursor]);
+
+            match Frame::parse_inline(&mut buf) {
+                Ok(frame) => {
+                    let len = buf.position() as usize;
+                    self.buffer.copy_within(len..self.cursor, 0);
+                    self.cursor -= len;
+
+                    if frame != Frame::Array(vec![]) {
+                        return Ok(Some(frame));
+                    }
+                }
+                Err(Error::Incomplete) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
         // Buf 型を作る
-        let mut buf = Cursor::new(&self.buffer[..]);
+        // バッファの `cursor` 以降はゼロ埋めされた空き領域なので、読み取り済みの範囲だけを対象にする
+        let mut buf = Cursor::new(&self.buffer[..self.cursor]);
 
         // フレーム全体が取得可能かどうかをチェックする
         match Frame::check(&mut buf) {
@@ -75,14 +122,16 @@ impl ConnectionTrait for Connection {
                 // フレームのバイト長を取得する
                 let len = buf.position() as usize;
 
-                // `parse` を呼び出すため、内部カーソルをリセットする
-                buf.set_position(0);
+                // `Vec<u8>` のバッファは再利用するので、フレーム分だけを `Bytes` にコピーしてからパースする
+                let data = Bytes::copy_from_slice(&self.buffer[..len]);
 
                 // フレームをパースする
-                let frame = Frame::parse(&mut buf)?;
+                let frame = Frame::parse(&mut Cursor::new(&data))?;
 
-                // バッファからフレーム分を読み捨てる vec
-                self.buffer.drain(..len);
+                // バッファからフレーム分を読み捨てる
+                // 残りのデータを先頭に詰めて、バッファの長さ (キャパシティ) は維持する
+                self.buffer.copy_within(len..self.cursor, 0);
+                self.cursor -= len;
 
                 // 呼び出し側にフレームを返す
                 Ok(Some(frame))
@@ -95,8 +144,46 @@ impl ConnectionTrait for Connection {
         }
     }
 
-    // Write a decimal frame to the stream
+    /// Write a decimal frame to the write buffer
+    ///
+    /// The buffer is sent to the socket by `flush`.
     async fn write_decimal(&mut self, val: u64) -> io::Result<()> {
-        unimplemented!()
+        frame::put_decimal(&mut self.write_buffer, val);
+        Ok(())
+    }
+
+    fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use tokio::io::duplex;
+
+    #[tokio::test]
+    async fn read_pipelined_frames_and_write_arrays() {
+        let (mut client, server) = duplex(4096);
+        let mut connection = Connection::new(server);
+
+        // Two frames in one write, the second one only partially
+        client.write_all(b"+first\r\n$6\r\nsec").await.unwrap();
+        let frame = connection.read_frame().await.unwrap().unwrap();
+        assert_eq!(frame, "first");
+
+        client.write_all(b"ond\r\n").await.unwrap();
+        let frame = connection.read_frame().await.unwrap().unwrap();
+        assert_eq!(frame, "second");
+
+        let frame = Frame::Array(vec![Frame::Bulk(Bytes::from("a")), Frame::Integer(-2)]);
+        connection.write_frame(&frame).await.unwrap();
+        connection.flush().await.unwrap();
+        drop(connection);
+
+        let mut written = Vec::new();
+        client.read_to_end(&mut written).await.unwrap();
+        assert_eq!(written, b"*2\r\n$1\r\na\r\n:-2\r\n");
     }
 }
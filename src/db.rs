@@ -0,0 +1,683 @@
+//! The default, in-memory, implementation of `Storage`.
+
+use crate::storage::{SetCondition, Storage};
+
+use bytes::Bytes;
+use tokio::sync::Notify;
+use tokio::time::{self, Instant};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+/// Default number of shards the keyspace is split into.
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Server state shared across all connections.
+///
+/// The keyspace is split into shards, chosen by key hash. Each shard contains
+/// a `HashMap` storing the key/value data and a `BTreeSet` tracking key
+/// deadlines, guarded by its own mutex. Commands touching different shards
+/// therefore do not contend on a single lock.
+///
+/// A `Db` instance is a handle to shared state. Cloning `Db` is shallow and
+/// only incurs an atomic ref count increment.
+///
+/// When a `Db` value is created, a background task is spawned. This task is
+/// used to expire values after the requested duration has elapsed. The task
+/// runs until all instances of `Db` are dropped, at which point the task
+/// terminates.
+#[derive(Debug, Clone)]
+pub struct Db {
+    /// Handle to shared state. The background task only holds a `Weak`
+    /// reference, so the state is dropped with the last `Db` handle.
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    /// The shards of the keyspace. Each shard is guarded by a mutex. This is a
+    /// `std::sync::Mutex` and not a Tokio mutex. This is because there are no
+    /// asynchronous operations being performed while holding the mutex.
+    /// Additionally, the critical sections are very small.
+    ///
+    /// When more than one shard must be locked at once, the shards are always
+    /// locked in ascending index order to avoid deadlocks.
+    shards: Box<[Mutex<State>]>,
+
+    /// Notifies the background task handling entry expiration. The background
+    /// task waits on this to be notified, then checks for expired values or
+    /// whether the shared state has been dropped.
+    background_task: Arc<Notify>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// The key-value data.
+    entries: HashMap<String, Entry>,
+
+    /// Tracks key TTLs.
+    ///
+    /// A `BTreeSet` is used to maintain expirations sorted by when they expire.
+    /// This allows the background task to iterate this map to find the value
+    /// expiring next.
+    ///
+    /// While highly unlikely, it is possible for more than one expiration to be
+    /// created for the same instant. Because of this, the `Instant` is
+    /// insufficient for the key. A unique key (`String`) is used to
+    /// break these ties.
+    expirations: BTreeSet<(Instant, String)>,
+}
+
+/// Entry in the key-value store
+#[derive(Debug)]
+struct Entry {
+    /// Stored data
+    data: Bytes,
+
+    /// Instant at which the entry expires and should be removed from the
+    /// database.
+    expires_at: Option<Instant>,
+}
+
+impl Db {
+    /// Create a new, empty, `Db` instance split into `shards` shards.
+    /// Allocates shared state and spawns a background task to manage key
+    /// expiration.
+    ///
+    /// Must be called from within a Tokio runtime.
+    ///
+    /// # Panics
+    ///
+    /// panics if `shards` is zero
+    pub fn new(shards: usize) -> Db {
+        assert!(shards > 0, "the keyspace needs at least one shard");
+
+        let shared = Arc::new(Shared {
+            shards: (0..shards)
+                .map(|_| {
+                    Mutex::new(State {
+                        entries: HashMap::new(),
+                        expirations: BTreeSet::new(),
+                    })
+                })
+                .collect(),
+            background_task: Arc::new(Notify::new()),
+        });
+
+        // Start the background task.
+        tokio::spawn(purge_expired_tasks(
+            Arc::downgrade(&shared),
+            shared.background_task.clone(),
+        ));
+
+        Db { shared }
+    }
+}
+
+impl Default for Db {
+    /// Create a `Db` split into `DEFAULT_SHARDS` shards.
+    fn default() -> Db {
+        Db::new(DEFAULT_SHARDS)
+    }
+}
+
+impl Storage for Db {
+    /// Get the value associated with a key.
+    ///
+    /// Returns `None` if there is no value associated with the key, or if the
+    /// key has expired. Expired keys found here are removed immediately
+    /// instead of waiting for the background task.
+    fn get(&self, key: &str) -> Option<Bytes> {
+        let mut state = self.shared.lock(key);
+        state.live_entry(key).map(|entry| entry.data.clone())
+    }
+
+    /// Set the value associated with a key together with an optional
+    /// expiration instant.
+    ///
+    /// The value is only stored when `condition` holds. Setting a key removes
+    /// any deadline previously associated with it. Returns `true` if the value
+    /// was stored.
+    fn set(
+        &self,
+        key: String,
+        value: Bytes,
+        expires_at: Option<Instant>,
+        condition: SetCondition,
+    ) -> bool {
+        let mut state = self.shared.lock(&key);
+
+        let exists = state.live_entry(&key).is_some();
+        match condition {
+            SetCondition::IfNotExists if exists => return false,
+            SetCondition::IfExists if !exists => return false,
+            _ => {}
+        }
+
+        // If there was a value previously associated with the key **and** it
+        // had an expiration time, the associated entry in the `expirations`
+        // map must also be removed. This avoids leaking data.
+        state.remove_entry(&key);
+
+        // Only notify the worker task if the newly inserted expiration is the
+        // **next** key to evict. In this case, the worker needs to be woken up
+        // to update its state.
+        let notify = state.schedule(&key, expires_at);
+
+        // Insert the entry into the `HashMap`.
+        state.entries.insert(
+            key,
+            Entry {
+                data: value,
+                expires_at,
+            },
+        );
+
+        // Release the mutex before notifying the background task. This helps
+        // reduce contention by avoiding the background task waking up only to
+        // be unable to acquire the mutex due to this function still holding it.
+        drop(state);
+
+        if notify {
+            // Finally, only notify the background task if it needs to update
+            // its state to reflect a new expiration.
+            self.shared.background_task.notify_one();
+        }
+
+        true
+    }
+
+    fn expire(&self, key: &str, expires_at: Option<Instant>) -> bool {
+        let mut state = self.shared.lock(key);
+
+        let prev = match state.live_entry(key) {
+            Some(entry) => std::mem::replace(&mut entry.expires_at, expires_at),
+            None => return false,
+        };
+
+        if let Some(prev) = prev {
+            state.expirations.remove(&(prev, key.to_string()));
+        } else if expires_at.is_none() {
+            // Nothing to persist
+            return false;
+        }
+        let notify = state.schedule(key, expires_at);
+
+        drop(state);
+
+        if notify {
+            self.shared.background_task.notify_one();
+        }
+
+        true
+    }
+
+    /// Get the deadline of a key.
+    ///
+    /// Returns `None` if the key does not exist and `Some(None)` if the key
+    /// exists but has no associated deadline.
+    fn expires_at(&self, key: &str) -> Option<Option<Instant>> {
+        let mut state = self.shared.lock(key);
+        state.live_entry(key).map(|entry| entry.expires_at)
+    }
+
+    /// Get the value associated with a key together with its deadline, both
+    /// read while the shard is locked.
+    fn get_entry(&self, key: &str) -> Option<(Bytes, Option<Instant>)> {
+        let mut state = self.shared.lock(key);
+        state
+            .live_entry(key)
+            .map(|entry| (entry.data.clone(), entry.expires_at))
+    }
+
+    /// Get the values associated with several keys at once.
+    ///
+    /// All shards holding the keys are locked for the duration of the lookup,
+    /// so the values form a consistent snapshot.
+    fn get_many(&self, keys: &[String]) -> Vec<Option<Bytes>> {
+        let mut shards = self.shared.lock_many(keys.iter().map(String::as_str));
+
+        keys.iter()
+            .map(|key| {
+                shards
+                    .state(key)
+                    .live_entry(key)
+                    .map(|entry| entry.data.clone())
+            })
+            .collect()
+    }
+
+    /// All shards holding the keys are locked while the keys are removed.
+    fn del(&self, keys: &[String]) -> usize {
+        let mut shards = self.shared.lock_many(keys.iter().map(String::as_str));
+
+        keys.iter()
+            .filter(|key| {
+                let state = shards.state(key);
+                state.live_entry(key).is_some() && state.remove_entry(key).is_some()
+            })
+            .count()
+    }
+
+    /// The shard holding the key is locked while the key is compared and
+    /// removed.
+    fn del_if_unchanged(&self, key: &str, value: &Bytes, expires_at: Option<Instant>) -> bool {
+        let mut state = self.shared.lock(key);
+
+        match state.live_entry(key) {
+            Some(entry) if entry.data == *value && entry.expires_at == expires_at => {
+                state.remove_entry(key).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    /// Rename `key` to `new_key`, keeping its value and deadline. Any value
+    /// held by `new_key` is overwritten.
+    ///
+    /// Returns `false` if `key` does not exist.
+    fn rename(&self, key: &str, new_key: &str) -> bool {
+        let mut shards = self.shared.lock_many([key, new_key].into_iter());
+
+        let entry = match shards.state(key).live_entry(key) {
+            Some(_) => shards.state(key).remove_entry(key).unwrap(),
+            None => return false,
+        };
+
+        let state = shards.state(new_key);
+        state.remove_entry(new_key);
+        let notify = state.schedule(new_key, entry.expires_at);
+        state.entries.insert(new_key.to_string(), entry);
+
+        drop(shards);
+
+        if notify {
+            self.shared.background_task.notify_one();
+        }
+
+        true
+    }
+
+    /// Every shard is locked while the entries are copied, so the snapshot
+    /// reflects the keyspace at a single point in time. Values are reference
+    /// counted, so the locks are only held while the keys are cloned.
+    fn snapshot(&self) -> Vec<(String, Bytes, Option<Instant>)> {
+        let shards: Vec<_> = self
+            .shared
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap())
+            .collect();
+        let now = Instant::now();
+
+        shards
+            .iter()
+            .flat_map(|state| state.entries.iter())
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| (key.clone(), entry.data.clone(), entry.expires_at))
+            .collect()
+    }
+
+    /// Keys are visited shard by shard. The cursor holds the shard index in its
+    /// upper 32 bits and the position within the shard in its lower 32 bits.
+    /// As positions index into the shard's `HashMap`, keys of a shard that is
+    /// modified during the iteration may be skipped or returned twice.
+    fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<String>) {
+        let mut shard = (cursor >> 32) as usize;
+        let mut position = (cursor & u32::MAX as u64) as usize;
+        let mut keys = Vec::with_capacity(count);
+
+        while shard < self.shared.shards.len() {
+            let state = self.shared.shards[shard].lock().unwrap();
+            let now = Instant::now();
+
+            let remaining = count.saturating_sub(keys.len()).max(1);
+            for (key, entry) in state.entries.iter().skip(position).take(remaining) {
+                position += 1;
+                if !entry.is_expired(now) {
+                    keys.push(key.clone());
+                }
+            }
+
+            if position >= state.entries.len() {
+                shard += 1;
+                position = 0;
+            }
+
+            if keys.len() >= count {
+                break;
+            }
+        }
+
+        if shard >= self.shared.shards.len() {
+            (0, keys)
+        } else {
+            (((shard as u64) << 32) | position as u64, keys)
+        }
+    }
+}
+
+impl Shared {
+    /// Returns the index of the shard holding `key`.
+    fn shard_index(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// Lock the shard holding `key`.
+    fn lock(&self, key: &str) -> MutexGuard<'_, State> {
+        self.shards[self.shard_index(key)].lock().unwrap()
+    }
+
+    /// Lock every shard holding one of `keys`.
+    ///
+    /// Shards are locked in ascending index order, and each shard only once,
+    /// so concurrent callers can never wait on each other in a cycle.
+    fn lock_many<'a>(&self, keys: impl Iterator<Item = &'a str>) -> LockedShards<'_> {
+        let mut indices: Vec<usize> = keys.map(|key| self.shard_index(key)).collect();
+        indices.sort_unstable();
+        indices.dedup();
+
+        LockedShards {
+            shared: self,
+            guards: indices
+                .into_iter()
+                .map(|index| (index, self.shards[index].lock().unwrap()))
+                .collect(),
+        }
+    }
+
+    /// Purge all expired keys and return the `Instant` at which the **next**
+    /// key will expire. The background task will sleep until this instant.
+    fn purge_expired_keys(&self) -> Option<Instant> {
+        // Find all keys scheduled to expire **before** now.
+        let now = Instant::now();
+
+        // Shards are purged one at a time, so the background task never holds
+        // more than one lock.
+        self.shards
+            .iter()
+            .filter_map(|shard| shard.lock().unwrap().purge_expired_keys(now))
+            .min()
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        // The last `Db` handle is gone. Wake the background task up so it
+        // notices and exits.
+        self.background_task.notify_one();
+    }
+}
+
+/// Guards of the shards locked by `Shared::lock_many`, sorted by shard index.
+struct LockedShards<'a> {
+    shared: &'a Shared,
+    guards: Vec<(usize, MutexGuard<'a, State>)>,
+}
+
+impl LockedShards<'_> {
+    /// Returns the state of the shard holding `key`.
+    ///
+    /// # Panics
+    ///
+    /// panics if the shard holding `key` was not locked
+    fn state(&mut self, key: &str) -> &mut State {
+        let index = self.shared.shard_index(key);
+        let pos = self
+            .guards
+            .binary_search_by_key(&index, |(index, _)| *index)
+            .expect("shard not locked");
+        &mut self.guards[pos].1
+    }
+}
+
+impl State {
+    /// Purge the keys of this shard that expired at `now` and return the
+    /// `Instant` at which the next key will expire.
+    fn purge_expired_keys(&mut self, now: Instant) -> Option<Instant> {
+        while let Some(&(when, ref key)) = self.expirations.iter().next() {
+            if when > now {
+                // Done purging, `when` is the instant at which the next key
+                // expires. The worker task will wait until this instant.
+                return Some(when);
+            }
+
+            // The key expired, remove it
+            self.entries.remove(key);
+            self.expirations.remove(&(when, key.clone()));
+        }
+
+        None
+    }
+
+    fn next_expiration(&self) -> Option<Instant> {
+        self.expirations
+            .iter()
+            .next()
+            .map(|expiration| expiration.0)
+    }
+
+    /// Look up a key, treating it as missing if its deadline has passed.
+    ///
+    /// Expired entries are removed on access so that reads never observe a
+    /// value the background task has not purged yet.
+    fn live_entry(&mut self, key: &str) -> Option<&mut Entry> {
+        if self.entries.get(key)?.is_expired(Instant::now()) {
+            self.remove_entry(key);
+            return None;
+        }
+
+        self.entries.get_mut(key)
+    }
+
+    /// Remove an entry together with its tracked deadline, if any.
+    fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+
+        if let Some(when) = entry.expires_at {
+            self.expirations.remove(&(when, key.to_string()));
+        }
+
+        Some(entry)
+    }
+
+    /// Track the deadline of `key` in `expirations`.
+    ///
+    /// Returns `true` if the background task must be woken up because the new
+    /// deadline is earlier than every deadline it currently knows about.
+    fn schedule(&mut self, key: &str, expires_at: Option<Instant>) -> bool {
+        let when = match expires_at {
+            Some(when) => when,
+            None => return false,
+        };
+
+        let notify = self
+            .next_expiration()
+            .map(|expiration| expiration > when)
+            .unwrap_or(true);
+
+        self.expirations.insert((when, key.to_string()));
+
+        notify
+    }
+}
+
+impl Entry {
+    /// Returns `true` if the entry's deadline is at or before `now`.
+    fn is_expired(&self, now: Instant) -> bool {
+        matches!(self.expires_at, Some(when) if when <= now)
+    }
+}
+
+/// Routine executed by the background task.
+///
+/// Wait to be notified. On notification, purge any expired keys from the shared
+/// state. If the shared state has been dropped, terminate the task.
+async fn purge_expired_tasks(shared: Weak<Shared>, background_task: Arc<Notify>) {
+    // Purge all keys that are expired. `purge_expired_keys` returns the instant
+    // at which the **next** key will expire. The worker should wait until the
+    // instant has passed then purge again.
+    //
+    // The strong reference is released before waiting, so that dropping the
+    // last `Db` handle drops the shared state and ends the loop.
+    while let Some(next) = shared.upgrade().map(|shared| shared.purge_expired_keys()) {
+        if let Some(when) = next {
+            // Wait until the next key expires **or** until the background task
+            // is notified. If the task is notified, then it must reload its
+            // state as new keys have been set to expire early. This is done by
+            // looping.
+            tokio::select! {
+                _ = time::sleep_until(when) => {}
+                _ = background_task.notified() => {}
+            }
+        } else {
+            // There are no keys expiring in the future. Wait until the task is
+            // notified.
+            background_task.notified().await;
+        }
+    }
+
+    tracing::debug!("Purge background task shut down")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn expired_keys_are_not_visible() {
+        let db = Db::new(DEFAULT_SHARDS);
+        let when = Instant::now() + Duration::from_millis(20);
+
+        db.set(
+            "a".to_string(),
+            Bytes::from_static(b"1"),
+            Some(when),
+            SetCondition::Always,
+        );
+        assert_eq!(db.get("a"), Some(Bytes::from_static(b"1")));
+        assert_eq!(db.expires_at("a"), Some(Some(when)));
+
+        time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(db.get("a"), None);
+        assert_eq!(db.expires_at("a"), None);
+    }
+
+    #[tokio::test]
+    async fn set_conditions_and_persist() {
+        let db = Db::new(DEFAULT_SHARDS);
+        let when = Instant::now() + Duration::from_secs(60);
+
+        assert!(!db.set(
+            "a".to_string(),
+            Bytes::from_static(b"1"),
+            None,
+            SetCondition::IfExists
+        ));
+        assert!(db.set(
+            "a".to_string(),
+            Bytes::from_static(b"1"),
+            Some(when),
+            SetCondition::IfNotExists
+        ));
+        assert!(!db.set(
+            "a".to_string(),
+            Bytes::from_static(b"2"),
+            None,
+            SetCondition::IfNotExists
+        ));
+        assert_eq!(db.get("a"), Some(Bytes::from_static(b"1")));
+
+        assert!(db.expire("a", None));
+        assert!(!db.expire("a", None));
+        assert_eq!(db.expires_at("a"), Some(None));
+
+        // Overwriting a key drops its deadline
+        assert!(db.expire("a", Some(when)));
+        assert!(db.set(
+            "a".to_string(),
+            Bytes::from_static(b"3"),
+            None,
+            SetCondition::IfExists
+        ));
+        assert_eq!(db.expires_at("a"), Some(None));
+        assert!(db.shared.lock("a").expirations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn del_if_unchanged_compares_value_and_deadline() {
+        let db = Db::new(DEFAULT_SHARDS);
+        let when = Instant::now() + Duration::from_secs(60);
+        let value = Bytes::from_static(b"1");
+        db.set(
+            "a".to_string(),
+            value.clone(),
+            Some(when),
+            SetCondition::Always,
+        );
+        assert_eq!(db.get_entry("a"), Some((value.clone(), Some(when))));
+
+        assert!(!db.del_if_unchanged("a", &Bytes::from_static(b"2"), Some(when)));
+        assert!(!db.del_if_unchanged("a", &value, None));
+        assert!(db.del_if_unchanged("a", &value, Some(when)));
+        assert_eq!(db.get_entry("a"), None);
+        assert!(db.shared.lock("a").expirations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn multi_key_operations_across_shards() {
+        let db = Db::new(DEFAULT_SHARDS);
+        let keys: Vec<String> = (0..32).map(|i| format!("key:{}", i)).collect();
+        for key in &keys[..16] {
+            db.set(
+                key.clone(),
+                Bytes::from(key.clone()),
+                None,
+                SetCondition::Always,
+            );
+        }
+
+        let values = db.get_many(&keys);
+        assert_eq!(values.iter().filter(|value| value.is_some()).count(), 16);
+        assert_eq!(values[3], Some(Bytes::from_static(b"key:3")));
+        assert_eq!(db.snapshot().len(), 16);
+
+        let when = Instant::now() + Duration::from_secs(60);
+        assert!(db.expire("key:0", Some(when)));
+        assert!(db.rename("key:0", "key:31"));
+        assert!(!db.rename("key:0", "key:31"));
+        assert_eq!(db.expires_at("key:31"), Some(Some(when)));
+
+        assert_eq!(db.del(&keys), 16);
+        assert!(db.get_many(&keys).iter().all(Option::is_none));
+    }
+
+    #[tokio::test]
+    async fn concurrent_renames_do_not_deadlock() {
+        let db = Db::new(DEFAULT_SHARDS);
+        db.set("a".to_string(), Bytes::new(), None, SetCondition::Always);
+        db.set("b".to_string(), Bytes::new(), None, SetCondition::Always);
+
+        let threads: Vec<_> = [("a", "b"), ("b", "a")]
+            .into_iter()
+            .map(|(from, to)| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        db.rename(from, to);
+                        db.get_many(&[to.to_string(), from.to_string()]);
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+}
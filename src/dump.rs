@@ -0,0 +1,667 @@
+//! Export of the keyspace to JSON Lines or CSV, and import of such an export.
+//!
+//! Each key is exported as one JSON object per line, or one CSV record, with
+//! the following fields:
+//!
+//! * `key`
+//! * `type`: `string`, `list`, `set`, `zset` or `hash`
+//! * `ttl`: remaining time to live in milliseconds, empty if the key does not
+//!   expire
+//! * `encoding`: `base64` if the key or one of the strings of the value is not
+//!   valid UTF-8, in which case all of them are base64 encoded
+//! * `value`: a string, an array of strings for lists and sets, an array of
+//!   `[member, score]` pairs for sorted sets and an object for hashes. In CSV,
+//!   values other than strings are written as JSON.
+//!
+//! Keys are sorted, as well as the members of sets and the fields of hashes,
+//! so that two exports of the same keyspace are identical and can be diffed.
+
+use crate::connection::{Connection, ConnectionTrait};
+use crate::frame::Frame;
+pub use crate::rdb::Value;
+use crate::{clock, rdb};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use tokio::net::TcpStream;
+
+/// Number of keys whose commands are sent before reading the replies.
+const PIPELINE: usize = 1000;
+
+/// Prefix of the keys lists, sets, sorted sets and hashes are written to
+/// before replacing the key of the entry.
+const TMP_PREFIX: &[u8] = b"my-mini-redis-dump:";
+
+/// Output format of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Format {
+    /// One JSON object per line
+    #[default]
+    Json,
+    /// Comma-separated values, with a header
+    Csv,
+}
+
+/// Key of the keyspace, along with its value and time to live.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub key: Bytes,
+    pub value: Value,
+    /// remaining time to live in milliseconds
+    pub ttl: Option<u64>,
+}
+
+/// Entry as written in JSON Lines.
+#[derive(Serialize, Deserialize)]
+struct JsonEntry {
+    key: String,
+    #[serde(rename = "type")]
+    ty: String,
+    ttl: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encoding: Option<String>,
+    value: serde_json::Value,
+}
+
+/// Entry as written in CSV, where the value is a single field.
+#[derive(Serialize, Deserialize)]
+struct CsvEntry {
+    key: String,
+    #[serde(rename = "type")]
+    ty: String,
+    ttl: Option<u64>,
+    encoding: Option<String>,
+    value: String,
+}
+
+/// Write `entries` to `out` in `format`.
+pub fn write(out: impl Write, format: Format, entries: &[Entry]) -> crate::Result<()> {
+    match format {
+        Format::Json => {
+            let mut out = io::BufWriter::new(out);
+            for entry in entries {
+                serde_json::to_writer(&mut out, &JsonEntry::from(entry))?;
+                out.write_all(b"\n")?;
+            }
+            out.flush()?;
+        }
+        Format::Csv => {
+            let mut out = csv::Writer::from_writer(out);
+            for entry in entries {
+                let entry = JsonEntry::from(entry);
+                let value = match entry.value {
+                    serde_json::Value::String(s) => s,
+                    value => value.to_string(),
+                };
+                out.serialize(CsvEntry {
+                    key: entry.key,
+                    ty: entry.ty,
+                    ttl: entry.ttl,
+                    encoding: entry.encoding,
+                    value,
+                })?;
+            }
+            out.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Read the entries written by `write` in `format`.
+pub fn read(input: impl BufRead, format: Format) -> crate::Result<Vec<Entry>> {
+    let mut entries = vec![];
+    match format {
+        Format::Json => {
+            for (i, line) in input.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let entry = serde_json::from_str::<JsonEntry>(&line)
+                    .map_err(|err| err.to_string())
+                    .and_then(Entry::try_from)
+                    .map_err(|err| format!("line {}: {}", i + 1, err))?;
+                entries.push(entry);
+            }
+        }
+        Format::Csv => {
+            for (i, record) in csv::Reader::from_reader(input).deserialize().enumerate() {
+                let entry: CsvEntry = record?;
+                let value = match entry.ty.as_str() {
+                    "string" => serde_json::Value::String(entry.value),
+                    _ => serde_json::from_str(&entry.value)
+                        .map_err(|err| format!("record {}: {}", i + 1, err))?,
+                };
+                let entry = Entry::try_from(JsonEntry {
+                    key: entry.key,
+                    ty: entry.ty,
+                    ttl: entry.ttl,
+                    encoding: entry.encoding,
+                    value,
+                })
+                .map_err(|err| format!("record {}: {}", i + 1, err))?;
+                entries.push(entry);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Read the entries of the RDB file at `path`, e.g. written by `SAVE` or by
+/// Redis, skipping the keys whose deadline has passed.
+pub fn read_snapshot(path: &Path) -> crate::Result<Vec<Entry>> {
+    let data = std::fs::read(path)?;
+    let records = rdb::decode(&data).map_err(|err| format!("{}: {}", path.display(), err))?;
+
+    let now = clock::unix_time_ms();
+    let mut entries: Vec<_> = records
+        .into_iter()
+        .filter_map(|record| {
+            let ttl = match record.expires_at {
+                Some(expires_at) if expires_at <= now => return None,
+                Some(expires_at) => Some(expires_at - now),
+                None => None,
+            };
+            Some(Entry {
+                key: record.key,
+                value: record.value,
+                ttl,
+            })
+        })
+        .collect();
+    sort(&mut entries);
+    Ok(entries)
+}
+
+/// Read all the keys of the server at `addr`, which may also be a Redis
+/// server, with `SCAN`.
+///
+/// Keys of types other than those of `Value`, e.g. streams, are skipped with
+/// a warning, as well as keys deleted while they are read.
+pub async fn fetch(addr: &str) -> crate::Result<Vec<Entry>> {
+    let mut client = Client::connect(addr).await?;
+
+    let mut entries = vec![];
+    let mut cursor = Bytes::from("0");
+    loop {
+        let reply = client
+            .call(&[&b"scan"[..], &cursor, b"count", b"1000"])
+            .await?;
+        let (next, keys) = match reply {
+            Frame::Array(mut reply) if reply.len() == 2 => {
+                let keys = reply.pop().unwrap();
+                (bulk(reply.pop().unwrap())?, strings(keys)?)
+            }
+            frame => return Err(format!("unexpected reply to SCAN: {:?}", frame).into()),
+        };
+
+        for key in keys {
+            if let Some(entry) = client.fetch(key).await? {
+                entries.push(entry);
+            }
+        }
+
+        if next == "0" {
+            break;
+        }
+        cursor = next;
+    }
+
+    sort(&mut entries);
+    Ok(entries)
+}
+
+/// Write `entries` to the server at `addr`, replacing the keys of the same
+/// name, and return the number of keys written.
+///
+/// Strings are written with `SET`, the other types with `RPUSH`, `SADD`,
+/// `ZADD` or `HSET` to a temporary key which is then renamed to the key, so
+/// that the key is kept if the server rejects the write, e.g. because it does
+/// not support the type. Such keys are skipped with a warning, as well as keys
+/// with a time to live of 0, which have already expired.
+pub async fn replay(addr: &str, entries: &[Entry]) -> crate::Result<usize> {
+    let mut client = Client::connect(addr).await?;
+
+    let (expired, entries): (Vec<_>, Vec<_>) =
+        entries.iter().partition(|entry| entry.ttl == Some(0));
+    for entry in expired {
+        tracing::warn!("Skipped key {:?}: already expired", entry.key);
+    }
+
+    let mut written = 0;
+    for batch in entries.chunks(PIPELINE) {
+        // キーごとのコマンドをまとめて送ってから、応答を順に読む
+        let commands: Vec<_> = batch.iter().map(|entry| commands(entry)).collect();
+        for frame in commands.iter().flatten() {
+            client.connection.write_frame(frame).await?;
+        }
+        client.connection.flush().await?;
+
+        for (entry, commands) in batch.iter().zip(&commands) {
+            let mut error = None;
+            for _ in commands {
+                if let Frame::Error(err) = client.read().await? {
+                    error.get_or_insert(err);
+                }
+            }
+            match error {
+                Some(err) => tracing::warn!("Skipped key {:?}: {}", entry.key, err),
+                None => written += 1,
+            }
+        }
+    }
+
+    Ok(written)
+}
+
+/// Sort `entries` by key, and the members of sets and fields of hashes.
+fn sort(entries: &mut [Entry]) {
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    for entry in entries {
+        match &mut entry.value {
+            Value::Set(members) => members.sort(),
+            Value::Hash(fields) => fields.sort(),
+            _ => {}
+        }
+    }
+}
+
+/// Commands writing `entry`.
+fn commands(entry: &Entry) -> Vec<Frame> {
+    let command = |name: &str, key: &Bytes, args: Vec<Bytes>| {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from(name.to_string()));
+        frame.push_bulk(key.clone());
+        for arg in args {
+            frame.push_bulk(arg);
+        }
+        frame
+    };
+    let key = &entry.key;
+    let ttl = entry.ttl.map(|ttl| Bytes::from(ttl.to_string()));
+
+    let (name, args) = match &entry.value {
+        Value::String(value) => {
+            let mut args = vec![value.clone()];
+            if let Some(ttl) = ttl {
+                args.extend([Bytes::from("px"), ttl]);
+            }
+            return vec![command("set", key, args)];
+        }
+        Value::List(items) => ("rpush", items.clone()),
+        Value::Set(members) => ("sadd", members.clone()),
+        Value::SortedSet(members) => (
+            "zadd",
+            members
+                .iter()
+                .flat_map(|(member, score)| [Bytes::from(score.to_string()), member.clone()])
+                .collect(),
+        ),
+        Value::Hash(fields) => (
+            "hset",
+            fields
+                .iter()
+                .flat_map(|(field, value)| [field.clone(), value.clone()])
+                .collect(),
+        ),
+    };
+
+    // 空の集合型は Redis に存在しないので、キーを削除するだけにする
+    if args.is_empty() {
+        return vec![command("del", key, vec![])];
+    }
+
+    // 型に対応していないサーバーでキーを消してしまわないように、一時キーに書き込んでから
+    // RENAME で置き換える。書き込みに失敗した場合は一時キーが存在しないので、RENAME も
+    // 失敗して元のキーは残る
+    let tmp = Bytes::from([TMP_PREFIX, &key[..]].concat());
+    let mut commands = vec![command("del", &tmp, vec![]), command(name, &tmp, args)];
+    if let Some(ttl) = ttl {
+        commands.push(command("pexpire", &tmp, vec![ttl]));
+    }
+    commands.push(command("rename", &tmp, vec![key.clone()]));
+    commands
+}
+
+impl From<&Entry> for JsonEntry {
+    fn from(entry: &Entry) -> JsonEntry {
+        let base64 = !strings_of(entry).all(|s| std::str::from_utf8(s).is_ok());
+        let text = |s: &Bytes| match base64 {
+            true => BASE64.encode(s),
+            false => String::from_utf8_lossy(s).into_owned(),
+        };
+
+        let value = match &entry.value {
+            Value::String(value) => json!(text(value)),
+            Value::List(items) | Value::Set(items) => {
+                json!(items.iter().map(text).collect::<Vec<_>>())
+            }
+            Value::SortedSet(members) => members
+                .iter()
+                .map(|(member, score)| {
+                    // JSON の数値は無限大を表せないので、文字列で書く
+                    let score = match score.is_finite() {
+                        true => json!(score),
+                        false => json!(score.to_string()),
+                    };
+                    json!([text(member), score])
+                })
+                .collect(),
+            Value::Hash(fields) => serde_json::Value::Object(
+                fields
+                    .iter()
+                    .map(|(field, value)| (text(field), json!(text(value))))
+                    .collect(),
+            ),
+        };
+
+        JsonEntry {
+            key: text(&entry.key),
+            ty: entry.value.type_name().to_string(),
+            ttl: entry.ttl,
+            encoding: base64.then(|| "base64".to_string()),
+            value,
+        }
+    }
+}
+
+impl TryFrom<JsonEntry> for Entry {
+    type Error = String;
+
+    fn try_from(entry: JsonEntry) -> Result<Entry, String> {
+        let base64 = match entry.encoding.as_deref() {
+            None | Some("") => false,
+            Some("base64") => true,
+            Some(encoding) => return Err(format!("unknown encoding {:?}", encoding)),
+        };
+        let bytes = |s: &str| match base64 {
+            true => BASE64
+                .decode(s)
+                .map(Bytes::from)
+                .map_err(|err| format!("invalid base64 {:?}: {}", s, err)),
+            false => Ok(Bytes::from(s.to_string())),
+        };
+        let string = |value: &serde_json::Value| match value.as_str() {
+            Some(s) => bytes(s),
+            None => Err(format!("expected a string, got {}", value)),
+        };
+        let array = |value: &serde_json::Value| match value.as_array() {
+            Some(items) => Ok(items.clone()),
+            None => Err(format!("expected an array, got {}", value)),
+        };
+
+        let value = match entry.ty.as_str() {
+            "string" => Value::String(string(&entry.value)?),
+            "list" => Value::List(
+                array(&entry.value)?
+                    .iter()
+                    .map(string)
+                    .collect::<Result<_, _>>()?,
+            ),
+            "set" => Value::Set(
+                array(&entry.value)?
+                    .iter()
+                    .map(string)
+                    .collect::<Result<_, _>>()?,
+            ),
+            "zset" => Value::SortedSet(
+                array(&entry.value)?
+                    .iter()
+                    .map(|pair| match array(pair)?.as_slice() {
+                        [member, score] => Ok((string(member)?, score_of(score)?)),
+                        _ => Err(format!("expected [member, score], got {}", pair)),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            "hash" => match &entry.value {
+                serde_json::Value::Object(fields) => Value::Hash(
+                    fields
+                        .iter()
+                        .map(|(field, value)| Ok((bytes(field)?, string(value)?)))
+                        .collect::<Result<_, String>>()?,
+                ),
+                value => return Err(format!("expected an object, got {}", value)),
+            },
+            ty => return Err(format!("unknown type {:?}", ty)),
+        };
+
+        Ok(Entry {
+            key: bytes(&entry.key)?,
+            value,
+            ttl: entry.ttl,
+        })
+    }
+}
+
+/// Strings held by `entry`, including its key.
+fn strings_of(entry: &Entry) -> impl Iterator<Item = &Bytes> {
+    let strings: Vec<&Bytes> = match &entry.value {
+        Value::String(value) => vec![value],
+        Value::List(items) | Value::Set(items) => items.iter().collect(),
+        Value::SortedSet(members) => members.iter().map(|(member, _)| member).collect(),
+        Value::Hash(fields) => fields.iter().flat_map(|(f, v)| [f, v]).collect(),
+    };
+    std::iter::once(&entry.key).chain(strings)
+}
+
+fn score_of(value: &serde_json::Value) -> Result<f64, String> {
+    match value {
+        serde_json::Value::Number(n) => n.as_f64(),
+        serde_json::Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| format!("invalid score {}", value))
+}
+
+/// Connection to a server, sending commands one at a time.
+struct Client {
+    connection: Connection,
+}
+
+impl Client {
+    async fn connect(addr: &str) -> crate::Result<Client> {
+        let socket = TcpStream::connect(addr).await?;
+        Ok(Client {
+            connection: Connection::new(socket),
+        })
+    }
+
+    async fn read(&mut self) -> crate::Result<Frame> {
+        match self.connection.read_frame().await? {
+            Some(frame) => Ok(frame),
+            None => Err("connection closed by the server".into()),
+        }
+    }
+
+    /// Send a command and return its reply, or an error reply as an `Err`.
+    async fn call(&mut self, args: &[&[u8]]) -> crate::Result<Frame> {
+        let mut frame = Frame::array();
+        for arg in args {
+            frame.push_bulk(Bytes::copy_from_slice(arg));
+        }
+        self.connection.write_frame(&frame).await?;
+        self.connection.flush().await?;
+
+        match self.read().await? {
+            Frame::Error(err) => Err(err.into()),
+            frame => Ok(frame),
+        }
+    }
+
+    /// Read the value and time to live of `key`, or `None` if it no longer
+    /// exists or has an unsupported type.
+    async fn fetch(&mut self, key: Bytes) -> crate::Result<Option<Entry>> {
+        let ty = match self.call(&["type".as_bytes(), &key]).await? {
+            Frame::Simple(ty) => ty,
+            frame => return Err(format!("unexpected reply to TYPE: {:?}", frame).into()),
+        };
+
+        let reply = match ty.as_str() {
+            "none" => return Ok(None),
+            "string" => self.call(&["get".as_bytes(), &key]).await?,
+            "list" => self.call(&["lrange".as_bytes(), &key, b"0", b"-1"]).await?,
+            "set" => self.call(&["smembers".as_bytes(), &key]).await?,
+            "zset" => {
+                let reply = &[&b"zrange"[..], &key, b"0", b"-1", b"withscores"];
+                self.call(reply).await?
+            }
+            "hash" => self.call(&["hgetall".as_bytes(), &key]).await?,
+            ty => {
+                tracing::warn!("Skipped key {:?} of unsupported type {}", key, ty);
+                return Ok(None);
+            }
+        };
+        if matches!(reply, Frame::Null) {
+            return Ok(None);
+        }
+
+        let value = match ty.as_str() {
+            "string" => Value::String(bulk(reply)?),
+            "list" => Value::List(strings(reply)?),
+            "set" => Value::Set(strings(reply)?),
+            "zset" => Value::SortedSet(
+                pairs(strings(reply)?)
+                    .into_iter()
+                    .map(|(member, score)| {
+                        let score = std::str::from_utf8(&score)
+                            .ok()
+                            .and_then(|s| s.parse().ok())
+                            .ok_or_else(|| format!("invalid score {:?}", score))?;
+                        Ok((member, score))
+                    })
+                    .collect::<Result<_, String>>()?,
+            ),
+            _ => Value::Hash(pairs(strings(reply)?)),
+        };
+
+        let ttl = match self.call(&["pttl".as_bytes(), &key]).await? {
+            // 読み出す間に期限切れになったキーは書き出さない
+            Frame::Integer(0 | -2) => return Ok(None),
+            Frame::Integer(ttl) if ttl > 0 => Some(ttl as u64),
+            Frame::Integer(_) => None,
+            frame => return Err(format!("unexpected reply to PTTL: {:?}", frame).into()),
+        };
+
+        Ok(Some(Entry { key, value, ttl }))
+    }
+}
+
+fn bulk(frame: Frame) -> crate::Result<Bytes> {
+    match frame {
+        Frame::Bulk(bytes) => Ok(bytes),
+        Frame::Simple(s) => Ok(Bytes::from(s)),
+        frame => Err(format!("expected a string, got {:?}", frame).into()),
+    }
+}
+
+fn strings(frame: Frame) -> crate::Result<Vec<Bytes>> {
+    match frame {
+        Frame::Array(items) | Frame::Set(items) => items.into_iter().map(bulk).collect(),
+        frame => Err(format!("expected an array, got {:?}", frame).into()),
+    }
+}
+
+fn pairs(items: Vec<Bytes>) -> Vec<(Bytes, Bytes)> {
+    let mut items = items.into_iter();
+    let mut pairs = vec![];
+    while let (Some(a), Some(b)) = (items.next(), items.next()) {
+        pairs.push((a, b));
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::server::testing::serve;
+    use crate::server::MiniRedisServer;
+
+    #[test]
+    fn json_and_csv_round_trip() {
+        let b = |s: &'static str| Bytes::from(s);
+        let entries = vec![
+            Entry {
+                key: b("hash"),
+                value: Value::Hash(vec![(b("f"), b("v,\"quoted\""))]),
+                ttl: None,
+            },
+            Entry {
+                key: b("string"),
+                value: Value::String(Bytes::from(vec![0xff, 0x00])),
+                ttl: Some(1500),
+            },
+            Entry {
+                key: b("zset"),
+                value: Value::SortedSet(vec![(b("m"), 1.5), (b("top"), f64::INFINITY)]),
+                ttl: None,
+            },
+        ];
+
+        let mut json = vec![];
+        write(&mut json, Format::Json, &entries).unwrap();
+        let lines: Vec<_> = std::str::from_utf8(&json).unwrap().lines().collect();
+        assert_eq!(
+            lines[1],
+            r#"{"key":"c3RyaW5n","type":"string","ttl":1500,"encoding":"base64","value":"/wA="}"#
+        );
+        assert_eq!(read(&json[..], Format::Json).unwrap(), entries);
+
+        let mut csv = vec![];
+        write(&mut csv, Format::Csv, &entries).unwrap();
+        assert_eq!(read(&csv[..], Format::Csv).unwrap(), entries);
+    }
+
+    #[tokio::test]
+    async fn fetch_and_replay() {
+        let server = MiniRedisServer::new("127.0.0.1:0".to_string(), Db::default());
+        let (addr, tx, handle) = serve(server).await;
+
+        let kept = Entry {
+            key: Bytes::from("list"),
+            value: Value::String(Bytes::from("kept")),
+            ttl: None,
+        };
+        assert_eq!(replay(&addr, std::slice::from_ref(&kept)).await.unwrap(), 1);
+
+        let entries = vec![
+            Entry {
+                key: Bytes::from("a"),
+                value: Value::String(Bytes::from("1")),
+                ttl: None,
+            },
+            Entry {
+                key: Bytes::from("b"),
+                value: Value::String(Bytes::from("2")),
+                ttl: Some(60_000),
+            },
+            Entry {
+                key: Bytes::from("list"),
+                value: Value::List(vec![Bytes::from("x")]),
+                ttl: None,
+            },
+            Entry {
+                key: Bytes::from("expired"),
+                value: Value::String(Bytes::from("3")),
+                ttl: Some(0),
+            },
+        ];
+        // The server only holds strings, so the list is rejected and the
+        // string of the same name is kept. The expired key is skipped.
+        assert_eq!(replay(&addr, &entries).await.unwrap(), 2);
+
+        let fetched = fetch(&addr).await.unwrap();
+        assert_eq!(fetched.len(), 3);
+        assert_eq!(fetched[0], entries[0]);
+        assert!(matches!(fetched[1].ttl, Some(ttl) if ttl > 50_000 && ttl <= 60_000));
+        assert_eq!(fetched[2], kept);
+
+        tx.send(()).unwrap();
+        handle.await.unwrap().unwrap();
+    }
+}
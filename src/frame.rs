@@ -1,24 +1,64 @@
 //! Provides a type representing a Redis protocol frame as well as utilities for
 //! parsing frames from a byte array.
 
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes};
 use std::convert::TryInto;
 use std::fmt;
 use std::io::Cursor;
 use std::num::TryFromIntError;
+use std::ops::Range;
 use std::string::FromUtf8Error;
 
 /// A frame in the Redis protocol.
-#[derive(Clone, Debug)]
+///
+/// The variants following `NullArray` only exist in RESP3. When writing to a
+/// connection speaking RESP2, they are sent as the closest RESP2 type instead.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Frame {
     Simple(String),
     Error(String),
-    Integer(u64),
+    Integer(i64),
     Bulk(Bytes),
     Null,
     Array(Vec<Frame>),
+    NullArray,
+    Double(f64),
+    Boolean(bool),
+    BigNumber(String),
+    /// A string along with its three byte format, e.g. `txt` or `mkd`
+    Verbatim(String, Bytes),
+    Map(Vec<(Frame, Frame)>),
+    Set(Vec<Frame>),
+    /// Out of band data, such as pub/sub messages
+    Push(Vec<Frame>),
+    /// A reply preceded by auxiliary information about it
+    Attribute(Vec<(Frame, Frame)>, Box<Frame>),
 }
 
+/// Version of the Redis protocol spoken on a connection, selected by `HELLO`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+/// Maximum nesting depth of array frames.
+const MAX_DEPTH: usize = 64;
+
+/// Maximum length of an inline command, guarding against a client that never
+/// sends a new line.
+const MAX_INLINE_LEN: usize = 64 * 1024;
+
+/// Maximum length of a bulk string, as `proto-max-bulk-len` of Redis.
+///
+/// The whole string is buffered before the frame is parsed, so a client could
+/// otherwise make the server allocate any amount of memory.
+const MAX_BULK_LEN: u64 = 512 * 1024 * 1024;
+
+/// Maximum number of elements of an aggregate frame.
+const MAX_MULTIBULK_LEN: u64 = 1024 * 1024;
+
 #[derive(Debug)]
 pub enum Error {
     /// Not enough data is available to parse a message
@@ -30,7 +70,7 @@ pub enum Error {
 
 impl Frame {
     /// Returns an empty array
-    pub(crate) fn array() -> Frame {
+    pub fn array() -> Frame {
         Frame::Array(vec![])
     }
 
@@ -39,7 +79,7 @@ impl Frame {
     /// # Panics
     ///
     /// panics if `self` is not an array
-    pub(crate) fn push_bulk(&mut self, bytes: Bytes) {
+    pub fn push_bulk(&mut self, bytes: Bytes) {
         match self {
             Frame::Array(vec) => {
                 vec.push(Frame::Bulk(bytes));
@@ -53,7 +93,7 @@ impl Frame {
     /// # Panics
     ///
     /// panics if `self` is not an array
-    pub(crate) fn push_int(&mut self, value: u64) {
+    pub fn push_int(&mut self, value: i64) {
         match self {
             Frame::Array(vec) => {
                 vec.push(Frame::Integer(value));
@@ -65,50 +105,86 @@ impl Frame {
     /// Checks if an entire message can be decoded from `src`
     /// フレーム全体が取得可能かどうかをチェックする。
     /// src のカーソルは進むので、check 後に再度 buffer を先頭から利用したい場合は、buf.set_position(0) でカーソルを buffer の先頭に戻す必要がある。
+    ///
+    /// Any input that `parse` would reject is reported as `Error::Other`
+    /// here, so a frame that passes `check` always parses.
     pub fn check(src: &mut Cursor<&[u8]>) -> Result<(), Error> {
+        Frame::check_nested(src, 0)
+    }
+
+    fn check_nested(src: &mut Cursor<&[u8]>, depth: usize) -> Result<(), Error> {
         // get_u8: バッファ src から 1 byte 取得してカーソルを 1 つ進める
         // ここでは、フレームの種類をチェックしている
         match get_u8(src)? {
-            b'+' => {
-                get_line(src)?;
-                Ok(())
-            }
-            b'-' => {
-                get_line(src)?;
+            b'+' | b'-' => {
+                let line = get_line(src)?;
+                std::str::from_utf8(line).map_err(|_| "invalid UTF-8 in simple string")?;
                 Ok(())
             }
             b':' => {
-                let _ = get_decimal(src)?;
+                let _ = get_integer(src)?;
                 Ok(())
             }
             b'$' => {
                 if b'-' == peek_u8(src)? {
                     // Skip '-1\r\n'
-                    skip(src, 4)
+                    get_null(src)
                 } else {
-                    // Read the bulk string
-                    let len: usize = get_decimal(src)?.try_into()?;
-
-                    // skip that number of bytes + 2 (\r\n).
-                    skip(src, len + 2)
+                    get_blob(src).map(drop)
                 }
             }
-            b'*' => {
-                let len = get_decimal(src)?;
+            b'_' => match get_line(src)? {
+                b"" => Ok(()),
+                _ => Err("invalid null".into()),
+            },
+            b',' => parse_double(get_line(src)?).map(drop),
+            b'#' => parse_boolean(get_line(src)?).map(drop),
+            b'(' => parse_big_number(get_line(src)?).map(drop),
+            b'=' => {
+                let range = get_blob(src)?;
+                verbatim_format(&src.get_ref()[range]).map(drop)
+            }
+            kind @ (b'*' | b'~' | b'>' | b'%' | b'|') => {
+                if kind == b'*' && b'-' == peek_u8(src)? {
+                    // Skip '-1\r\n'
+                    return get_null(src);
+                }
+
+                // Each nested aggregate recurses, bound the depth so that a
+                // stream of `*1\r\n` cannot overflow the stack.
+                if depth >= MAX_DEPTH {
+                    return Err("too many nested aggregates".into());
+                }
+
+                // Maps and attributes are followed by a key and a value for
+                // each entry, attributes then by the reply they annotate
+                let mut len = get_decimal(src)?;
+                if len > MAX_MULTIBULK_LEN {
+                    return Err("invalid multibulk length".into());
+                }
+                if kind == b'%' || kind == b'|' {
+                    len = len.checked_mul(2).ok_or("invalid map length")?;
+                }
+                if kind == b'|' {
+                    len = len.checked_add(1).ok_or("invalid map length")?;
+                }
 
                 for _ in 0..len {
-                    Frame::check(src)?;
+                    Frame::check_nested(src, depth + 1)?;
                 }
 
                 Ok(())
             }
-            actual => Err(format!("protocol error; invalid frame type byte `{}`", actual).into()),
+            actual => Err(format!("invalid frame type byte '{}'", actual.escape_ascii()).into()),
         }
     }
 
     /// The message has already been validated with `check`.
     /// Frame::check が実行されていることが前提でこのメソッドは実行される
-    pub fn parse(src: &mut Cursor<&[u8]>) -> Result<Frame, Error> {
+    ///
+    /// Bulk strings are returned as slices of `src`, sharing its buffer
+    /// instead of copying the data.
+    pub fn parse(src: &mut Cursor<&Bytes>) -> Result<Frame, Error> {
         // get_u8: バッファ src から 1 byte 取得してカーソルを 1 つ進める
         // ここでは、フレームの種類を決定している
         match get_u8(src)? {
@@ -131,49 +207,112 @@ impl Frame {
                 Ok(Frame::Error(string))
             }
             b':' => {
-                let len = get_decimal(src)?;
-                Ok(Frame::Integer(len))
+                let value = get_integer(src)?;
+                Ok(Frame::Integer(value))
             }
             b'$' => {
                 if b'-' == peek_u8(src)? {
-                    let line = get_line(src)?;
-
-                    if line != b"-1" {
-                        return Err("protocol error; invalid frame format".into());
-                    }
-
+                    get_null(src)?;
                     Ok(Frame::Null)
                 } else {
-                    // Read the bulk string
-                    let len = get_decimal(src)?.try_into()?;
-                    let n = len + 2;
-
-                    if src.remaining() < n {
-                        return Err(Error::Incomplete);
-                    }
-
-                    let data = Bytes::copy_from_slice(&src.chunk()[..len]);
-
-                    // skip that number of bytes + 2 (\r\n).
-                    skip(src, n)?;
-
-                    Ok(Frame::Bulk(data))
+                    let range = get_blob(src)?;
+                    Ok(Frame::Bulk(src.get_ref().slice(range)))
                 }
             }
+            b'_' => {
+                get_line(src)?;
+                Ok(Frame::Null)
+            }
+            b',' => Ok(Frame::Double(parse_double(get_line(src)?)?)),
+            b'#' => Ok(Frame::Boolean(parse_boolean(get_line(src)?)?)),
+            b'(' => Ok(Frame::BigNumber(parse_big_number(get_line(src)?)?)),
+            b'=' => {
+                let data = src.get_ref().slice(get_blob(src)?);
+                let format = verbatim_format(&data)?;
+                Ok(Frame::Verbatim(format, data.slice(4..)))
+            }
             b'*' => {
-                let len = get_decimal(src)?.try_into()?;
-                let mut out = Vec::with_capacity(len);
-
-                for _ in 0..len {
-                    out.push(Frame::parse(src)?);
+                if b'-' == peek_u8(src)? {
+                    get_null(src)?;
+                    return Ok(Frame::NullArray);
                 }
 
-                Ok(Frame::Array(out))
+                Ok(Frame::Array(Frame::parse_entries(src)?))
             }
-            _ => unimplemented!(),
+            b'~' => Ok(Frame::Set(Frame::parse_entries(src)?)),
+            b'>' => Ok(Frame::Push(Frame::parse_entries(src)?)),
+            b'%' => Ok(Frame::Map(Frame::parse_pairs(src)?)),
+            b'|' => {
+                let attributes = Frame::parse_pairs(src)?;
+                let reply = Frame::parse(src)?;
+                Ok(Frame::Attribute(attributes, Box::new(reply)))
+            }
+            actual => Err(format!("invalid frame type byte '{}'", actual.escape_ascii()).into()),
+        }
+    }
+
+    /// Checks if `src` starts with an inline command rather than a frame.
+    ///
+    /// Inline commands are what users type into `telnet` or `nc`, e.g.
+    /// `SET a "hello world"`. They are told apart from frames by their first
+    /// byte, which is not one of the frame type bytes.
+    pub fn is_inline(src: &[u8]) -> bool {
+        match src.first() {
+            Some(byte) => !b"+-:$*_,#(=~>%|".contains(byte),
+            None => false,
         }
     }
 
+    /// Parse an inline command terminated by `\r\n` or `\n` into the array of
+    /// bulk strings a client library would have sent.
+    ///
+    /// Arguments are separated by spaces. Double quoted arguments may contain
+    /// spaces and the escapes `\n`, `\r`, `\t`, `\b`, `\a`, `\\`, `\"` and
+    /// `\xHH`, single quoted arguments only `\'`. An empty line results in an
+    /// empty array.
+    pub fn parse_inline(src: &mut Cursor<&[u8]>) -> Result<Frame, Error> {
+        let start = src.position() as usize;
+        let buf = &src.get_ref()[start..];
+
+        let end = match buf.iter().position(|&b| b == b'\n') {
+            Some(end) => end,
+            None if buf.len() > MAX_INLINE_LEN => return Err("too big inline request".into()),
+            None => return Err(Error::Incomplete),
+        };
+        let line = buf[..end].strip_suffix(b"\r").unwrap_or(&buf[..end]);
+
+        let args = split_inline_args(line)?;
+        src.set_position((start + end + 1) as u64);
+
+        Ok(Frame::Array(args.into_iter().map(Frame::Bulk).collect()))
+    }
+
+    /// Parse the length-prefixed entries of an array, set or push frame
+    fn parse_entries(src: &mut Cursor<&Bytes>) -> Result<Vec<Frame>, Error> {
+        let len = get_decimal(src)?.try_into()?;
+        let mut out = Vec::with_capacity(len);
+
+        for _ in 0..len {
+            out.push(Frame::parse(src)?);
+        }
+
+        Ok(out)
+    }
+
+    /// Parse the length-prefixed key / value pairs of a map or attribute frame
+    fn parse_pairs(src: &mut Cursor<&Bytes>) -> Result<Vec<(Frame, Frame)>, Error> {
+        let len = get_decimal(src)?.try_into()?;
+        let mut out = Vec::with_capacity(len);
+
+        for _ in 0..len {
+            let key = Frame::parse(src)?;
+            let value = Frame::parse(src)?;
+            out.push((key, value));
+        }
+
+        Ok(out)
+    }
+
     /// Converts the frame to an "unexpected frame" error
     pub(crate) fn to_error(&self) -> crate::Error {
         format!("unexpected frame: {}", self).into()
@@ -202,13 +341,28 @@ impl fmt::Display for Frame {
                 Ok(string) => string.fmt(fmt),
                 Err(_) => write!(fmt, "{:?}", msg),
             },
-            Frame::Null => "(nil)".fmt(fmt),
-            Frame::Array(parts) => {
+            Frame::Null | Frame::NullArray => "(nil)".fmt(fmt),
+            Frame::Double(num) => num.fmt(fmt),
+            Frame::Boolean(val) => val.fmt(fmt),
+            Frame::BigNumber(num) => num.fmt(fmt),
+            Frame::Verbatim(_, text) => Frame::Bulk(text.clone()).fmt(fmt),
+            Frame::Array(parts) | Frame::Set(parts) | Frame::Push(parts) => {
                 for (i, part) in parts.iter().enumerate() {
                     if i > 0 {
                         write!(fmt, " ")?;
-                        part.fmt(fmt)?;
                     }
+                    part.fmt(fmt)?;
+                }
+
+                Ok(())
+            }
+            Frame::Attribute(_, reply) => reply.fmt(fmt),
+            Frame::Map(pairs) => {
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(fmt, " ")?;
+                    }
+                    write!(fmt, "{} {}", key, value)?;
                 }
 
                 Ok(())
@@ -217,7 +371,7 @@ impl fmt::Display for Frame {
     }
 }
 
-fn peek_u8(src: &mut Cursor<&[u8]>) -> Result<u8, Error> {
+fn peek_u8<T: AsRef<[u8]> + ?Sized>(src: &mut Cursor<&T>) -> Result<u8, Error> {
     if !src.has_remaining() {
         return Err(Error::Incomplete);
     }
@@ -225,7 +379,7 @@ fn peek_u8(src: &mut Cursor<&[u8]>) -> Result<u8, Error> {
     Ok(src.chunk()[0])
 }
 
-fn get_u8(src: &mut Cursor<&[u8]>) -> Result<u8, Error> {
+fn get_u8<T: AsRef<[u8]> + ?Sized>(src: &mut Cursor<&T>) -> Result<u8, Error> {
     if !src.has_remaining() {
         return Err(Error::Incomplete);
     }
@@ -233,7 +387,7 @@ fn get_u8(src: &mut Cursor<&[u8]>) -> Result<u8, Error> {
     Ok(src.get_u8())
 }
 
-fn skip(src: &mut Cursor<&[u8]>, n: usize) -> Result<(), Error> {
+fn skip<T: AsRef<[u8]> + ?Sized>(src: &mut Cursor<&T>, n: usize) -> Result<(), Error> {
     if src.remaining() < n {
         return Err(Error::Incomplete);
     }
@@ -242,29 +396,348 @@ fn skip(src: &mut Cursor<&[u8]>, n: usize) -> Result<(), Error> {
     Ok(())
 }
 
+/// Read the `-1\r\n` line of a null bulk string or null array
+fn get_null<T: AsRef<[u8]> + ?Sized>(src: &mut Cursor<&T>) -> Result<(), Error> {
+    match get_line(src)? {
+        b"-1" => Ok(()),
+        _ => Err("invalid frame format".into()),
+    }
+}
+
+/// Split the line of an inline command into its arguments, see
+/// `Frame::parse_inline`.
+pub(crate) fn split_inline_args(line: &[u8]) -> Result<Vec<Bytes>, Error> {
+    const UNBALANCED: &str = "unbalanced quotes in inline request";
+
+    let mut args = vec![];
+    let mut i = 0;
+
+    loop {
+        // Skip the blanks separating arguments
+        while line.get(i).is_some_and(u8::is_ascii_whitespace) {
+            i += 1;
+        }
+        if i == line.len() {
+            return Ok(args);
+        }
+
+        let mut arg = vec![];
+        let quote = match line[i] {
+            q @ (b'"' | b'\'') => {
+                i += 1;
+                Some(q)
+            }
+            _ => None,
+        };
+
+        loop {
+            match (quote, line.get(i)) {
+                (None, None) => break,
+                (None, Some(c)) if c.is_ascii_whitespace() => break,
+                (Some(_), None) => return Err(UNBALANCED.into()),
+                (Some(q), Some(&c)) if c == q => {
+                    // The closing quote must end the argument
+                    i += 1;
+                    if line.get(i).is_some_and(|c| !c.is_ascii_whitespace()) {
+                        return Err(UNBALANCED.into());
+                    }
+                    break;
+                }
+                (Some(b'"'), Some(b'\\')) if i + 1 < line.len() => {
+                    let hex = line
+                        .get(i + 2..i + 4)
+                        .filter(|hex| line[i + 1] == b'x' && hex.iter().all(u8::is_ascii_hexdigit))
+                        .and_then(|hex| std::str::from_utf8(hex).ok())
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+                    if let Some(byte) = hex {
+                        arg.push(byte);
+                        i += 4;
+                    } else {
+                        arg.push(match line[i + 1] {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'b' => 0x08,
+                            b'a' => 0x07,
+                            c => c,
+                        });
+                        i += 2;
+                    }
+                }
+                (Some(b'\''), Some(b'\\')) if line.get(i + 1) == Some(&b'\'') => {
+                    arg.push(b'\'');
+                    i += 2;
+                }
+                (_, Some(&c)) => {
+                    arg.push(c);
+                    i += 1;
+                }
+            }
+        }
+
+        args.push(Bytes::from(arg));
+    }
+}
+
+/// Format a double the way it is written in a double frame
+pub(crate) fn format_double(val: f64) -> String {
+    if val.is_nan() {
+        "nan".to_string()
+    } else {
+        val.to_string()
+    }
+}
+
+/// Encode `frame` into `dst` as it is written to a connection speaking
+/// `protocol`.
+///
+/// RESP2 has no equivalent of the RESP3 types, so with `Protocol::Resp2` they
+/// are encoded as the closest RESP2 type instead, e.g. maps as flat arrays of
+/// keys and values.
+pub(crate) fn encode(frame: &Frame, protocol: Protocol, dst: &mut impl BufMut) {
+    match frame {
+        Frame::Simple(val) => {
+            dst.put_u8(b'+');
+            dst.put_slice(val.as_bytes());
+            dst.put_slice(b"\r\n");
+        }
+        Frame::Error(val) => {
+            dst.put_u8(b'-');
+            dst.put_slice(val.as_bytes());
+            dst.put_slice(b"\r\n");
+        }
+        Frame::Integer(val) => {
+            dst.put_u8(b':');
+            if *val < 0 {
+                dst.put_u8(b'-');
+            }
+            put_decimal(dst, val.unsigned_abs());
+        }
+        Frame::Null | Frame::NullArray if protocol == Protocol::Resp3 => {
+            dst.put_slice(b"_\r\n");
+        }
+        Frame::Null => {
+            dst.put_slice(b"$-1\r\n");
+        }
+        Frame::Bulk(val) => {
+            dst.put_u8(b'$');
+            put_decimal(dst, val.len() as u64);
+            dst.put_slice(val);
+            dst.put_slice(b"\r\n");
+        }
+        Frame::NullArray => {
+            dst.put_slice(b"*-1\r\n");
+        }
+        Frame::Array(val) => {
+            dst.put_u8(b'*');
+            put_decimal(dst, val.len() as u64);
+
+            for entry in val {
+                encode(entry, protocol, dst);
+            }
+        }
+        Frame::Double(val) if protocol == Protocol::Resp2 => {
+            encode(&Frame::Bulk(format_double(*val).into()), protocol, dst);
+        }
+        Frame::Boolean(val) if protocol == Protocol::Resp2 => {
+            encode(&Frame::Integer(*val as i64), protocol, dst);
+        }
+        Frame::BigNumber(val) if protocol == Protocol::Resp2 => {
+            encode(&Frame::Bulk(val.clone().into()), protocol, dst);
+        }
+        Frame::Verbatim(_, val) if protocol == Protocol::Resp2 => {
+            encode(&Frame::Bulk(val.clone()), protocol, dst);
+        }
+        Frame::Set(val) | Frame::Push(val) if protocol == Protocol::Resp2 => {
+            dst.put_u8(b'*');
+            put_decimal(dst, val.len() as u64);
+
+            for entry in val {
+                encode(entry, protocol, dst);
+            }
+        }
+        Frame::Map(val) if protocol == Protocol::Resp2 => {
+            dst.put_u8(b'*');
+            put_decimal(dst, 2 * val.len() as u64);
+
+            for (key, value) in val {
+                encode(key, protocol, dst);
+                encode(value, protocol, dst);
+            }
+        }
+        Frame::Attribute(_, reply) if protocol == Protocol::Resp2 => {
+            encode(reply, protocol, dst);
+        }
+        Frame::Double(val) => {
+            dst.put_u8(b',');
+            dst.put_slice(format_double(*val).as_bytes());
+            dst.put_slice(b"\r\n");
+        }
+        Frame::Boolean(val) => {
+            dst.put_slice(if *val { b"#t\r\n" } else { b"#f\r\n" });
+        }
+        Frame::BigNumber(val) => {
+            dst.put_u8(b'(');
+            dst.put_slice(val.as_bytes());
+            dst.put_slice(b"\r\n");
+        }
+        Frame::Verbatim(format, val) => {
+            dst.put_u8(b'=');
+            put_decimal(dst, val.len() as u64 + 4);
+            dst.put_slice(format.as_bytes());
+            dst.put_u8(b':');
+            dst.put_slice(val);
+            dst.put_slice(b"\r\n");
+        }
+        Frame::Set(val) | Frame::Push(val) => {
+            let prefix = if let Frame::Set(_) = frame {
+                b'~'
+            } else {
+                b'>'
+            };
+            dst.put_u8(prefix);
+            put_decimal(dst, val.len() as u64);
+
+            for entry in val {
+                encode(entry, protocol, dst);
+            }
+        }
+        Frame::Map(val) => {
+            dst.put_u8(b'%');
+            put_decimal(dst, val.len() as u64);
+
+            for (key, value) in val {
+                encode(key, protocol, dst);
+                encode(value, protocol, dst);
+            }
+        }
+        Frame::Attribute(val, reply) => {
+            dst.put_u8(b'|');
+            put_decimal(dst, val.len() as u64);
+
+            for (key, value) in val {
+                encode(key, protocol, dst);
+                encode(value, protocol, dst);
+            }
+            encode(reply, protocol, dst);
+        }
+    }
+}
+
+/// Encode a `\r\n` terminated decimal into `dst`
+pub(crate) fn put_decimal(dst: &mut impl BufMut, val: u64) {
+    use std::io::Write;
+
+    // `u64::MAX` has 20 digits, so writing to the buffer cannot fail
+    let mut buf = [0u8; 20];
+    let mut cursor = Cursor::new(&mut buf[..]);
+    let _ = write!(cursor, "{}", val);
+    let len = cursor.position() as usize;
+
+    dst.put_slice(&buf[..len]);
+    dst.put_slice(b"\r\n");
+}
+
+/// Read a length-prefixed blob, the payload of bulk and verbatim strings
+///
+/// Returns the position of the data within `src`.
+fn get_blob<T: AsRef<[u8]> + ?Sized>(src: &mut Cursor<&T>) -> Result<Range<usize>, Error> {
+    let len = get_decimal(src)?;
+    if len > MAX_BULK_LEN {
+        return Err("invalid bulk length".into());
+    }
+    let len = len as usize;
+    let start = src.position() as usize;
+
+    // skip that number of bytes + 2 (\r\n).
+    skip(src, len.checked_add(2).ok_or("invalid bulk length")?)?;
+
+    // The data must be terminated by \r\n
+    if &(*src.get_ref()).as_ref()[start + len..start + len + 2] != b"\r\n" {
+        return Err("invalid bulk length".into());
+    }
+
+    Ok(start..start + len)
+}
+
+/// Parse the line of a double frame, e.g. `1.5`, `inf` or `nan`
+fn parse_double(line: &[u8]) -> Result<f64, Error> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|line| line.parse().ok())
+        .ok_or_else(|| "invalid double".into())
+}
+
+/// Parse the line of a boolean frame, `t` or `f`
+fn parse_boolean(line: &[u8]) -> Result<bool, Error> {
+    match line {
+        b"t" => Ok(true),
+        b"f" => Ok(false),
+        _ => Err("invalid boolean".into()),
+    }
+}
+
+/// Parse the line of a big number frame, an arbitrarily long integer
+fn parse_big_number(line: &[u8]) -> Result<String, Error> {
+    let digits = line.strip_prefix(b"-").unwrap_or(line);
+
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return Err("invalid big number".into());
+    }
+
+    Ok(String::from_utf8(line.to_vec())?)
+}
+
+/// Get the format of a verbatim string, the three bytes preceding a `:`
+fn verbatim_format(data: &[u8]) -> Result<String, Error> {
+    match data.get(..4) {
+        Some([format @ .., b':']) => Ok(String::from_utf8(format.to_vec())?),
+        _ => Err("invalid verbatim string".into()),
+    }
+}
+
 /// Read a new-line terminated decimal
-fn get_decimal(src: &mut Cursor<&[u8]>) -> Result<u64, Error> {
+fn get_decimal<T: AsRef<[u8]> + ?Sized>(src: &mut Cursor<&T>) -> Result<u64, Error> {
+    use atoi::atoi;
+
+    let line = get_line(src)?;
+
+    atoi::<u64>(line)
+        .filter(|_| !line.is_empty() && line.iter().all(u8::is_ascii_digit))
+        .ok_or_else(|| "invalid frame format".into())
+}
+
+/// Read a new-line terminated, optionally negative, decimal
+fn get_integer<T: AsRef<[u8]> + ?Sized>(src: &mut Cursor<&T>) -> Result<i64, Error> {
     use atoi::atoi;
 
     let line = get_line(src)?;
+    let digits = line.strip_prefix(b"-").unwrap_or(line);
+
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return Err("invalid frame format".into());
+    }
 
-    atoi::<u64>(line).ok_or_else(|| "protocol error; invalid frame format".into())
+    // `atoi` uses checked arithmetic, so out of range values are `None`
+    atoi::<i64>(line).ok_or_else(|| "integer out of range".into())
 }
 
 /// Find a line
-fn get_line<'a>(src: &mut Cursor<&'a [u8]>) -> Result<&'a [u8], Error> {
+fn get_line<'a, T: AsRef<[u8]> + ?Sized>(src: &mut Cursor<&'a T>) -> Result<&'a [u8], Error> {
     // Scan the bytes directly
+    let buf: &'a [u8] = (*src.get_ref()).as_ref();
     let start = src.position() as usize;
     // Scan to the second to last byte
-    let end = src.get_ref().len() - 1;
+    let end = buf.len().saturating_sub(1);
 
     for i in start..end {
-        if src.get_ref()[i] == b'\r' && src.get_ref()[i + 1] == b'\n' {
+        if buf[i] == b'\r' && buf[i + 1] == b'\n' {
             // We found a line, update the position to be *after* the \n
             src.set_position((i + 2) as u64);
 
             // Return the line
-            return Ok(&src.get_ref()[start..i]);
+            return Ok(&buf[start..i]);
         }
     }
 
@@ -285,13 +758,13 @@ impl From<&str> for Error {
 
 impl From<FromUtf8Error> for Error {
     fn from(_src: FromUtf8Error) -> Error {
-        "protocol error; invalid frame format".into()
+        "invalid frame format".into()
     }
 }
 
 impl From<TryFromIntError> for Error {
     fn from(_src: TryFromIntError) -> Error {
-        "protocol error; invalid frame format".into()
+        "invalid frame format".into()
     }
 }
 
@@ -301,7 +774,156 @@ impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::Incomplete => "stream ended early".fmt(fmt),
-            Error::Other(err) => err.fmt(fmt),
+            Error::Other(err) => write!(fmt, "protocol error; {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Runs `check` and, if it succeeds, `parse` over `input`.
+    fn check_and_parse(input: &[u8]) -> Result<Frame, Error> {
+        let mut buf = Cursor::new(input);
+        Frame::check(&mut buf)?;
+
+        let data = Bytes::copy_from_slice(&input[..buf.position() as usize]);
+        Ok(Frame::parse(&mut Cursor::new(&data)).expect("checked frame must parse"))
+    }
+
+    #[test]
+    fn invalid_frames_are_errors() {
+        let nested = "*1\r\n".repeat(MAX_DEPTH + 1) + ":1\r\n";
+        let corpus: &[&[u8]] = &[
+            b"!\r\n",
+            b"\x00",
+            b"$abc\r\n",
+            b"$-2\r\n",
+            b"*-2\r\n",
+            b"$+3\r\nabc\r\n",
+            b":1x\r\n",
+            b"*1x\r\n:1\r\n",
+            b"$18446744073709551615\r\n",
+            b"$99999999999999999999\r\n",
+            b"$9999999999\r\n",
+            b"$536870913\r\n",
+            b"=536870913\r\n",
+            b"*9999999999\r\n",
+            b"*1048577\r\n",
+            b"%1048577\r\n",
+            b"$3\r\nabcde\r\n",
+            b"+\xff\r\n",
+            b"*2\r\n:1\r\n?\r\n",
+            b":18446744073709551615\r\n",
+            b":9223372036854775808\r\n",
+            b":-9223372036854775809\r\n",
+            b":-\r\n",
+            b":--1\r\n",
+            b":+1\r\n",
+            b"_x\r\n",
+            b",1.5x\r\n",
+            b"#x\r\n",
+            b"(12a\r\n",
+            b"=3\r\ntxt\r\n",
+            b"%1\r\n:1\r\n?\r\n",
+            nested.as_bytes(),
+        ];
+
+        for input in corpus {
+            match check_and_parse(input) {
+                Err(Error::Other(_)) => {}
+                res => panic!("{:?} was not rejected: {:?}", input.escape_ascii(), res),
+            }
+        }
+    }
+
+    #[test]
+    fn signed_integers() {
+        for (input, expected) in [
+            (&b":-1\r\n"[..], -1),
+            (b":0\r\n", 0),
+            (b":9223372036854775807\r\n", i64::MAX),
+            (b":-9223372036854775808\r\n", i64::MIN),
+        ] {
+            assert_eq!(check_and_parse(input).unwrap(), Frame::Integer(expected));
+        }
+    }
+
+    #[test]
+    fn inline_commands() {
+        fn inline(input: &[u8]) -> Result<Vec<String>, Error> {
+            match Frame::parse_inline(&mut Cursor::new(input))? {
+                Frame::Array(args) => Ok(args.iter().map(|arg| arg.to_string()).collect()),
+                frame => panic!("unexpected frame {:?}", frame),
+            }
+        }
+
+        assert_eq!(inline(b"PING\r\n").unwrap(), ["PING"]);
+        assert_eq!(inline(b"  set a   b\n").unwrap(), ["set", "a", "b"]);
+        assert_eq!(inline(b"\r\n").unwrap(), Vec::<String>::new());
+        assert_eq!(
+            inline(&[&br#"SET "a b" 'it\'s' "\x41\n\"" """#[..], b"\n"].concat()).unwrap(),
+            ["SET", "a b", "it's", "A\n\"", ""]
+        );
+
+        assert!(matches!(inline(b"SET a b"), Err(Error::Incomplete)));
+        assert!(matches!(inline(b"SET \"a\n"), Err(Error::Other(_))));
+        assert!(matches!(inline(b"SET \"a\"b\n"), Err(Error::Other(_))));
+        assert!(matches!(
+            inline(&vec![b'a'; MAX_INLINE_LEN + 1]),
+            Err(Error::Other(_))
+        ));
+    }
+
+    #[test]
+    fn partial_frames_are_incomplete() {
+        let input = b"*2\r\n$5\r\nhello\r\n:42\r\n";
+        assert!(check_and_parse(input).is_ok());
+
+        for len in 0..input.len() {
+            assert!(matches!(
+                check_and_parse(&input[..len]),
+                Err(Error::Incomplete)
+            ));
+        }
+    }
+
+    /// Fragments of the protocol, concatenated so that generated inputs get
+    /// past the first byte more often than uniformly random bytes.
+    fn fragments() -> impl Strategy<Value = Vec<u8>> {
+        let fragment = prop_oneof![
+            Just(b"*".to_vec()),
+            Just(b"$".to_vec()),
+            Just(b":".to_vec()),
+            Just(b"+".to_vec()),
+            Just(b"-".to_vec()),
+            Just(b"-1".to_vec()),
+            Just(b"\r\n".to_vec()),
+            proptest::sample::select(b"_,#(=~>%|".to_vec()).prop_map(|c| vec![c]),
+            Just(b"t".to_vec()),
+            Just(b"1.5".to_vec()),
+            Just(b"txt:".to_vec()),
+            (0u64..20).prop_map(|n| n.to_string().into_bytes()),
+            Just(u64::MAX.to_string().into_bytes()),
+            Just((MAX_BULK_LEN + 1).to_string().into_bytes()),
+            Just((MAX_MULTIBULK_LEN + 1).to_string().into_bytes()),
+            proptest::collection::vec(any::<u8>(), 0..4),
+        ];
+        proptest::collection::vec(fragment, 0..24).prop_map(|parts| parts.concat())
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_bytes_never_panic(input in proptest::collection::vec(any::<u8>(), 0..64)) {
+            let _ = check_and_parse(&input);
+            let _ = Frame::parse_inline(&mut Cursor::new(&input[..]));
+        }
+
+        #[test]
+        fn arbitrary_fragments_never_panic(input in fragments()) {
+            let _ = check_and_parse(&input);
         }
     }
 }
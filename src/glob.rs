@@ -0,0 +1,122 @@
+//! Glob-style pattern matching, as used by `KEYS` and `SCAN ... MATCH`.
+
+/// Returns `true` if `string` matches the glob-style `pattern`.
+///
+/// Supported patterns:
+///
+/// * `?` matches exactly one byte
+/// * `*` matches any number of bytes, including none
+/// * `[abc]`, `[a-z]` match one of the listed bytes or ranges, `[^abc]`
+///   matches any other byte
+/// * `\x` matches `x` literally
+pub(crate) fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+
+    // Position of the last `*` in `pattern` and of the byte of `string` it was
+    // tried against. On a mismatch, the `*` is made to match one more byte.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        let matched = match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, s));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match_class(&pattern[p + 1..], string[s]).map(|len| p + 1 + len),
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == string[s]).then_some(p + 2),
+            Some(&c) => (c == string[s]).then_some(p + 1),
+            None => None,
+        };
+
+        match (matched, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                s += 1;
+            }
+            (None, Some((star, tried))) => {
+                p = star + 1;
+                s = tried + 1;
+                backtrack = Some((star, tried + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    // Trailing `*`s match the empty string
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Match `c` against the class at the start of `pattern`, which is the
+/// pattern following the opening `[`.
+///
+/// Returns the length of the class including its closing `]` if `c` matches.
+/// An unterminated class extends to the end of the pattern.
+fn match_class(pattern: &[u8], c: u8) -> Option<usize> {
+    let (negate, mut i) = match pattern.first() {
+        Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut matched = false;
+
+    while i < pattern.len() && pattern[i] != b']' {
+        if pattern[i] == b'\\' && i + 1 < pattern.len() {
+            matched |= pattern[i + 1] == c;
+            i += 2;
+        } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']' {
+            let (start, end) = (
+                pattern[i].min(pattern[i + 2]),
+                pattern[i].max(pattern[i + 2]),
+            );
+            matched |= (start..=end).contains(&c);
+            i += 3;
+        } else {
+            matched |= pattern[i] == c;
+            i += 1;
+        }
+    }
+
+    // Skip the closing `]`
+    let len = (i + 1).min(pattern.len());
+
+    (matched != negate).then_some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn matches_redis_style_patterns() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("*", "", true),
+            ("*", "anything", true),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h*llo", "heeeello", true),
+            ("h*llo", "hello world", false),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-b]llo", "hbllo", true),
+            ("h[a-b]llo", "hcllo", false),
+            ("cache:*", "cache:user:1", true),
+            ("cache:*", "session:1", false),
+            ("*:*:1", "cache:user:1", true),
+            ("a\\*b", "a*b", true),
+            ("a\\*b", "axb", false),
+        ];
+
+        for &(pattern, string, expected) in cases {
+            assert_eq!(
+                glob_match(pattern.as_bytes(), string.as_bytes()),
+                expected,
+                "{:?} against {:?}",
+                pattern,
+                string
+            );
+        }
+    }
+}
@@ -1,9 +1,24 @@
+pub mod acl;
+mod aof;
 pub mod args_parser;
+mod clock;
 pub mod command;
+pub mod config;
 pub mod connection;
 pub mod connection_raw;
+pub mod db;
+pub mod dump;
 pub mod frame;
+mod glob;
+pub mod listener;
+mod parse;
+mod rdb;
 pub mod server;
+mod session;
+mod shutdown;
+mod stats;
+pub mod storage;
+pub mod tls;
 
 /// Error returned by most functions.
 ///
@@ -0,0 +1,138 @@
+//! Sources of client connections served by `MiniRedisServer`.
+
+use crate::connection::Stream;
+
+use async_trait::async_trait;
+use std::io;
+use std::task::Poll;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+/// Accepts client connections.
+///
+/// `MiniRedisServer::serve` accepts connections from any `Listener`, so the
+/// server is not tied to TCP. For example, tests can serve in-memory
+/// `tokio::io::duplex` streams without binding a port.
+#[async_trait]
+pub trait Listener: Send {
+    /// Stream of an accepted connection
+    type Io: Stream;
+
+    /// Wait for the next connection, returning its stream along with a
+    /// description of the peer used in logs.
+    async fn accept(&mut self) -> io::Result<(Self::Io, String)>;
+
+    /// Describe the address the listener is bound to, used in logs.
+    fn local_addr(&self) -> io::Result<String>;
+}
+
+#[async_trait]
+impl Listener for TcpListener {
+    type Io = tokio::net::TcpStream;
+
+    async fn accept(&mut self) -> io::Result<(Self::Io, String)> {
+        let (socket, addr) = TcpListener::accept(self).await?;
+
+        // 応答はパイプライン単位でまとめて flush するので、Nagle アルゴリズムによる遅延は不要
+        if let Err(err) = socket.set_nodelay(true) {
+            tracing::warn!("Failed to set TCP_NODELAY: {}", err);
+        }
+
+        Ok((socket, addr.to_string()))
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        TcpListener::local_addr(self).map(|addr| addr.to_string())
+    }
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl Listener for UnixListener {
+    type Io = tokio::net::UnixStream;
+
+    async fn accept(&mut self) -> io::Result<(Self::Io, String)> {
+        let (socket, addr) = UnixListener::accept(self).await?;
+
+        // クライアント側のソケットは通常名前を持たないので、その場合は待ち受けているパスを表示する
+        let peer = match addr.as_pathname() {
+            Some(path) => path.display().to_string(),
+            None => Listener::local_addr(self)?,
+        };
+
+        Ok((socket, peer))
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        let addr = UnixListener::local_addr(self)?;
+        Ok(match addr.as_pathname() {
+            Some(path) => format!("unix:{}", path.display()),
+            None => "unix:(unnamed)".to_string(),
+        })
+    }
+}
+
+/// Listener accepting streams of any type, see `boxed`.
+pub type BoxListener = Box<dyn Listener<Io = Box<dyn Stream>>>;
+
+/// Box `listener` and the streams it accepts, so that listeners of
+/// different transports can be served together by `Listeners`.
+pub fn boxed<L: Listener + 'static>(listener: L) -> BoxListener {
+    Box::new(Boxed(listener))
+}
+
+struct Boxed<L>(L);
+
+#[async_trait]
+impl<L: Listener> Listener for Boxed<L> {
+    type Io = Box<dyn Stream>;
+
+    async fn accept(&mut self) -> io::Result<(Self::Io, String)> {
+        let (socket, addr) = self.0.accept().await?;
+        Ok((Box::new(socket), addr))
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        self.0.local_addr()
+    }
+}
+
+/// Accepts connections from several listeners at once, e.g. a `TcpListener`
+/// and a `UnixListener`.
+pub struct Listeners(pub Vec<BoxListener>);
+
+#[async_trait]
+impl Listener for Listeners {
+    type Io = Box<dyn Stream>;
+
+    async fn accept(&mut self) -> io::Result<(Self::Io, String)> {
+        let mut accepts: Vec<_> = self
+            .0
+            .iter_mut()
+            .map(|listener| listener.accept())
+            .collect();
+
+        // Once a listener accepts a connection, the pending `accept` of the
+        // others are dropped. This must not lose a connection, which holds
+        // for the tokio listeners.
+        std::future::poll_fn(|cx| {
+            for accept in accepts.iter_mut() {
+                if let Poll::Ready(res) = accept.as_mut().poll(cx) {
+                    return Poll::Ready(res);
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        let addrs = self
+            .0
+            .iter()
+            .map(|listener| listener.local_addr())
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(addrs.join(", "))
+    }
+}
@@ -1,19 +1,23 @@
 use clap::Parser;
 
-use my_mini_redis::{args_parser::ArgsParser, server::MiniRedisServer};
+use my_mini_redis::{args_parser::ArgsParser, db::Db, server::MiniRedisServer};
+use tokio::signal;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> my_mini_redis::Result<()> {
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::INFO)
         .init();
 
     // Define server
     let args = ArgsParser::parse();
-    let addr = format!("{}:{}", args.ip, args.port);
-    let server = MiniRedisServer::new(addr);
+    let config = args.load_config()?;
+    let db = Db::new(config.get_as("shards"));
+    let server = MiniRedisServer::from_config(config, db)?;
+    if let Some(path) = &args.import_rdb {
+        server.import_rdb(path, args.import_strict)?;
+    }
 
-    // Run server
-    let output = server.run();
-    output.await;
+    // Run server until ctrl-c is pressed
+    server.run(signal::ctrl_c()).await
 }
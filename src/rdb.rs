@@ -0,0 +1,915 @@
+//! Snapshots of the keyspace in the Redis RDB format.
+//!
+//! A snapshot starts with the `REDIS` magic string and a four digit version,
+//! followed by auxiliary fields, the entries of the keyspace and an `EOF`
+//! opcode. The last 8 bytes are a CRC64 checksum of everything before them.
+//! Values are stored as RDB strings, so the files can also be read by Redis.
+//!
+//! Files written by Redis itself can be read as well: lists, sets, sorted
+//! sets and hashes are decoded from any of the encodings Redis has used,
+//! including the compact ones implemented in `packed`, although only the
+//! strings can be loaded into the keyspace.
+
+mod lzf;
+mod packed;
+
+use crate::clock;
+use crate::config::Config;
+use crate::stats::Stats;
+use crate::storage::{SetCondition, Storage};
+
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Version of the RDB format written by `encode`, as used by Redis 5 and 6.
+pub(crate) const RDB_VERSION: u16 = 9;
+
+/// Highest version of the RDB format `decode` reads.
+const MAX_RDB_VERSION: u16 = 11;
+
+// Opcodes preceding the entries of the keyspace
+const OPCODE_FUNCTION_PRE_GA: u8 = 0xf6;
+const OPCODE_FUNCTION2: u8 = 0xf5;
+const OPCODE_MODULE_AUX: u8 = 0xf7;
+const OPCODE_IDLE: u8 = 0xf8;
+const OPCODE_FREQ: u8 = 0xf9;
+const OPCODE_AUX: u8 = 0xfa;
+const OPCODE_RESIZEDB: u8 = 0xfb;
+const OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+const OPCODE_EXPIRETIME: u8 = 0xfd;
+const OPCODE_SELECTDB: u8 = 0xfe;
+const OPCODE_EOF: u8 = 0xff;
+
+// Types of the values, each one followed by its encoding
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_MODULE_PRE_GA: u8 = 6;
+const TYPE_MODULE_2: u8 = 7;
+const TYPE_HASH_ZIPMAP: u8 = 9;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+// Containers of the nodes of a quicklist
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+const QUICKLIST_NODE_PACKED: u64 = 2;
+
+// Special encodings of strings, selected by the lower 6 bits of a length
+// whose upper 2 bits are set
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+/// Entry of a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Record {
+    pub(crate) key: Bytes,
+    pub(crate) value: Value,
+    /// Unix time in milliseconds at which the key expires
+    pub(crate) expires_at: Option<u64>,
+}
+
+/// Value of an entry of a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(Bytes),
+    List(Vec<Bytes>),
+    Set(Vec<Bytes>),
+    SortedSet(Vec<(Bytes, f64)>),
+    Hash(Vec<(Bytes, Bytes)>),
+}
+
+impl Value {
+    /// Name of the type of the value, as reported by the `TYPE` command.
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::SortedSet(_) => "zset",
+            Value::Hash(_) => "hash",
+        }
+    }
+
+    /// Type written before the key, selecting the encoding of the value.
+    fn rdb_type(&self) -> u8 {
+        match self {
+            Value::String(_) => TYPE_STRING,
+            Value::List(_) => TYPE_LIST,
+            Value::Set(_) => TYPE_SET,
+            Value::SortedSet(_) => TYPE_ZSET_2,
+            Value::Hash(_) => TYPE_HASH,
+        }
+    }
+}
+
+/// Serialize `records` as an RDB file.
+pub(crate) fn encode(records: &[Record]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let _ = write!(buf, "REDIS{:04}", RDB_VERSION);
+
+    for (name, value) in [
+        ("mini-redis-ver", env!("CARGO_PKG_VERSION")),
+        ("redis-bits", "64"),
+    ] {
+        buf.push(OPCODE_AUX);
+        encode_string(&mut buf, name.as_bytes());
+        encode_string(&mut buf, value.as_bytes());
+    }
+
+    buf.push(OPCODE_SELECTDB);
+    encode_length(&mut buf, 0);
+    buf.push(OPCODE_RESIZEDB);
+    encode_length(&mut buf, records.len() as u64);
+    let expires = records.iter().filter(|r| r.expires_at.is_some()).count();
+    encode_length(&mut buf, expires as u64);
+
+    for record in records {
+        if let Some(expires_at) = record.expires_at {
+            buf.push(OPCODE_EXPIRETIME_MS);
+            buf.extend_from_slice(&expires_at.to_le_bytes());
+        }
+        buf.push(record.value.rdb_type());
+        encode_string(&mut buf, &record.key);
+        encode_value(&mut buf, &record.value);
+    }
+
+    buf.push(OPCODE_EOF);
+    let checksum = crc64(0, &buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf
+}
+
+/// Parse an RDB file into its entries.
+///
+/// Only the entries of database 0 are returned. Streams and functions, which
+/// have no equivalent here, are skipped with a warning, while module values
+/// cannot be skipped and are an error.
+pub(crate) fn decode(data: &[u8]) -> Result<Vec<Record>, String> {
+    let mut reader = Reader::new(data);
+
+    let magic = reader.take(9)?;
+    let version = match (&magic[..5], std::str::from_utf8(&magic[5..])) {
+        (b"REDIS", Ok(version)) => version.parse::<u16>().map_err(|_| "invalid RDB version")?,
+        _ => return Err("not an RDB file".to_string()),
+    };
+    if version > MAX_RDB_VERSION {
+        return Err(format!("unsupported RDB version {}", version));
+    }
+
+    let mut records = vec![];
+    let mut expires_at = None;
+    let mut db = 0;
+    let mut other_dbs = 0;
+    let mut streams = 0;
+    let mut functions = 0;
+    loop {
+        match reader.byte()? {
+            OPCODE_EOF => break,
+            OPCODE_AUX => {
+                reader.string()?;
+                reader.string()?;
+            }
+            OPCODE_SELECTDB => {
+                db = reader.length()?;
+            }
+            OPCODE_RESIZEDB => {
+                reader.length()?;
+                reader.length()?;
+            }
+            OPCODE_EXPIRETIME_MS => {
+                let ms = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+                expires_at = Some(ms);
+            }
+            OPCODE_EXPIRETIME => {
+                let secs = u32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+                expires_at = Some(secs as u64 * 1000);
+            }
+            OPCODE_IDLE => {
+                reader.length()?;
+            }
+            OPCODE_FREQ => {
+                reader.byte()?;
+            }
+            OPCODE_FUNCTION2 => {
+                reader.string()?;
+                functions += 1;
+            }
+            op @ (OPCODE_FUNCTION_PRE_GA | OPCODE_MODULE_AUX) => {
+                return Err(format!("unsupported opcode {:#x}", op));
+            }
+            ty => {
+                let key = reader.string()?;
+                let value = reader.value(ty)?;
+                let expires_at = expires_at.take();
+                match value {
+                    None => streams += 1,
+                    Some(_) if db != 0 => other_dbs += 1,
+                    Some(value) => records.push(Record {
+                        key,
+                        value,
+                        expires_at,
+                    }),
+                }
+            }
+        }
+    }
+
+    if other_dbs > 0 {
+        tracing::warn!("Skipped {} keys of databases other than 0", other_dbs);
+    }
+    if streams > 0 {
+        tracing::warn!("Skipped {} streams, which are not supported", streams);
+    }
+    if functions > 0 {
+        tracing::warn!(
+            "Skipped {} function libraries, which are not supported",
+            functions
+        );
+    }
+
+    // バージョン 5 以降はチェックサムが続く。0 はチェックサムが無効化されていることを表す
+    if version >= 5 {
+        let end = reader.pos;
+        let checksum = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+        if checksum != 0 && checksum != crc64(0, &data[..end]) {
+            return Err("checksum mismatch".to_string());
+        }
+    }
+
+    Ok(records)
+}
+
+/// Serialize `value` as a `DUMP` payload: the type and the value as written
+/// in an RDB file, followed by the RDB version and a CRC64 checksum.
+pub(crate) fn dump_payload(value: &Value) -> Vec<u8> {
+    let mut buf = vec![value.rdb_type()];
+    encode_value(&mut buf, value);
+    buf.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let checksum = crc64(0, &buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf
+}
+
+/// Parse a `DUMP` payload, written by `dump_payload` or by Redis.
+pub(crate) fn restore_payload(payload: &[u8]) -> Result<Value, &'static str> {
+    const WRONG: &str = "DUMP payload version or checksum are wrong";
+
+    let end = payload.len().checked_sub(10).ok_or(WRONG)?;
+    let version = u16::from_le_bytes(payload[end..end + 2].try_into().unwrap());
+    let checksum = u64::from_le_bytes(payload[end + 2..].try_into().unwrap());
+    if version > MAX_RDB_VERSION || checksum != crc64(0, &payload[..end + 2]) {
+        return Err(WRONG);
+    }
+
+    let mut reader = Reader::new(&payload[..end]);
+    let value = reader
+        .byte()
+        .and_then(|ty| reader.value(ty))
+        .map_err(|_| "Bad data format")?;
+    match value {
+        Some(value) if reader.pos == end => Ok(value),
+        _ => Err("Bad data format"),
+    }
+}
+
+/// Write a snapshot of `db` to the dump file, returning once it is written.
+///
+/// Fails while a background save is running, since it could replace the
+/// dump file with its older snapshot once done.
+pub(crate) fn save(db: &dyn Storage, config: &Config, stats: &Stats) -> crate::Result<()> {
+    if stats.bgsave_in_progress.load(Ordering::SeqCst) {
+        return Err("Background save already in progress".into());
+    }
+
+    let changes = stats.changes_since_last_save.load(Ordering::Relaxed);
+    write_file(&config.dump_path(), &records(db))?;
+    saved(stats, changes);
+    Ok(())
+}
+
+/// Write a snapshot of `db` to the dump file in the background.
+///
+/// The entries are copied before returning, so the snapshot reflects the
+/// keyspace at the time of the call. They are then serialized and written on
+/// a blocking thread, without holding up the calling task. Only one
+/// background save runs at a time.
+pub(crate) fn bgsave(
+    db: &dyn Storage,
+    config: &Config,
+    stats: &Arc<Stats>,
+) -> Result<(), &'static str> {
+    if stats.bgsave_in_progress.swap(true, Ordering::SeqCst) {
+        return Err("Background save already in progress");
+    }
+
+    let changes = stats.changes_since_last_save.load(Ordering::Relaxed);
+    let records = records(db);
+    let path = config.dump_path();
+    let stats = stats.clone();
+    tokio::task::spawn_blocking(move || {
+        let result = write_file(&path, &records);
+        match &result {
+            Ok(()) => {
+                saved(&stats, changes);
+                tracing::info!("Background saving terminated with success");
+            }
+            Err(err) => tracing::error!("Background saving error: {}", err),
+        }
+        stats
+            .last_bgsave_ok
+            .store(result.is_ok(), Ordering::Relaxed);
+        stats.bgsave_in_progress.store(false, Ordering::SeqCst);
+    });
+
+    Ok(())
+}
+
+/// Load the snapshot at `path` into `db`.
+///
+/// Keys whose deadline has already passed are skipped, as well as keys of the
+/// types the keyspace cannot hold, which are reported with a warning. Returns
+/// the number of keys loaded.
+pub(crate) fn load(db: &dyn Storage, path: &Path) -> crate::Result<usize> {
+    let records = read(path)?;
+    Ok(insert(db, records, path))
+}
+
+/// Read and decode the snapshot at `path`, without loading it.
+pub(crate) fn read(path: &Path) -> crate::Result<Vec<Record>> {
+    let data = std::fs::read(path)?;
+    let records = decode(&data).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(records)
+}
+
+/// Describe the keys of `records` whose type the keyspace cannot hold, e.g.
+/// `hash (1), list (2)`, or return `None` if there are none.
+pub(crate) fn unsupported(records: &[Record]) -> Option<String> {
+    let mut unsupported = BTreeMap::new();
+    for record in records {
+        if !matches!(record.value, Value::String(_)) {
+            *unsupported.entry(record.value.type_name()).or_insert(0) += 1;
+        }
+    }
+
+    let types: Vec<_> = unsupported
+        .iter()
+        .map(|(ty, n)| format!("{} ({})", ty, n))
+        .collect();
+    (!types.is_empty()).then(|| types.join(", "))
+}
+
+/// Insert `records`, read from the snapshot at `path`, into `db`, as `load`
+/// does.
+pub(crate) fn insert(db: &dyn Storage, records: Vec<Record>, path: &Path) -> usize {
+    if let Some(skipped) = unsupported(&records) {
+        tracing::warn!(
+            "Skipped keys of unsupported types in {}: {}",
+            path.display(),
+            skipped
+        );
+    }
+
+    let mut loaded = 0;
+    for record in records {
+        let value = match record.value {
+            Value::String(value) => value,
+            _ => continue,
+        };
+        let key = match String::from_utf8(record.key.to_vec()) {
+            Ok(key) => key,
+            Err(_) => {
+                tracing::warn!("Skipping key that is not valid UTF-8: {:?}", record.key);
+                continue;
+            }
+        };
+        let expires_at = match record.expires_at.map(clock::from_unix_ms) {
+            Some(None) => continue,
+            Some(when) => when,
+            None => None,
+        };
+
+        db.set(key, value, expires_at, SetCondition::Always);
+        loaded += 1;
+    }
+    loaded
+}
+
+/// Copy the entries of `db`, converting deadlines to Unix time.
+fn records(db: &dyn Storage) -> Vec<Record> {
+    db.snapshot()
+        .into_iter()
+        .map(|(key, value, expires_at)| Record {
+            key: Bytes::from(key),
+            value: Value::String(value),
+            expires_at: expires_at.map(clock::to_unix_ms),
+        })
+        .collect()
+}
+
+/// Record a successful save of the keyspace as it was after `changes` writes.
+fn saved(stats: &Stats, changes: u64) {
+    // 保存中に行われた書き込みは、次の保存の対象として数えたままにする
+    let _ = stats
+        .changes_since_last_save
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+            Some(n.saturating_sub(changes))
+        });
+    stats
+        .last_save_time
+        .store(clock::unix_time(), Ordering::Relaxed);
+}
+
+/// Write `records` to `path` as an RDB file.
+///
+/// The snapshot is written to a temporary file which then replaces `path`, so
+/// a crash while saving never leaves a partial snapshot behind.
+fn write_file(path: &Path, records: &[Record]) -> io::Result<()> {
+    // 同時に実行された保存が同じ一時ファイルに書き込まないように、連番を付ける
+    static SEQ: AtomicU64 = AtomicU64::new(0);
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+    let tmp = path.with_file_name(format!("temp-{}-{}.rdb", std::process::id(), seq));
+
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(&encode(records))?;
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::rename(&tmp, path)
+}
+
+fn encode_length(buf: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        buf.push(len as u8);
+    } else if len < 1 << 14 {
+        buf.extend_from_slice(&(len as u16 | 0x4000).to_be_bytes());
+    } else if len <= u32::MAX as u64 {
+        buf.push(0x80);
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        buf.push(0x81);
+        buf.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+fn encode_string(buf: &mut Vec<u8>, s: &[u8]) {
+    encode_length(buf, s.len() as u64);
+    buf.extend_from_slice(s);
+}
+
+fn encode_value(buf: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::String(s) => encode_string(buf, s),
+        Value::List(items) | Value::Set(items) => {
+            encode_length(buf, items.len() as u64);
+            for item in items {
+                encode_string(buf, item);
+            }
+        }
+        Value::SortedSet(members) => {
+            encode_length(buf, members.len() as u64);
+            for (member, score) in members {
+                encode_string(buf, member);
+                buf.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        Value::Hash(fields) => {
+            encode_length(buf, fields.len() as u64);
+            for (field, value) in fields {
+                encode_string(buf, field);
+                encode_string(buf, value);
+            }
+        }
+    }
+}
+
+/// Pair up the flattened fields and values of a hash.
+fn pairs(items: Vec<Bytes>) -> Result<Vec<(Bytes, Bytes)>, String> {
+    if !items.len().is_multiple_of(2) {
+        return Err("hash with a field missing its value".to_string());
+    }
+    let mut items = items.into_iter();
+    let mut fields = vec![];
+    while let (Some(field), Some(value)) = (items.next(), items.next()) {
+        fields.push((field, value));
+    }
+    Ok(fields)
+}
+
+/// Pair up the flattened members and scores of a sorted set.
+fn scores(items: Vec<Bytes>) -> Result<Vec<(Bytes, f64)>, String> {
+    pairs(items)?
+        .into_iter()
+        .map(|(member, score)| Ok((member, parse_score(&score)?)))
+        .collect()
+}
+
+fn parse_score(s: &[u8]) -> Result<f64, String> {
+    std::str::from_utf8(s)
+        .ok()
+        .and_then(|s| match s {
+            "inf" | "+inf" => Some(f64::INFINITY),
+            "-inf" => Some(f64::NEG_INFINITY),
+            s => s.parse().ok(),
+        })
+        .ok_or_else(|| format!("invalid score {:?}", Bytes::copy_from_slice(s)))
+}
+
+/// Length prefix of an RDB string.
+enum Length {
+    Len(u64),
+    /// The string is stored with a special encoding, e.g. as an integer
+    Encoded(u8),
+}
+
+/// Cursor over the bytes of an RDB file.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.data.len() - self.pos < n {
+            return Err("unexpected end of file".to_string());
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn length_or_encoding(&mut self) -> Result<Length, String> {
+        let first = self.byte()?;
+        let len = match first >> 6 {
+            0 => (first & 0x3f) as u64,
+            1 => ((first & 0x3f) as u64) << 8 | self.byte()? as u64,
+            2 if first == 0x80 => u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64,
+            2 if first == 0x81 => u64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+            2 => return Err(format!("invalid length encoding {:#x}", first)),
+            _ => return Ok(Length::Encoded(first & 0x3f)),
+        };
+        Ok(Length::Len(len))
+    }
+
+    fn length(&mut self) -> Result<u64, String> {
+        match self.length_or_encoding()? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(_) => Err("unexpected string encoding".to_string()),
+        }
+    }
+
+    fn string(&mut self) -> Result<Bytes, String> {
+        let n = match self.length_or_encoding()? {
+            Length::Len(len) => {
+                let len = usize::try_from(len).map_err(|_| "string too long")?;
+                return Ok(Bytes::copy_from_slice(self.take(len)?));
+            }
+            Length::Encoded(ENC_INT8) => self.take(1)?[0] as i8 as i64,
+            Length::Encoded(ENC_INT16) => {
+                i16::from_le_bytes(self.take(2)?.try_into().unwrap()) as i64
+            }
+            Length::Encoded(ENC_INT32) => {
+                i32::from_le_bytes(self.take(4)?.try_into().unwrap()) as i64
+            }
+            Length::Encoded(ENC_LZF) => {
+                let compressed = self.length()? as usize;
+                let len = self.length()? as usize;
+                let data = lzf::decompress(self.take(compressed)?, len)?;
+                return Ok(Bytes::from(data));
+            }
+            Length::Encoded(enc) => return Err(format!("unsupported string encoding {}", enc)),
+        };
+        Ok(Bytes::from(n.to_string()))
+    }
+
+    fn strings(&mut self) -> Result<Vec<Bytes>, String> {
+        let mut items = vec![];
+        for _ in 0..self.length()? {
+            items.push(self.string()?);
+        }
+        Ok(items)
+    }
+
+    /// Read a score of a sorted set of type `TYPE_ZSET`, stored as a string
+    /// prefixed with its length, or as one of the special lengths below.
+    fn string_score(&mut self) -> Result<f64, String> {
+        match self.byte()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => parse_score(self.take(len as usize)?),
+        }
+    }
+
+    /// Read a value of type `ty`, or skip it and return `None` if it is a
+    /// stream.
+    fn value(&mut self, ty: u8) -> Result<Option<Value>, String> {
+        let value = match ty {
+            TYPE_STRING => Value::String(self.string()?),
+            TYPE_LIST => Value::List(self.strings()?),
+            TYPE_SET => Value::Set(self.strings()?),
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let mut members = vec![];
+                for _ in 0..self.length()? {
+                    let member = self.string()?;
+                    let score = match ty {
+                        TYPE_ZSET => self.string_score()?,
+                        _ => f64::from_le_bytes(self.take(8)?.try_into().unwrap()),
+                    };
+                    members.push((member, score));
+                }
+                Value::SortedSet(members)
+            }
+            TYPE_HASH => {
+                let mut fields = vec![];
+                for _ in 0..self.length()? {
+                    fields.push((self.string()?, self.string()?));
+                }
+                Value::Hash(fields)
+            }
+            TYPE_HASH_ZIPMAP => Value::Hash(packed::zipmap(&self.string()?)?),
+            TYPE_LIST_ZIPLIST => Value::List(packed::ziplist(&self.string()?)?),
+            TYPE_SET_INTSET => Value::Set(packed::intset(&self.string()?)?),
+            TYPE_ZSET_ZIPLIST => Value::SortedSet(scores(packed::ziplist(&self.string()?)?)?),
+            TYPE_HASH_ZIPLIST => Value::Hash(pairs(packed::ziplist(&self.string()?)?)?),
+            TYPE_LIST_QUICKLIST => {
+                let mut items = vec![];
+                for _ in 0..self.length()? {
+                    items.extend(packed::ziplist(&self.string()?)?);
+                }
+                Value::List(items)
+            }
+            TYPE_HASH_LISTPACK => Value::Hash(pairs(packed::listpack(&self.string()?)?)?),
+            TYPE_ZSET_LISTPACK => Value::SortedSet(scores(packed::listpack(&self.string()?)?)?),
+            TYPE_LIST_QUICKLIST_2 => {
+                let mut items = vec![];
+                for _ in 0..self.length()? {
+                    let container = self.length()?;
+                    let data = self.string()?;
+                    match container {
+                        // 大きな要素は listpack に詰めずにそのまま保存される
+                        QUICKLIST_NODE_PLAIN => items.push(data),
+                        QUICKLIST_NODE_PACKED => items.extend(packed::listpack(&data)?),
+                        _ => return Err(format!("invalid quicklist container {}", container)),
+                    }
+                }
+                Value::List(items)
+            }
+            TYPE_SET_LISTPACK => Value::Set(packed::listpack(&self.string()?)?),
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                self.skip_stream(ty)?;
+                return Ok(None);
+            }
+            TYPE_MODULE_PRE_GA | TYPE_MODULE_2 => {
+                return Err("module values are not supported".to_string())
+            }
+            ty => return Err(format!("unknown value type {}", ty)),
+        };
+        Ok(Some(value))
+    }
+
+    /// Skip a stream, whose layout changed with each type.
+    fn skip_stream(&mut self, ty: u8) -> Result<(), String> {
+        // エントリを詰めた listpack と、その先頭の ID
+        for _ in 0..self.length()? {
+            self.string()?;
+            self.string()?;
+        }
+        // 長さと最後の ID。新しい形式では最初の ID、削除された最大の ID、追加された数が続く
+        let fields = if ty == TYPE_STREAM_LISTPACKS { 3 } else { 8 };
+        for _ in 0..fields {
+            self.length()?;
+        }
+
+        for _ in 0..self.length()? {
+            // コンシューマーグループの名前と最後に配信した ID
+            self.string()?;
+            self.length()?;
+            self.length()?;
+            if ty != TYPE_STREAM_LISTPACKS {
+                self.length()?;
+            }
+            // 保留中のエントリの ID、配信時刻、配信回数
+            for _ in 0..self.length()? {
+                self.take(16 + 8)?;
+                self.length()?;
+            }
+            // コンシューマーの名前、時刻、保留中のエントリの ID
+            for _ in 0..self.length()? {
+                self.string()?;
+                self.take(8)?;
+                if ty == TYPE_STREAM_LISTPACKS_3 {
+                    self.take(8)?;
+                }
+                for _ in 0..self.length()? {
+                    self.take(16)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lookup table of `crc64`, computed at compile time.
+const CRC64_TABLE: [u64; 256] = {
+    // Jones polynomial, reflected
+    const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-64/Jones checksum of `data`, as used by Redis for RDB files and `DUMP`
+/// payloads. `crc` is the checksum of the preceding data, 0 initially.
+pub(crate) fn crc64(crc: u64, data: &[u8]) -> u64 {
+    data.iter().fold(crc, |crc, &b| {
+        CRC64_TABLE[((crc ^ b as u64) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn save_is_refused_during_bgsave() {
+        let stats = Stats::default();
+        stats.bgsave_in_progress.store(true, Ordering::SeqCst);
+        let err = save(&crate::db::Db::default(), &Config::default(), &stats).unwrap_err();
+        assert_eq!(err.to_string(), "Background save already in progress");
+    }
+
+    #[test]
+    fn crc64_matches_redis() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let records = vec![
+            Record {
+                key: Bytes::from("small"),
+                value: Value::String(Bytes::from("value")),
+                expires_at: None,
+            },
+            Record {
+                key: Bytes::from("large"),
+                value: Value::String(Bytes::from(vec![b'x'; 20_000])),
+                expires_at: Some(1_700_000_000_000),
+            },
+            Record {
+                key: Bytes::from("list"),
+                value: Value::List(vec![Bytes::from("a"), Bytes::from("b")]),
+                expires_at: None,
+            },
+            Record {
+                key: Bytes::from("zset"),
+                value: Value::SortedSet(vec![(Bytes::from("m"), -1.5)]),
+                expires_at: None,
+            },
+            Record {
+                key: Bytes::from("hash"),
+                value: Value::Hash(vec![(Bytes::from("f"), Bytes::from("v"))]),
+                expires_at: None,
+            },
+        ];
+
+        let data = encode(&records);
+        assert_eq!(&data[..9], b"REDIS0009");
+        assert_eq!(decode(&data).unwrap(), records);
+
+        // A corrupted byte is detected by the checksum
+        let mut corrupted = data.clone();
+        corrupted[40] ^= 1;
+        assert!(decode(&corrupted).is_err());
+
+        // A truncated file is an error
+        assert!(decode(&data[..data.len() - 9]).is_err());
+    }
+
+    #[test]
+    fn decode_integer_encoded_strings() {
+        // Written by Redis for `SET n 1000`
+        let mut data = b"REDIS0009\xfe\x00\x00\x01n\xc1\xe8\x03\xff".to_vec();
+        let checksum = crc64(0, &data);
+        data.extend_from_slice(&checksum.to_le_bytes());
+
+        let records = decode(&data).unwrap();
+        assert_eq!(records[0].key, "n");
+        assert_eq!(records[0].value, Value::String(Bytes::from("1000")));
+    }
+
+    #[test]
+    fn dump_payloads() {
+        // Returned by Redis for `DUMP mykey` after `SET mykey 10`
+        let payload = b"\x00\xc0\n\t\x00\xbem\x06\x89Z(\x00\n";
+        assert_eq!(
+            restore_payload(payload),
+            Ok(Value::String(Bytes::from("10")))
+        );
+
+        let value = Value::Hash(vec![(Bytes::from("f"), Bytes::from("v"))]);
+        let mut payload = dump_payload(&value);
+        assert_eq!(restore_payload(&payload), Ok(value));
+
+        payload[1] ^= 1;
+        assert!(restore_payload(&payload).is_err());
+
+        // LZF-compressed strings claiming a huge length once decompressed
+        for len in [u64::MAX, 1 << 45] {
+            let mut payload = b"\x00\xc3\x02\x81".to_vec();
+            payload.extend_from_slice(&len.to_be_bytes());
+            payload.extend_from_slice(b"\x00a\x0a\x00");
+            let checksum = crc64(0, &payload);
+            payload.extend_from_slice(&checksum.to_le_bytes());
+            assert_eq!(restore_payload(&payload), Err("Bad data format"));
+        }
+    }
+
+    #[test]
+    fn decode_redis_encodings() {
+        // A listpack holding "f", "v", "n", 1
+        let listpack = b"\x12\x00\x00\x00\x04\x00\x81f\x02\x81v\x02\x81n\x02\x01\x01\xff";
+
+        let mut data = b"REDIS0011\xfa\x09redis-ver\x057.2.4\xfe\x00\xfb\x06\x01".to_vec();
+        // An LZF-compressed string of 20 "a", expiring in 2096
+        data.extend_from_slice(b"\xfd\x00\x28\x6b\xee\x00\x03lzf\xc3\x05\x14\x00a\xe0\x0a\x00");
+        data.extend_from_slice(b"\x10\x04hash\x12");
+        data.extend_from_slice(listpack);
+        data.extend_from_slice(b"\x12\x04list\x02\x02\x12");
+        data.extend_from_slice(listpack);
+        data.extend_from_slice(b"\x01\x03big");
+        data.extend_from_slice(b"\x0b\x03set\x0c\x02\x00\x00\x00\x02\x00\x00\x00\x01\x00\x02\x00");
+        data.extend_from_slice(b"\x03\x04zset\x01\x01m\x031.5");
+        // An empty stream, then a key of database 1, both skipped
+        data.extend_from_slice(b"\x15\x06stream\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        data.extend_from_slice(b"\xfe\x01\x00\x05other\x01x\xff");
+        let checksum = crc64(0, &data);
+        data.extend_from_slice(&checksum.to_le_bytes());
+
+        let records = decode(&data).unwrap();
+        let b = |s: &'static str| Bytes::from(s);
+        let values: Vec<_> = records
+            .iter()
+            .map(|r| (r.key.clone(), r.value.clone()))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                (b("lzf"), Value::String(Bytes::from(vec![b'a'; 20]))),
+                (
+                    b("hash"),
+                    Value::Hash(vec![(b("f"), b("v")), (b("n"), b("1"))])
+                ),
+                (
+                    b("list"),
+                    Value::List(vec![b("f"), b("v"), b("n"), b("1"), b("big")])
+                ),
+                (b("set"), Value::Set(vec![b("1"), b("2")])),
+                (b("zset"), Value::SortedSet(vec![(b("m"), 1.5)])),
+            ]
+        );
+        assert_eq!(records[0].expires_at, Some(4_000_000_000_000));
+    }
+}
@@ -0,0 +1,76 @@
+//! Decompression of the LZF-compressed strings of RDB files.
+
+/// Decompress `input`, which holds `len` bytes once decompressed.
+///
+/// The input is a sequence of chunks, each starting with a control byte:
+/// below 32, it is followed by `ctrl + 1` literal bytes. Otherwise it is a
+/// back reference copying bytes already decompressed, whose length is held
+/// by the upper 3 bits, or by the next byte if they are all set, and whose
+/// offset is held by the lower 5 bits and the following byte.
+pub(super) fn decompress(input: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    let truncated = || "truncated LZF string".to_string();
+
+    // 長さは入力に書かれた値なので、圧縮後の長さから展開しうる長さを超える場合は
+    // 確保する前にエラーとする
+    if len > input.len().saturating_mul(255).saturating_add(64) {
+        return Err(format!("LZF string of {} bytes too long", len));
+    }
+
+    let mut out = Vec::with_capacity(len);
+    let mut pos = 0;
+    while pos < input.len() {
+        let ctrl = input[pos] as usize;
+        pos += 1;
+
+        if ctrl < 32 {
+            let literal = input.get(pos..pos + ctrl + 1).ok_or_else(truncated)?;
+            out.extend_from_slice(literal);
+            pos += literal.len();
+        } else {
+            let mut n = ctrl >> 5;
+            if n == 7 {
+                n += *input.get(pos).ok_or_else(truncated)? as usize;
+                pos += 1;
+            }
+            let offset = ((ctrl & 0x1f) << 8) + *input.get(pos).ok_or_else(truncated)? as usize + 1;
+            pos += 1;
+
+            let start = out
+                .len()
+                .checked_sub(offset)
+                .ok_or("invalid LZF back reference")?;
+            // 参照先とコピー先が重なることがあるので、1 バイトずつコピーする
+            for i in 0..n + 2 {
+                out.push(out[start + i]);
+            }
+        }
+
+        if out.len() > len {
+            break;
+        }
+    }
+
+    if out.len() != len {
+        return Err(format!(
+            "LZF string decompressed to {} bytes instead of {}",
+            out.len(),
+            len
+        ));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literals_and_back_references() {
+        // "abc" as a literal, then 19 bytes copied from 3 bytes back
+        let input = b"\x02abc\xe0\x0a\x02";
+        assert_eq!(decompress(input, 22).unwrap(), b"abcabcabcabcabcabcabca");
+
+        assert!(decompress(input, 21).is_err());
+        assert!(decompress(b"\xe0\x0a\x02", 19).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+//! Decoders of the compact encodings Redis uses to store small collections
+//! as a single RDB string: ziplists, listpacks, intsets and zipmaps.
+//!
+//! Integers stored in these encodings are returned as their decimal string
+//! representation, as Redis does when reading them.
+
+use super::Reader;
+
+use bytes::Bytes;
+
+/// Decode the elements of a ziplist.
+///
+/// A ziplist starts with its size in bytes, the offset of its last entry and
+/// its number of entries, and ends with `0xff`. Each entry holds the length
+/// of the previous entry, an encoding byte and the data.
+pub(super) fn ziplist(data: &[u8]) -> Result<Vec<Bytes>, String> {
+    let mut reader = Reader::new(data);
+    reader.take(10)?;
+
+    let mut items = vec![];
+    loop {
+        match reader.byte()? {
+            0xff => break,
+            0xfe => {
+                reader.take(4)?;
+            }
+            _ => {}
+        }
+
+        let encoding = reader.byte()?;
+        let len = match encoding >> 6 {
+            0 => (encoding & 0x3f) as usize,
+            1 => ((encoding & 0x3f) as usize) << 8 | reader.byte()? as usize,
+            2 => u32::from_be_bytes(reader.take(4)?.try_into().unwrap()) as usize,
+            _ => {
+                let n = match encoding {
+                    0xc0 => i16::from_le_bytes(reader.take(2)?.try_into().unwrap()) as i64,
+                    0xd0 => i32::from_le_bytes(reader.take(4)?.try_into().unwrap()) as i64,
+                    0xe0 => i64::from_le_bytes(reader.take(8)?.try_into().unwrap()),
+                    0xf0 => int24(reader.take(3)?),
+                    0xfe => reader.byte()? as i8 as i64,
+                    // 4 ビットの即値 (1 から 13) で 0 から 12 を表す
+                    0xf1..=0xfd => (encoding & 0x0f) as i64 - 1,
+                    _ => return Err(format!("invalid ziplist encoding {:#x}", encoding)),
+                };
+                items.push(Bytes::from(n.to_string()));
+                continue;
+            }
+        };
+        items.push(Bytes::copy_from_slice(reader.take(len)?));
+    }
+
+    Ok(items)
+}
+
+/// Decode the elements of a listpack.
+///
+/// A listpack starts with its size in bytes and its number of elements, and
+/// ends with `0xff`. Each element holds an encoding byte, the data, and the
+/// size of both, used to iterate backwards.
+pub(super) fn listpack(data: &[u8]) -> Result<Vec<Bytes>, String> {
+    let mut reader = Reader::new(data);
+    reader.take(6)?;
+
+    let mut items = vec![];
+    loop {
+        let start = reader.pos;
+        let encoding = reader.byte()?;
+        let item = match encoding {
+            0xff => break,
+            0x00..=0x7f => Ok(encoding as i64),
+            0x80..=0xbf => Err(reader.take((encoding & 0x3f) as usize)?),
+            0xc0..=0xdf => {
+                let n = ((encoding & 0x1f) as i64) << 8 | reader.byte()? as i64;
+                // 13 ビットの符号付き整数
+                Ok(if n >= 1 << 12 { n - (1 << 13) } else { n })
+            }
+            0xe0..=0xef => {
+                let len = ((encoding & 0x0f) as usize) << 8 | reader.byte()? as usize;
+                Err(reader.take(len)?)
+            }
+            0xf0 => {
+                let len = u32::from_le_bytes(reader.take(4)?.try_into().unwrap()) as usize;
+                Err(reader.take(len)?)
+            }
+            0xf1 => Ok(i16::from_le_bytes(reader.take(2)?.try_into().unwrap()) as i64),
+            0xf2 => Ok(int24(reader.take(3)?)),
+            0xf3 => Ok(i32::from_le_bytes(reader.take(4)?.try_into().unwrap()) as i64),
+            0xf4 => Ok(i64::from_le_bytes(reader.take(8)?.try_into().unwrap())),
+            _ => return Err(format!("invalid listpack encoding {:#x}", encoding)),
+        };
+        items.push(match item {
+            Ok(n) => Bytes::from(n.to_string()),
+            Err(s) => Bytes::copy_from_slice(s),
+        });
+
+        let len = reader.pos - start;
+        let backlen = match len {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        reader.take(backlen)?;
+    }
+
+    Ok(items)
+}
+
+/// Decode the elements of an intset: the size of its integers (2, 4 or 8
+/// bytes), their number, then the integers in ascending order.
+pub(super) fn intset(data: &[u8]) -> Result<Vec<Bytes>, String> {
+    let mut reader = Reader::new(data);
+    let size = u32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+    let len = u32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+
+    let mut items = vec![];
+    for _ in 0..len {
+        let n = match size {
+            2 => i16::from_le_bytes(reader.take(2)?.try_into().unwrap()) as i64,
+            4 => i32::from_le_bytes(reader.take(4)?.try_into().unwrap()) as i64,
+            8 => i64::from_le_bytes(reader.take(8)?.try_into().unwrap()),
+            _ => return Err(format!("invalid intset encoding {}", size)),
+        };
+        items.push(Bytes::from(n.to_string()));
+    }
+
+    Ok(items)
+}
+
+/// Decode the fields of a zipmap, the encoding of small hashes used before
+/// Redis 2.6.
+///
+/// A zipmap starts with its number of entries and ends with `0xff`. Each
+/// entry holds the field, the value and unused bytes following the value.
+pub(super) fn zipmap(data: &[u8]) -> Result<Vec<(Bytes, Bytes)>, String> {
+    fn len(reader: &mut Reader<'_>) -> Result<Option<usize>, String> {
+        match reader.byte()? {
+            0xff => Ok(None),
+            0xfe => Ok(Some(
+                u32::from_le_bytes(reader.take(4)?.try_into().unwrap()) as usize,
+            )),
+            n => Ok(Some(n as usize)),
+        }
+    }
+
+    let mut reader = Reader::new(data);
+    reader.byte()?;
+
+    let mut fields = vec![];
+    while let Some(field_len) = len(&mut reader)? {
+        let field = Bytes::copy_from_slice(reader.take(field_len)?);
+        let value_len = len(&mut reader)?.ok_or("truncated zipmap")?;
+        let free = reader.byte()? as usize;
+        let value = Bytes::copy_from_slice(reader.take(value_len)?);
+        reader.take(free)?;
+        fields.push((field, value));
+    }
+
+    Ok(fields)
+}
+
+/// Read a little endian, signed, 24 bit integer.
+fn int24(bytes: &[u8]) -> i64 {
+    (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wrap listpack entries with the header and the terminator.
+    fn wrap_listpack(entries: &[u8], count: u16) -> Vec<u8> {
+        let mut data = ((entries.len() + 7) as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&count.to_le_bytes());
+        data.extend_from_slice(entries);
+        data.push(0xff);
+        data
+    }
+
+    #[test]
+    fn listpack_entries() {
+        let entries = [
+            &b"\x81f\x02"[..],       // "f"
+            b"\x05\x01",             // 5, 7 bit integer
+            b"\xdf\xff\x02",         // -1, 13 bit integer
+            b"\xf1\xe8\x03\x03",     // 1000, 16 bit integer
+            b"\xf2\x00\x00\x80\x04", // -8388608, 24 bit integer
+            b"\xe0\x01z\x03",        // "z", 12 bit length
+        ]
+        .concat();
+        let items = listpack(&wrap_listpack(&entries, 6)).unwrap();
+        assert_eq!(items, ["f", "5", "-1", "1000", "-8388608", "z"]);
+    }
+
+    #[test]
+    fn ziplist_entries() {
+        let mut data = vec![0; 10];
+        data.extend_from_slice(b"\x00\x01a"); // "a"
+        data.extend_from_slice(b"\x03\xf6"); // 5, immediate
+        data.extend_from_slice(b"\x02\xc0\x2c\x01"); // 300, 16 bit integer
+        data.extend_from_slice(b"\x04\xfe\xfe"); // -2, 8 bit integer
+        data.push(0xff);
+        assert_eq!(ziplist(&data).unwrap(), ["a", "5", "300", "-2"]);
+    }
+
+    #[test]
+    fn intset_and_zipmap_entries() {
+        let data = b"\x02\x00\x00\x00\x03\x00\x00\x00\xff\xff\x01\x00\x02\x00";
+        assert_eq!(intset(data).unwrap(), ["-1", "1", "2"]);
+
+        let data = b"\x01\x01f\x02\x01ab!\xff";
+        assert_eq!(
+            zipmap(data).unwrap(),
+            [(Bytes::from("f"), Bytes::from("ab"))]
+        );
+    }
+}
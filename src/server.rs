@@ -1,112 +1,734 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+#[cfg(test)]
+pub(crate) mod testing;
 
-use crate::connection::{Connection, ConnectionTrait};
-use mini_redis::{
-    Command::{self, Get, Set},
-    Frame,
-};
-use tokio::net::{TcpListener, TcpStream};
+use crate::acl::{Acl, Denied};
+use crate::aof::{self, Aof};
+use crate::clock;
+use crate::command::Command;
+use crate::config::Config;
+use crate::connection::{Connection, ConnectionKind, ConnectionTrait, Stream};
+use crate::connection_raw;
+use crate::frame::{self, Frame};
+use crate::listener::{self, BoxListener, Listener, Listeners};
+use crate::rdb;
+use crate::session::Session;
+use crate::shutdown::Shutdown;
+use crate::stats::Stats;
+use crate::storage::Storage;
+use crate::tls::{self, TlsListener};
+use clap::ValueEnum;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::time;
+use tokio_rustls::rustls::ServerConfig;
 
-type DbInternal = HashMap<String, Vec<u8>>;
-type Db = Arc<Mutex<DbInternal>>;
+/// Default maximum number of concurrent connections the server accepts.
+///
+/// When this limit is reached, new clients receive an error reply and are
+/// disconnected.
+pub const MAX_CLIENTS: usize = 10000;
 
 pub struct MiniRedisServer {
     pub addr: String,
-    db: Db,
+    /// Whether `run` binds `addr`. Disabled to serve only the Unix socket.
+    tcp: bool,
+    /// Path of the Unix domain socket `run` binds in addition to `addr`, if
+    /// any.
+    unix_socket: Option<PathBuf>,
+    /// Permissions of the Unix domain socket, e.g. `0o770`. When `None`, the
+    /// permissions are left to the process umask.
+    unix_socket_perm: Option<u32>,
+    /// Address `run` serves TLS clients on, with the TLS configuration, if
+    /// any. See `tls::server_config`.
+    tls: Option<(String, Arc<ServerConfig>)>,
+    /// Users clients authenticate as, shared by all connections.
+    acl: Arc<Acl>,
+    /// Runtime configuration, shared by all connections.
+    ///
+    /// `maxclients` is read each time a connection is accepted, so that
+    /// `CONFIG SET maxclients` applies to the following connections.
+    config: Arc<Config>,
+    /// Statistics reported by `INFO`, shared by all connections.
+    stats: Arc<Stats>,
+    /// Append-only file write commands are logged to, if enabled.
+    aof: Option<Arc<Aof>>,
+    /// `ConnectionTrait` implementation used for accepted sockets.
+    connection_kind: ConnectionKind,
+    /// Shared keyspace handle.
+    ///
+    /// Every connection task holds a clone of the `Arc` and executes its
+    /// commands against it.
+    storage: Arc<dyn Storage>,
+}
+
+/// A connected client, counted in `connected_clients` while it holds one of
+/// the `maxclients` permits.
+///
+/// Both are released when the client is dropped, even if its connection task
+/// panics.
+struct Client {
+    _permit: OwnedSemaphorePermit,
+    stats: Arc<Stats>,
+}
+
+impl Client {
+    fn new(permit: OwnedSemaphorePermit, stats: Arc<Stats>) -> Client {
+        stats.connected_clients.fetch_add(1, Ordering::Relaxed);
+        Client {
+            _permit: permit,
+            stats,
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.stats.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl MiniRedisServer {
-    pub fn new(addr: String) -> Self {
-        let db = Arc::new(Mutex::new(HashMap::new()));
-        Self { addr, db }
+    /// Create a new server listening on `addr` and serving the keys held by
+    /// `storage`, e.g. an in-memory `Db`.
+    pub fn new(addr: String, storage: impl Storage) -> Self {
+        Self {
+            addr,
+            tcp: true,
+            unix_socket: None,
+            unix_socket_perm: None,
+            tls: None,
+            acl: Arc::new(Acl::default()),
+            config: Arc::new(Config::default()),
+            stats: Arc::new(Stats::default()),
+            aof: None,
+            connection_kind: ConnectionKind::default(),
+            storage: Arc::new(storage),
+        }
+    }
+
+    /// Create a new server serving the keys held by `storage` as set up by
+    /// `config`, e.g. loaded by `Config::load`.
+    ///
+    /// The keys persisted by a previous run are loaded into `storage`: from
+    /// the append-only file if `appendonly` is enabled and the file exists,
+    /// otherwise from the dump file (`dbfilename` in `dir`) if it exists.
+    pub fn from_config(config: Config, storage: impl Storage) -> crate::Result<Self> {
+        let aof_path = config.aof_path();
+        let dump = config.dump_path();
+        if let Some(path) = aof_path.as_ref().filter(|path| path.exists()) {
+            let replayed = aof::load(&storage, path)?;
+            tracing::info!("DB loaded from append only file: {} commands", replayed);
+        } else if dump.exists() {
+            let loaded = rdb::load(&storage, &dump)?;
+            tracing::info!("DB loaded from disk: {} keys", loaded);
+        }
+
+        let addr = format!(
+            "{}:{}",
+            config.get_as::<String>("bind"),
+            config.get_as::<u16>("port")
+        );
+        let connection_kind =
+            ConnectionKind::from_str(&config.get_as::<String>("connection"), false)?;
+
+        let mut server = MiniRedisServer::new(addr, storage).with_connection_kind(connection_kind);
+        if config.get_as::<u16>("port") == 0 {
+            server = server.without_tcp();
+        }
+        if let Some(path) = config.get_path("unixsocket") {
+            server = server.with_unix_socket(path, config.get_octal("unixsocketperm"));
+        }
+
+        let tls_port = config.get_as::<u16>("tls-port");
+        if tls_port != 0 {
+            let (cert, key) = match (
+                config.get_path("tls-cert-file"),
+                config.get_path("tls-key-file"),
+            ) {
+                (Some(cert), Some(key)) => (cert, key),
+                _ => return Err("tls-port requires tls-cert-file and tls-key-file".into()),
+            };
+            let tls =
+                tls::server_config(&cert, &key, config.get_path("tls-ca-cert-file").as_deref())?;
+            let addr = format!("{}:{}", config.get_as::<String>("bind"), tls_port);
+            server = server.with_tls(addr, tls);
+        }
+
+        let requirepass = config.get_as::<String>("requirepass");
+        if let Some(path) = config.get_path("aclfile") {
+            if !requirepass.is_empty() {
+                return Err("requirepass cannot be combined with aclfile, set the password of the default user in the ACL file".into());
+            }
+            server = server.with_acl(Acl::load(&path)?);
+        }
+        if !requirepass.is_empty() {
+            server = server.with_requirepass(requirepass);
+        }
+
+        server.config = Arc::new(config);
+        if let Some(path) = aof_path {
+            server.aof = Some(Aof::open(path, server.config.clone(), &*server.storage)?);
+        }
+        Ok(server)
+    }
+
+    /// Load the keys of the RDB file at `path`, e.g. written by Redis 5 to 7.2,
+    /// into the keyspace, replacing keys of the same name.
+    ///
+    /// The keyspace only holds strings, so keys of other types are skipped with
+    /// a warning, as `DEBUG RELOAD` does. With `strict`, the file is instead
+    /// rejected before any key is loaded if it holds such keys. The loaded keys
+    /// count as changes for the save points, and are written to the AOF, if
+    /// enabled, by rewriting it in the background. Returns the number of keys
+    /// loaded.
+    pub fn import_rdb(&self, path: &Path, strict: bool) -> crate::Result<usize> {
+        let records = rdb::read(path)?;
+        if let (Some(skipped), true) = (rdb::unsupported(&records), strict) {
+            return Err(format!(
+                "{} holds keys of unsupported types: {}",
+                path.display(),
+                skipped
+            )
+            .into());
+        }
+
+        let loaded = rdb::insert(&*self.storage, records, path);
+        self.stats
+            .changes_since_last_save
+            .fetch_add(loaded as u64, Ordering::Relaxed);
+        if let Some(aof) = &self.aof {
+            aof.bgrewrite(&*self.storage, &self.stats)?;
+        }
+        tracing::info!("Imported {} keys from {}", loaded, path.display());
+        Ok(loaded)
+    }
+
+    /// Set the maximum number of clients connected at the same time.
+    pub fn with_max_clients(self, max_clients: usize) -> Self {
+        // 値の範囲は呼び出し側が保証する
+        self.config
+            .set("maxclients", max_clients.to_string())
+            .expect("invalid maxclients");
+        self
+    }
+
+    /// Set the `ConnectionTrait` implementation used to serve clients.
+    pub fn with_connection_kind(mut self, connection_kind: ConnectionKind) -> Self {
+        self.connection_kind = connection_kind;
+        self
+    }
+
+    /// Also serve clients on the Unix domain socket at `path`.
+    ///
+    /// If `perm` is set, the socket file gets these permissions once bound.
+    pub fn with_unix_socket(mut self, path: impl Into<PathBuf>, perm: Option<u32>) -> Self {
+        self.unix_socket = Some(path.into());
+        self.unix_socket_perm = perm;
+        self
+    }
+
+    /// Also serve TLS clients on `addr`, using `config`.
+    pub fn with_tls(mut self, addr: String, config: Arc<ServerConfig>) -> Self {
+        self.tls = Some((addr, config));
+        self
+    }
+
+    /// Require clients to authenticate with `AUTH password` before running
+    /// commands, by setting the password of the `default` user.
+    pub fn with_requirepass(self, password: String) -> Self {
+        let _ = self.config.set("requirepass", password.clone());
+        let rules = ["resetpass".to_string(), format!(">{}", password)];
+        // どちらのルールも常に有効なので、失敗することはない
+        self.acl.set_user("default", &rules).unwrap();
+        self
+    }
+
+    /// Set the ACL users clients authenticate as, e.g. loaded by `Acl::load`.
+    pub fn with_acl(mut self, acl: Acl) -> Self {
+        self.acl = Arc::new(acl);
+        self
+    }
+
+    /// Do not bind `addr`, only serve the Unix domain socket.
+    pub fn without_tcp(mut self) -> Self {
+        self.tcp = false;
+        self
+    }
+
+    /// Bind `addr`, the Unix domain socket and the TLS address, if any, and
+    /// serve clients until `shutdown` completes.
+    ///
+    /// `shutdown` is typically `tokio::signal::ctrl_c()`, but any future can be
+    /// used, e.g. a `oneshot::Receiver` in tests. See `serve` for details.
+    pub async fn run(&self, shutdown: impl Future) -> crate::Result<()> {
+        let mut listeners = Vec::new();
+        if self.tcp {
+            listeners.push(listener::boxed(TcpListener::bind(&self.addr).await?));
+        }
+        if let Some(path) = &self.unix_socket {
+            listeners.push(self.bind_unix_socket(path)?);
+        }
+        if let Some((addr, config)) = &self.tls {
+            let listener = TcpListener::bind(addr).await?;
+            listeners.push(listener::boxed(TlsListener::new(listener, config.clone())));
+        }
+        if listeners.is_empty() {
+            return Err(
+                "not listening on any address, set a port, a TLS port or a unix socket".into(),
+            );
+        }
+
+        let result = self.serve(Listeners(listeners), shutdown).await;
+
+        // 次回の起動時に bind できるように、ソケットファイルを削除しておく
+        if let Some(path) = &self.unix_socket {
+            let _ = std::fs::remove_file(path);
+        }
+
+        result
+    }
+
+    #[cfg(unix)]
+    fn bind_unix_socket(&self, path: &std::path::Path) -> crate::Result<BoxListener> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        // 前回の実行で残ったソケットファイルがあると bind に失敗するので削除する
+        // ソケット以外のファイルは誤って消さないように、そのまま bind のエラーにする
+        if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+
+        let listener = tokio::net::UnixListener::bind(path)?;
+        if let Some(perm) = self.unix_socket_perm {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(perm))?;
+        }
+
+        Ok(listener::boxed(listener))
+    }
+
+    #[cfg(not(unix))]
+    fn bind_unix_socket(&self, _path: &std::path::Path) -> crate::Result<BoxListener> {
+        Err("unix sockets are not supported on this platform".into())
+    }
+
+    /// Serve clients accepted from `listener` until `shutdown` completes.
+    ///
+    /// `listener` is typically a `TcpListener`, but connections can be served
+    /// over any transport implementing `Listener`.
+    ///
+    /// Once `shutdown` completes the server stops accepting connections and
+    /// notifies every connection task. Each task finishes the command it is
+    /// currently executing, then closes its connection. This function returns
+    /// once all connection tasks have exited.
+    pub async fn serve(
+        &self,
+        mut listener: impl Listener,
+        shutdown: impl Future,
+    ) -> crate::Result<()> {
+        tracing::info!("Listening on {}", listener.local_addr()?);
+
+        // シャットダウンを通知するための broadcast チャネル
+        // 各コネクションタスクは receiver を保持し、値が送信される (または sender が drop される) とシャットダウンを開始する
+        let (notify_shutdown, _) = broadcast::channel(1);
+
+        // すべてのコネクションタスクの終了を待つための mpsc チャネル
+        // 各タスクは sender のクローンを保持し、タスクが終了すると drop される
+        // すべての sender が drop されると、receiver の `recv()` が `None` を返す
+        let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel::<()>(1);
+
+        let result = tokio::select! {
+            res = self.accept_loop(&mut listener, &notify_shutdown, &shutdown_complete_tx) => res,
+            _ = self.save_periodically() => Ok(()),
+            _ = shutdown => {
+                tracing::info!("shutting down");
+                Ok(())
+            }
+        };
+
+        // sender を drop して、すべてのコネクションタスクにシャットダウンを通知する
+        drop(notify_shutdown);
+        // 自身が保持する sender も drop しないと、下の `recv()` が終わらない
+        drop(shutdown_complete_tx);
+
+        // すべてのコネクションタスクが終了するまで待つ
+        let _ = shutdown_complete_rx.recv().await;
+
+        if let Some(aof) = &self.aof {
+            if let Err(err) = aof.sync() {
+                tracing::error!("Failed flushing the AOF before shutting down: {}", err);
+            }
+        }
+
+        // 保存条件が設定されている場合は、終了前にスナップショットを保存する
+        if !self.config.save_points().is_empty() {
+            // 実行中の BGSAVE が後から古いスナップショットで上書きしないように、終わるのを待つ
+            while self.stats.bgsave_in_progress.load(Ordering::SeqCst) {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+            match rdb::save(&*self.storage, &self.config, &self.stats) {
+                Ok(()) => tracing::info!("DB saved on disk"),
+                Err(err) => tracing::error!("Failed saving the DB before shutting down: {}", err),
+            }
+        }
+
+        result
+    }
+
+    /// Start a `BGSAVE` whenever one of the `save` points is reached, i.e. at
+    /// least `changes` writes were made and `seconds` elapsed since the last
+    /// save. Never returns.
+    async fn save_periodically(&self) {
+        /// Delay before retrying a background save that failed
+        const RETRY_DELAY: u64 = 5;
+
+        let mut interval = time::interval(Duration::from_secs(1));
+        let mut last_attempt = 0;
+        loop {
+            interval.tick().await;
+
+            let now = clock::unix_time();
+            let changes = self.stats.changes_since_last_save.load(Ordering::Relaxed);
+            let elapsed = now.saturating_sub(self.stats.last_save_time.load(Ordering::Relaxed));
+            let reached = self
+                .config
+                .save_points()
+                .iter()
+                .any(|&(seconds, min_changes)| changes >= min_changes && elapsed >= seconds);
+            // 失敗した直後は、すぐに再試行せず少し待つ
+            let failed_recently = !self.stats.last_bgsave_ok.load(Ordering::Relaxed)
+                && now.saturating_sub(last_attempt) < RETRY_DELAY;
+
+            if reached && !failed_recently {
+                last_attempt = now;
+                if rdb::bgsave(&*self.storage, &self.config, &self.stats).is_ok() {
+                    tracing::info!("{} changes in {} seconds. Saving...", changes, elapsed);
+                }
+            }
+        }
     }
 
-    pub async fn run(&self) {
-        let listener = TcpListener::bind(&self.addr).await.unwrap();
-        tracing::info!("Listening on {}", self.addr);
+    async fn accept_loop<L: Listener>(
+        &self,
+        listener: &mut L,
+        notify_shutdown: &broadcast::Sender<()>,
+        shutdown_complete_tx: &mpsc::Sender<()>,
+    ) -> crate::Result<()> {
+        // 接続数の上限は、接続ごとに 1 つずつ取得するセマフォの許可証で管理する
+        let mut limit = self.config.maxclients();
+        let limit_connections = Arc::new(Semaphore::new(limit));
+        // 上限を減らしたときに、まだ破棄できていない許可証の数
+        let mut owed = 0;
 
         loop {
-            // タプルの 2 つ目の要素は、新しいコネクションの IP とポートの情報を含んでいる
-            let (socket, socket_addr) = listener.accept().await.unwrap();
+            // タプルの 2 つ目の要素は、新しいコネクションの接続元 (TCP なら IP とポート) を表す
+            let (socket, socket_addr) = MiniRedisServer::accept(listener).await?;
             tracing::info!("Accepted connection from {}", socket_addr);
 
+            self.stats
+                .total_connections_received
+                .fetch_add(1, Ordering::Relaxed);
+
+            // CONFIG SET maxclients で上限が変わった場合は、許可証の数を合わせる
+            // 増やす場合は、まだ破棄できていない分を取り消してから、残りを追加する
+            let maxclients = self.config.maxclients();
+            if maxclients > limit {
+                let grow = maxclients - limit;
+                let cancelled = grow.min(owed);
+                owed -= cancelled;
+                limit_connections.add_permits(grow - cancelled);
+            } else {
+                owed += limit - maxclients;
+            }
+            limit = maxclients;
+
+            // 減らした分は、接続中のクライアントが切断して返された許可証から破棄していく
+            // 許可証を取得するのはこのループだけなので、空いている分は必ず取得できる
+            let paid = owed.min(limit_connections.available_permits());
+            if paid > 0 {
+                if let Ok(permits) = limit_connections.try_acquire_many(paid as u32) {
+                    permits.forget();
+                    owed -= paid;
+                }
+            }
+
+            // 接続数の上限に達している場合は、エラーを返してコネクションを閉じる
+            // エラーの書き込みで accept ループが止まらないように、別タスクで処理する
+            let permit = match limit_connections.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    tracing::warn!("Rejected connection from {}: max clients", socket_addr);
+                    self.stats
+                        .rejected_connections
+                        .fetch_add(1, Ordering::Relaxed);
+                    tokio::spawn(async move {
+                        let mut connection = Connection::new(socket);
+                        let frame = Frame::Error("ERR max number of clients reached".to_string());
+                        if connection.write_frame(&frame).await.is_ok() {
+                            let _ = connection.flush().await;
+                        }
+                    });
+                    continue;
+                }
+            };
+            let client = Client::new(permit, self.stats.clone());
+
             // それぞれのインバウンドソケットに対して、新しいタスクを生成 spawn する
             // ソケットは新しいタスクに move され、そこで処理がされる
-            let db = self.db.clone();
+            let db = self.storage.clone();
+            let mut session =
+                Session::new(self.acl.clone(), self.config.clone(), self.stats.clone());
+            session.aof = self.aof.clone();
+            let shutdown = Shutdown::new(notify_shutdown.subscribe());
+            let shutdown_complete = shutdown_complete_tx.clone();
+            let connection_kind = self.connection_kind;
             tokio::spawn(async move {
-                MiniRedisServer::process(socket, db).await; // variable `socket` moved here!
+                // variable `socket` moved here!
+                match connection_kind {
+                    ConnectionKind::Buffered => {
+                        MiniRedisServer::process::<Connection<_>, _>(socket, db, session, shutdown)
+                            .await
+                    }
+                    ConnectionKind::Raw => {
+                        MiniRedisServer::process::<connection_raw::Connection<_>, _>(
+                            socket, db, session, shutdown,
+                        )
+                        .await
+                    }
+                }
+
+                // タスクの終了を通知する
+                // 許可証を返して、次のクライアントが接続できるようにする
+                // タスクが panic した場合も、client と shutdown_complete は drop される
+                drop(client);
+                drop(shutdown_complete);
             });
         }
     }
 
-    async fn process(socket: TcpStream, db: Db) {
-        // `Connection` 型を使うことで、バイト列ではなく、Redis の「フレーム」を読み書きできるようになる。この `Connection` 型は mini-redis で定義されている。
-        let mut connection = Connection::new(socket); // ソケットから来るフレームをパースする
+    /// Accept an inbound connection.
+    ///
+    /// Errors are handled by backing off and retrying. An exponential backoff
+    /// strategy is used. After the first failure, the task waits for 1 second.
+    /// After the second failure, the task waits for 2 seconds. Each subsequent
+    /// failure doubles the wait time. If accepting fails on the 6th try after
+    /// waiting for 64 seconds, then this function returns with an error.
+    async fn accept<L: Listener>(listener: &mut L) -> crate::Result<(L::Io, String)> {
+        let mut backoff = 1;
+
+        // Try to accept a few times
+        loop {
+            // Perform the accept operation. If a socket is successfully
+            // accepted, return it. Otherwise, save the error.
+            match listener.accept().await {
+                Ok(accepted) => return Ok(accepted),
+                Err(err) => {
+                    if backoff > 64 {
+                        // Accept has failed too many times. Return the error.
+                        return Err(err.into());
+                    }
+                    tracing::error!(
+                        "failed to accept connection, retrying in {}s: {}",
+                        backoff,
+                        err
+                    );
+                }
+            }
+
+            // Pause execution until the back off period elapses.
+            time::sleep(Duration::from_secs(backoff)).await;
+
+            // Double the back off
+            backoff *= 2;
+        }
+    }
+
+    async fn process<C: ConnectionTrait<S> + Send, S: Stream>(
+        socket: S,
+        db: Arc<dyn Storage>,
+        mut session: Session, // HELLO で切り替えたプロトコルなど、コネクションごとの状態
+        mut shutdown: Shutdown,
+    ) {
+        // `Connection` 型を使うことで、バイト列ではなく、Redis の「フレーム」を読み書きできるようになる。
+        let mut connection = C::new(socket); // ソケットから来るフレームをパースする
+
+        // シャットダウンが通知されるまで、フレームを読み取ってコマンドを実行する
+        while !shutdown.is_shutdown() {
+            // フレームの読み取り中にシャットダウンが通知された場合は、読み取りを中断する
+            // 実行中のコマンドは中断されず、レスポンスを書き込んでからループを抜ける
+            let res = tokio::select! {
+                res = connection.read_frame() => res,
+                _ = shutdown.recv() => return,
+            };
+            let mut frame = match res {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return,
+                Err(err) => {
+                    return MiniRedisServer::reply_protocol_error(&mut connection, err).await
+                }
+            };
+
+            // パイプラインで送られてきたコマンドがすでにバッファに溜まっている場合は、続けて実行する
+            // 応答は write バッファに溜めておき、最後に 1 回だけ flush する
+            // こうすることで、1000 個のコマンドがまとめて送られてきても、1000 回 write システムコールを呼ばずに済む
+            loop {
+                tracing::debug!("GOT frame: {:?}", frame);
+
+                // フレームをパースしてコマンドを実行する
+                let response = MiniRedisServer::handle_frame(frame, &*db, &mut session).await;
 
-        while let Ok(Some(frame)) = connection.read_frame().await {
-            tracing::info!("GOT frame: {:?}", frame);
+                // HELLO の応答は、切り替え後のプロトコルで返す
+                connection.set_protocol(session.protocol);
+                if let Err(e) = connection.write_frame(&response).await {
+                    tracing::error!("Failed to write frame: {:?}", e);
+                    return;
+                }
+
+                frame = match connection.parse_frame() {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break,
+                    Err(err) => {
+                        return MiniRedisServer::reply_protocol_error(&mut connection, err).await
+                    }
+                };
+            }
 
-            // フレームをパースしてコマンドを実行する
-            let response = MiniRedisServer::handle_frame(frame, db.clone());
-            if let Err(e) = connection.write_frame(&response).await {
+            if let Err(e) = connection.flush().await {
                 tracing::error!("Failed to write frame: {:?}", e);
                 return;
             }
         }
     }
 
-    fn handle_frame(frame: Frame, db: Db) -> Frame {
+    /// 不正なバイト列を受け取った場合は、エラーを返してからコネクションを閉じる
+    /// フレームの境界が分からなくなるため、それ以降のデータは読み取れない
+    async fn reply_protocol_error<C: ConnectionTrait<S> + Send, S: Stream>(
+        connection: &mut C,
+        err: crate::Error,
+    ) {
+        if let Some(frame::Error::Other(err)) = err.downcast_ref::<frame::Error>() {
+            tracing::debug!("protocol error: {}", err);
+            let response = Frame::Error(format!("ERR Protocol error: {}", err));
+            if connection.write_frame(&response).await.is_ok() {
+                let _ = connection.flush().await;
+            }
+        }
+    }
+
+    async fn handle_frame(frame: Frame, db: &dyn Storage, session: &mut Session) -> Frame {
+        // AOF に記録するために、パースする前のフレームを残しておく
+        let logged = session.aof.is_some().then(|| frame.clone());
+
         // フレームをパースして、コマンドを取得する
-        match Command::from_frame(frame).unwrap() {
-            Set(cmd) => {
-                tracing::info!("SET {:?}", cmd);
-
-                match db.lock() {
-                    Ok(mut db) => {
-                        // DB に値をセットする
-                        tracing::info!("SET: key={:?}, value={:?}", cmd.key(), cmd.value());
-                        db.insert(cmd.key().to_string(), cmd.value().to_vec());
-                        Frame::Simple("OK".to_string())
-                    }
-                    Err(err) => {
-                        tracing::error!("lock error: {:?}", err);
-                        Frame::Error("lock error".to_string())
-                    }
-                }
+        // パースに失敗した場合 (未知のフレーム形式、引数の数の誤りなど) は `-ERR` を返す
+        let cmd = match Command::from_frame(frame) {
+            Ok(cmd) => cmd,
+            Err(err) => {
+                tracing::debug!("failed to parse command: {}", err);
+                return Frame::Error(format!("ERR {}", err));
             }
-            Get(cmd) => {
-                tracing::info!("GET {:?}", cmd);
-
-                // DB から値を取り出す
-                match db.lock() {
-                    Ok(db) => match db.get(cmd.key()) {
-                        Some(value) => {
-                            tracing::info!(
-                                "GET: key={:?}, value={:?}",
-                                cmd.key(),
-                                db.get(cmd.key())
-                            );
-                            Frame::Bulk(value.clone().into())
-                        }
-                        None => {
-                            tracing::info!("GET: No value found for key {:?}", cmd.key());
-                            Frame::Null
-                        }
-                    },
-                    Err(err) => {
-                        tracing::error!("lock error: {:?}", err);
-                        Frame::Error("lock error".to_string())
-                    }
+        };
+        tracing::debug!("{} {:?}", cmd.get_name(), cmd);
+        session
+            .stats
+            .total_commands_processed
+            .fetch_add(1, Ordering::Relaxed);
+
+        // 認証が済んでいないクライアントには、AUTH 以外のコマンドを実行させない
+        // 認証済みでも、ACL でユーザーに許可されていないコマンドやキーは拒否する
+        if !matches!(cmd, Command::Auth(_) | Command::Unknown(_)) {
+            let user = match &session.user {
+                Some(user) => user,
+                None => return Frame::Error("NOAUTH Authentication required.".to_string()),
+            };
+
+            match session.acl.check(user, &cmd) {
+                Ok(()) => {}
+                Err(Denied::User) => {
+                    session.user = None;
+                    return Frame::Error("NOAUTH Authentication required.".to_string());
+                }
+                Err(Denied::Command) => {
+                    return Frame::Error(format!(
+                        "NOPERM User {} has no permissions to run the '{}' command",
+                        user,
+                        cmd.get_name()
+                    ))
+                }
+                Err(Denied::Key) => {
+                    return Frame::Error("NOPERM No permissions to access a key".to_string())
                 }
             }
-            _ => Frame::Error("unimplemented".to_string()),
         }
+
+        // MIGRATE は移行先の応答を待つので、AOF をロックせずに非同期に実行する
+        if let Command::Migrate(cmd) = cmd {
+            return cmd.execute(db, session).await;
+        }
+
+        let (response, seq) = MiniRedisServer::apply(cmd, logged, db, session);
+
+        // appendfsync always の場合は、AOF がディスクに書き込まれてから応答する
+        if let (Some(aof), Some(seq)) = (&session.aof, seq) {
+            aof.wait(seq).await;
+        }
+        response
+    }
+
+    /// コマンドを実行して、応答と AOF に記録したコマンドの番号を返す
+    fn apply(
+        cmd: Command,
+        logged: Option<Frame>,
+        db: &dyn Storage,
+        session: &mut Session,
+    ) -> (Frame, Option<u64>) {
+        // 書き込みコマンドは AOF をロックしたまま実行して記録し、実行した順に AOF に並ぶようにする
+        // ロック中はメモリ上のバッファに追記するだけで、ファイルへの書き込みはロックの外で行う
+        let is_write = cmd.is_write();
+        let aof = session.aof.clone().filter(|_| is_write);
+        let mut log = aof.as_ref().map(|aof| aof.lock());
+        let keys: Vec<String> = match log {
+            Some(_) => cmd.keys().into_iter().map(str::to_string).collect(),
+            None => vec![],
+        };
+        let response = cmd.apply(db, session);
+
+        // 保存条件の判定のために、キー空間が変更された回数を数える
+        // DEL のように整数を返すコマンドは、変更したキーの数を返す
+        let mut seq = None;
+        if is_write {
+            let changes = match response {
+                Frame::Error(_) | Frame::Null => 0,
+                Frame::Integer(n) => n.max(0) as u64,
+                _ => 1,
+            };
+            session
+                .stats
+                .changes_since_last_save
+                .fetch_add(changes, Ordering::Relaxed);
+
+            if let (Some(log), Some(frame), true) = (&mut log, &logged, changes > 0) {
+                seq = Some(log.append(frame, &keys, db));
+            }
+        }
+
+        (response, seq)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::testing::{serve, serve_in_memory};
+    use super::*;
+    use crate::db::Db;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::oneshot;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
@@ -117,4 +739,397 @@ mod tests {
     fn it_works2() {
         panic!("Make this test fail");
     }
+
+    #[tokio::test]
+    async fn serve_returns_after_connections_drain() {
+        let server = MiniRedisServer::new("127.0.0.1:0".to_string(), Db::default());
+        let (addr, tx, handle) = serve(server).await;
+
+        let mut client = TcpStream::connect(&addr).await.unwrap();
+        client.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+
+        let mut response = [0; 7];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"+PONG\r\n");
+
+        // The connection is closed by the server and `serve` returns.
+        tx.send(()).unwrap();
+        assert_eq!(client.read(&mut response).await.unwrap(), 0);
+        handle.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn surplus_clients_are_rejected() {
+        let server =
+            MiniRedisServer::new("127.0.0.1:0".to_string(), Db::default()).with_max_clients(1);
+        let (addr, _tx, _handle) = serve(server).await;
+
+        let mut first = TcpStream::connect(&addr).await.unwrap();
+        first.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+        let mut response = [0; 7];
+        first.read_exact(&mut response).await.unwrap();
+
+        let mut second = TcpStream::connect(&addr).await.unwrap();
+        let mut response = Vec::new();
+        second.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response, b"-ERR max number of clients reached\r\n");
+
+        // The slot is released once the first client disconnects
+        drop(first);
+        loop {
+            let mut third = TcpStream::connect(&addr).await.unwrap();
+            third.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+            let mut response = [0; 7];
+            third.read_exact(&mut response).await.unwrap();
+            if &response == b"+PONG\r\n" {
+                break;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn maxclients_can_shrink_and_grow() {
+        let server =
+            MiniRedisServer::new("127.0.0.1:0".to_string(), Db::default()).with_max_clients(2);
+        let (addr, _tx, _handle) = serve(server).await;
+
+        async fn request(stream: &mut TcpStream, request: &[u8], len: usize) -> Vec<u8> {
+            stream.write_all(request).await.unwrap();
+            let mut response = vec![0; len];
+            stream.read_exact(&mut response).await.unwrap();
+            response
+        }
+
+        let mut first = TcpStream::connect(&addr).await.unwrap();
+        let shrink = b"CONFIG SET maxclients 1\r\n";
+        assert_eq!(request(&mut first, shrink, 5).await, b"+OK\r\n");
+
+        let mut second = TcpStream::connect(&addr).await.unwrap();
+        let mut response = Vec::new();
+        second.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response, b"-ERR max number of clients reached\r\n");
+
+        // The first client is still connected, and a second one fits again
+        let grow = b"CONFIG SET maxclients 2\r\n";
+        assert_eq!(request(&mut first, grow, 5).await, b"+OK\r\n");
+        let mut third = TcpStream::connect(&addr).await.unwrap();
+        assert_eq!(request(&mut third, b"PING\r\n", 7).await, b"+PONG\r\n");
+    }
+
+    #[tokio::test]
+    async fn protocol_error_closes_the_connection() {
+        let server = MiniRedisServer::new("127.0.0.1:0".to_string(), Db::default());
+        let (addr, _tx, _handle) = serve(server).await;
+
+        // The valid command is answered, the garbage after it closes the
+        // connection without running the last command
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(b"*1\r\n$4\r\nPING\r\n*x\r\n*1\r\n$4\r\nPING\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert_eq!(
+            response,
+            b"+PONG\r\n-ERR Protocol error: invalid frame format\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn hello_switches_the_protocol() {
+        let server = MiniRedisServer::new("127.0.0.1:0".to_string(), Db::default());
+        let (addr, _tx, _handle) = serve(server).await;
+
+        async fn request(stream: &mut TcpStream, request: &[u8], len: usize) -> Vec<u8> {
+            stream.write_all(request).await.unwrap();
+            let mut response = vec![0; len];
+            stream.read_exact(&mut response).await.unwrap();
+            response
+        }
+
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        let get = b"*2\r\n$3\r\nGET\r\n$7\r\nmissing\r\n";
+
+        // Null is sent as a RESP2 null bulk string until RESP3 is selected
+        assert_eq!(request(&mut stream, get, 5).await, b"$-1\r\n");
+        assert_eq!(
+            request(&mut stream, b"*2\r\n$5\r\nHELLO\r\n$1\r\n4\r\n", 39).await,
+            &b"-NOPROTO unsupported protocol version\r\n"[..]
+        );
+
+        // The reply to HELLO itself already uses RESP3
+        let response = request(&mut stream, b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n", 4).await;
+        assert_eq!(response, b"%6\r\n");
+        let mut rest = Vec::new();
+        while !rest.ends_with(b"$7\r\nmodules\r\n*0\r\n") {
+            rest.push(stream.read_u8().await.unwrap());
+        }
+
+        assert_eq!(request(&mut stream, get, 3).await, b"_\r\n");
+    }
+
+    #[tokio::test]
+    async fn maps_are_sent_as_arrays_in_resp2() {
+        let server = MiniRedisServer::new("127.0.0.1:0".to_string(), Db::default());
+        let (addr, _tx, _handle) = serve(server).await;
+
+        async fn config_get(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+            stream
+                .write_all(b"CONFIG GET maxclients\r\n")
+                .await
+                .unwrap();
+            let mut response = vec![0; len];
+            stream.read_exact(&mut response).await.unwrap();
+            response
+        }
+
+        let mut resp2 = TcpStream::connect(&addr).await.unwrap();
+        assert_eq!(
+            config_get(&mut resp2, 32).await,
+            b"*2\r\n$10\r\nmaxclients\r\n$5\r\n10000\r\n"
+        );
+
+        let mut resp3 = TcpStream::connect(&addr).await.unwrap();
+        resp3.write_all(b"HELLO 3\r\n").await.unwrap();
+        let mut hello = Vec::new();
+        while !hello.ends_with(b"$7\r\nmodules\r\n*0\r\n") {
+            hello.push(resp3.read_u8().await.unwrap());
+        }
+        assert!(hello.starts_with(b"%6\r\n"));
+        assert_eq!(
+            config_get(&mut resp3, 32).await,
+            b"%1\r\n$10\r\nmaxclients\r\n$5\r\n10000\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn inline_commands_are_accepted() {
+        let server = MiniRedisServer::new("127.0.0.1:0".to_string(), Db::default());
+        let (addr, _tx, _handle) = serve(server).await;
+
+        // Inline commands, as typed into `nc`, can be mixed with frames
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(b"SET a \"hello world\"\n\r\n*2\r\n$3\r\nGET\r\n$1\r\na\r\nping\r\n")
+            .await
+            .unwrap();
+
+        let expected = b"+OK\r\n$11\r\nhello world\r\n+PONG\r\n";
+        let mut response = vec![0; expected.len()];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, expected);
+    }
+
+    #[tokio::test]
+    async fn serves_in_memory_streams() {
+        for kind in [ConnectionKind::Buffered, ConnectionKind::Raw] {
+            let server = MiniRedisServer::new("in-memory".to_string(), Db::default())
+                .with_connection_kind(kind);
+            let (mut client, tx, handle) = serve_in_memory(server).await;
+
+            client
+                .write_all(
+                    b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n",
+                )
+                .await
+                .unwrap();
+
+            let mut response = [0; 12];
+            client.read_exact(&mut response).await.unwrap();
+            assert_eq!(&response, b"+OK\r\n$1\r\nv\r\n");
+
+            tx.send(()).unwrap();
+            assert_eq!(client.read(&mut response).await.unwrap(), 0);
+            handle.await.unwrap().unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_a_unix_socket() {
+        use std::os::unix::fs::PermissionsExt;
+        use tokio::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("mini-redis-{}.sock", std::process::id()));
+        let server = MiniRedisServer::new("127.0.0.1:0".to_string(), Db::default())
+            .with_unix_socket(&path, Some(0o700))
+            .without_tcp();
+        let (tx, rx) = oneshot::channel::<()>();
+        let handle = tokio::spawn(async move { server.run(rx).await });
+
+        let mut client = loop {
+            match UnixStream::connect(&path).await {
+                Ok(client) => break client,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        client.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+        let mut response = [0; 7];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"+PONG\r\n");
+
+        // The socket file is removed once the server shuts down
+        tx.send(()).unwrap();
+        handle.await.unwrap().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn requirepass_requires_auth() {
+        let server = MiniRedisServer::new("in-memory".to_string(), Db::default())
+            .with_requirepass("secret".to_string());
+        let (mut client, _tx, _handle) = serve_in_memory(server).await;
+
+        client
+            .write_all(b"GET k\r\nAUTH wrong\r\nAUTH admin secret\r\nAUTH secret\r\nGET k\r\n")
+            .await
+            .unwrap();
+
+        let expected: &[u8] = concat!(
+            "-NOAUTH Authentication required.\r\n",
+            "-WRONGPASS invalid username-password pair or user is disabled.\r\n",
+            "-WRONGPASS invalid username-password pair or user is disabled.\r\n",
+            "+OK\r\n",
+            "$-1\r\n",
+        )
+        .as_bytes();
+        let mut response = vec![0; expected.len()];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, expected);
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_loaded_on_startup() {
+        let dir = std::env::temp_dir().join(format!("mini-redis-rdb-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = || {
+            let config = Config::default();
+            config.set("dir", dir.display().to_string()).unwrap();
+            config
+        };
+
+        let _ = std::fs::remove_file(dir.join("dump.rdb"));
+
+        let server = MiniRedisServer::from_config(config(), Db::default()).unwrap();
+        let stats = server.stats.clone();
+        let (mut client, tx, handle) = serve_in_memory(server).await;
+        client
+            .write_all(
+                b"SET k v EX 3600\r\nSET n 1\r\nDEBUG RELOAD NOSAVE\r\nGET n\r\nSAVE\r\nBGSAVE\r\n",
+            )
+            .await
+            .unwrap();
+
+        // There is no dump file to reload yet, the keys are kept
+        let expected: &[u8] = concat!(
+            "+OK\r\n",
+            "+OK\r\n",
+            "-ERR Error trying to load the RDB dump\r\n",
+            "$1\r\n1\r\n",
+            "+OK\r\n",
+            "+Background saving started\r\n"
+        )
+        .as_bytes();
+        let mut response = vec![0; expected.len()];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, expected);
+
+        tx.send(()).unwrap();
+        handle.await.unwrap().unwrap();
+        while stats.bgsave_in_progress.load(Ordering::SeqCst) {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(stats.last_bgsave_ok.load(Ordering::SeqCst));
+
+        let db = Db::default();
+        MiniRedisServer::from_config(config(), db.clone()).unwrap();
+        assert_eq!(db.get("n"), Some("1".into()));
+        assert!(matches!(db.expires_at("k"), Some(Some(_))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn import_rdb_skips_unsupported_types() {
+        let path =
+            std::env::temp_dir().join(format!("mini-redis-import-{}.rdb", std::process::id()));
+        let records = [
+            rdb::Record {
+                key: "s".into(),
+                value: rdb::Value::String("v".into()),
+                expires_at: None,
+            },
+            rdb::Record {
+                key: "h".into(),
+                value: rdb::Value::Hash(vec![("f".into(), "v".into())]),
+                expires_at: None,
+            },
+        ];
+        std::fs::write(&path, rdb::encode(&records)).unwrap();
+
+        let db = Db::default();
+        let server = MiniRedisServer::new("in-memory".to_string(), db.clone());
+        assert!(server.import_rdb(&path, true).is_err());
+        assert_eq!(db.get("s"), None);
+
+        assert_eq!(server.import_rdb(&path, false).unwrap(), 1);
+        assert_eq!(db.get("s"), Some("v".into()));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn migrate_moves_keys_to_another_instance() {
+        let target_db = Db::default();
+        let target = MiniRedisServer::new("127.0.0.1:0".to_string(), target_db.clone());
+        let (target_addr, target_tx, target) = serve(target).await;
+        let (_, port) = target_addr.rsplit_once(':').unwrap();
+
+        let db = Db::default();
+        let server = MiniRedisServer::new("in-memory".to_string(), db.clone());
+        let (mut client, tx, handle) = serve_in_memory(server).await;
+        let commands = [
+            "SET a 1 PX 60000".to_string(),
+            "SET b 2".to_string(),
+            format!("MIGRATE 127.0.0.1 {} \"\" 0 1000 KEYS a b missing", port),
+            format!("MIGRATE 127.0.0.1 {} a 0 1000", port),
+            "SET b 3".to_string(),
+            format!("MIGRATE 127.0.0.1 {} b 0 1000 COPY", port),
+            format!("MIGRATE 127.0.0.1 {} b 0 1000 COPY REPLACE", port),
+        ];
+        for command in commands {
+            client.write_all(command.as_bytes()).await.unwrap();
+            client.write_all(b"\r\n").await.unwrap();
+        }
+
+        let expected: &[u8] = concat!(
+            "+OK\r\n",
+            "+OK\r\n",
+            "+OK\r\n",
+            "+NOKEY\r\n",
+            "+OK\r\n",
+            "-ERR Target instance replied with error: BUSYKEY Target key name already exists.\r\n",
+            "+OK\r\n",
+        )
+        .as_bytes();
+        let mut response = vec![0; expected.len()];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, expected);
+
+        assert_eq!(db.get("a"), None);
+        assert_eq!(db.get("b"), Some("3".into()));
+        assert_eq!(target_db.get("a"), Some("1".into()));
+        assert!(matches!(target_db.expires_at("a"), Some(Some(_))));
+        assert_eq!(target_db.get("b"), Some("3".into()));
+
+        tx.send(()).unwrap();
+        handle.await.unwrap().unwrap();
+        target_tx.send(()).unwrap();
+        target.await.unwrap().unwrap();
+    }
 }
@@ -0,0 +1,74 @@
+//! Fixtures running a `MiniRedisServer` in tests.
+
+use crate::listener::Listener;
+use crate::server::MiniRedisServer;
+
+use async_trait::async_trait;
+use std::io;
+use tokio::io::DuplexStream;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+/// Handle of the task running a server, returning the result of `serve`
+pub(crate) type ServerHandle = JoinHandle<crate::Result<()>>;
+
+/// Serve `server` on a free local TCP port.
+///
+/// Returns the address to connect to, the sender shutting the server down
+/// and the handle of the task running it. Dropping the sender also shuts the
+/// server down.
+pub(crate) async fn serve(server: MiniRedisServer) -> (String, oneshot::Sender<()>, ServerHandle) {
+    serve_with(server, |listener| listener).await
+}
+
+/// Same as `serve`, with the bound `TcpListener` wrapped by `wrap`, e.g. in a
+/// `TlsListener`.
+pub(crate) async fn serve_with<L: Listener + 'static>(
+    server: MiniRedisServer,
+    wrap: impl FnOnce(TcpListener) -> L,
+) -> (String, oneshot::Sender<()>, ServerHandle) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let listener = wrap(listener);
+    let (tx, rx) = oneshot::channel::<()>();
+    let handle = tokio::spawn(async move { server.serve(listener, rx).await });
+
+    (addr, tx, handle)
+}
+
+/// Serve `server` on in-memory streams, without binding a port.
+///
+/// Returns the client side of a single connection instead of an address.
+pub(crate) async fn serve_in_memory(
+    server: MiniRedisServer,
+) -> (DuplexStream, oneshot::Sender<()>, ServerHandle) {
+    let (streams, rx) = mpsc::channel(1);
+    let (tx, shutdown) = oneshot::channel::<()>();
+    let handle = tokio::spawn(async move { server.serve(ChannelListener(rx), shutdown).await });
+
+    let (client, server_side) = tokio::io::duplex(4096);
+    streams.send(server_side).await.unwrap();
+
+    (client, tx, handle)
+}
+
+/// Hands the server in-memory streams sent over a channel
+struct ChannelListener(mpsc::Receiver<DuplexStream>);
+
+#[async_trait]
+impl Listener for ChannelListener {
+    type Io = DuplexStream;
+
+    async fn accept(&mut self) -> io::Result<(DuplexStream, String)> {
+        match self.0.recv().await {
+            Some(stream) => Ok((stream, "in-memory".to_string())),
+            // No more clients, wait for the shutdown signal
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        Ok("in-memory".to_string())
+    }
+}
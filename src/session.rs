@@ -0,0 +1,49 @@
+//! State kept for each client connection.
+
+use crate::acl::Acl;
+use crate::aof::Aof;
+use crate::config::Config;
+use crate::frame::Protocol;
+use crate::stats::Stats;
+
+use std::sync::Arc;
+
+/// State of a single client connection.
+///
+/// A new `Session` is created for each accepted connection and handed to every
+/// command run on it, so commands such as `HELLO` can change how the following
+/// replies are sent.
+#[derive(Debug)]
+pub(crate) struct Session {
+    /// protocol version replies are encoded with
+    pub(crate) protocol: Protocol,
+    /// users of the server, shared by all connections
+    pub(crate) acl: Arc<Acl>,
+    /// configuration of the server, shared by all connections
+    pub(crate) config: Arc<Config>,
+    /// statistics of the server, shared by all connections
+    pub(crate) stats: Arc<Stats>,
+    /// append-only file writes are logged to, if enabled
+    pub(crate) aof: Option<Arc<Aof>>,
+    /// user the client is authenticated as, `None` until it runs `AUTH`
+    pub(crate) user: Option<String>,
+}
+
+impl Session {
+    /// Create the session of a new connection. Clients are authenticated as
+    /// the `default` user, unless it requires a password.
+    pub(crate) fn new(acl: Arc<Acl>, config: Arc<Config>, stats: Arc<Stats>) -> Session {
+        let user = acl
+            .authenticate("default", None)
+            .then(|| "default".to_string());
+
+        Session {
+            protocol: Protocol::default(),
+            acl,
+            config,
+            stats,
+            aof: None,
+            user,
+        }
+    }
+}
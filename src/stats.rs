@@ -0,0 +1,56 @@
+//! Statistics of the server, reported by `INFO`.
+
+use crate::clock::unix_time;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// Counters updated by the server and its connections.
+#[derive(Debug)]
+pub(crate) struct Stats {
+    /// number of clients currently connected
+    pub(crate) connected_clients: AtomicUsize,
+    /// number of connections accepted, including rejected ones
+    pub(crate) total_connections_received: AtomicU64,
+    /// number of commands run
+    pub(crate) total_commands_processed: AtomicU64,
+    /// number of connections rejected because of `maxclients`
+    pub(crate) rejected_connections: AtomicU64,
+    /// number of writes since the last snapshot was saved
+    pub(crate) changes_since_last_save: AtomicU64,
+    /// Unix time in seconds of the last successful save, or of the startup
+    pub(crate) last_save_time: AtomicU64,
+    /// whether a `BGSAVE` is writing a snapshot
+    pub(crate) bgsave_in_progress: AtomicBool,
+    /// whether the last `BGSAVE` succeeded
+    pub(crate) last_bgsave_ok: AtomicBool,
+    /// whether a `BGREWRITEAOF` is rewriting the AOF
+    pub(crate) aof_rewrite_in_progress: AtomicBool,
+}
+
+impl Stats {
+    /// Reset the counters, as `CONFIG RESETSTAT` does.
+    ///
+    /// `connected_clients` and the persistence fields describe the current
+    /// state rather than counting events, so they are kept.
+    pub(crate) fn reset(&self) {
+        self.total_connections_received.store(0, Ordering::Relaxed);
+        self.total_commands_processed.store(0, Ordering::Relaxed);
+        self.rejected_connections.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats {
+            connected_clients: AtomicUsize::new(0),
+            total_connections_received: AtomicU64::new(0),
+            total_commands_processed: AtomicU64::new(0),
+            rejected_connections: AtomicU64::new(0),
+            changes_since_last_save: AtomicU64::new(0),
+            last_save_time: AtomicU64::new(unix_time()),
+            bgsave_in_progress: AtomicBool::new(false),
+            last_bgsave_ok: AtomicBool::new(true),
+            aof_rewrite_in_progress: AtomicBool::new(false),
+        }
+    }
+}
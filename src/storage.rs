@@ -0,0 +1,140 @@
+//! The keyspace interface commands are executed against.
+//!
+//! `MiniRedisServer` only talks to its keyspace through the `Storage` trait,
+//! so the default in-memory `Db` can be replaced by another store, e.g. a
+//! persistent or instrumented one, or by a fake in unit tests.
+
+use bytes::Bytes;
+use std::collections::BTreeSet;
+use tokio::time::Instant;
+
+/// Condition under which `Storage::set` stores a value, as selected by the
+/// `NX` and `XX` options of `SET`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SetCondition {
+    /// Always store the value.
+    #[default]
+    Always,
+
+    /// Only store the value if the key does not already exist (`NX`).
+    IfNotExists,
+
+    /// Only store the value if the key already exists (`XX`).
+    IfExists,
+}
+
+/// A key / value store with per-key deadlines.
+///
+/// Keys whose deadline has passed must behave exactly as if they did not
+/// exist. Implementations are shared by every connection, so all methods take
+/// `&self` and must be safe to call concurrently.
+pub trait Storage: Send + Sync + 'static {
+    /// Get the value associated with a key.
+    ///
+    /// Returns `None` if there is no value associated with the key, or if the
+    /// key has expired. `Bytes` is reference counted, so returning the value
+    /// does not copy it.
+    fn get(&self, key: &str) -> Option<Bytes>;
+
+    /// Get the values associated with several keys.
+    ///
+    /// The default implementation looks the keys up one by one. Stores that
+    /// can do so should override it to read all keys from a consistent view.
+    fn get_many(&self, keys: &[String]) -> Vec<Option<Bytes>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
+    /// Set the value associated with a key together with an optional
+    /// expiration instant.
+    ///
+    /// The value is only stored when `condition` holds. Setting a key removes
+    /// any deadline previously associated with it. Returns `true` if the value
+    /// was stored.
+    fn set(
+        &self,
+        key: String,
+        value: Bytes,
+        expires_at: Option<Instant>,
+        condition: SetCondition,
+    ) -> bool;
+
+    /// Get the value associated with a key together with its deadline.
+    ///
+    /// The default implementation calls `get` then `expires_at`, so a write
+    /// made in between may be partially reflected. Stores that can do so
+    /// should override it to read both at once.
+    fn get_entry(&self, key: &str) -> Option<(Bytes, Option<Instant>)> {
+        let value = self.get(key)?;
+        let expires_at = self.expires_at(key)?;
+        Some((value, expires_at))
+    }
+
+    /// Remove the given keys.
+    ///
+    /// Returns the number of keys that existed and were removed.
+    fn del(&self, keys: &[String]) -> usize;
+
+    /// Remove `key` only if it still holds `value` and expires at
+    /// `expires_at`, e.g. once a copy of it was written elsewhere.
+    ///
+    /// The comparison and the removal must be atomic, so that a write made
+    /// concurrently is never lost. Returns `true` if the key was removed.
+    fn del_if_unchanged(&self, key: &str, value: &Bytes, expires_at: Option<Instant>) -> bool;
+
+    /// Rename `key` to `new_key`, keeping its value and deadline. Any value
+    /// held by `new_key` is overwritten.
+    ///
+    /// Returns `false` if `key` does not exist.
+    fn rename(&self, key: &str, new_key: &str) -> bool;
+
+    /// Incrementally iterate over the keys.
+    ///
+    /// Iteration starts with cursor `0` and returns up to about `count` keys
+    /// together with the cursor to pass to the next call. Iteration is complete
+    /// when the returned cursor is `0`. Keys that exist during the whole
+    /// iteration should be returned at least once, and may be returned more
+    /// than once.
+    fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<String>);
+
+    /// Set the instant at which an existing key expires, replacing any
+    /// previous deadline. `None` removes the deadline, making the key
+    /// persistent.
+    ///
+    /// Returns `false` if the key does not exist, or if `expires_at` is `None`
+    /// and the key had no deadline.
+    fn expire(&self, key: &str, expires_at: Option<Instant>) -> bool;
+
+    /// Get the deadline of a key.
+    ///
+    /// Returns `None` if the key does not exist and `Some(None)` if the key
+    /// exists but has no associated deadline.
+    fn expires_at(&self, key: &str) -> Option<Option<Instant>>;
+
+    /// Copy every live key along with its value and deadline, e.g. to write a
+    /// snapshot of the keyspace.
+    ///
+    /// The default implementation reads the keys one by one while iterating
+    /// with `scan`, so writes made meanwhile may be partially reflected. Stores
+    /// that can do so should override it to copy all keys from a consistent
+    /// view.
+    fn snapshot(&self) -> Vec<(String, Bytes, Option<Instant>)> {
+        let mut keys = BTreeSet::new();
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = self.scan(cursor, 1000);
+            keys.extend(batch);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        keys.into_iter()
+            .filter_map(|key| {
+                let value = self.get(&key)?;
+                let expires_at = self.expires_at(&key)?;
+                Some((key, value, expires_at))
+            })
+            .collect()
+    }
+}
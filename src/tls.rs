@@ -0,0 +1,219 @@
+//! TLS support, serving clients over rustls.
+
+use crate::listener::Listener;
+
+use async_trait::async_trait;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// Time a client is given to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Build the TLS configuration of the server from PEM files.
+///
+/// If `client_ca_file` is set, clients must present a certificate signed by
+/// one of the CAs it contains (mutual TLS).
+pub fn server_config(
+    cert_file: &Path,
+    key_file: &Path,
+    client_ca_file: Option<&Path>,
+) -> crate::Result<Arc<ServerConfig>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_file)?))
+        .collect::<io::Result<Vec<_>>>()?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_file)?))?
+        .ok_or_else(|| format!("no private key found in {}", key_file.display()))?;
+
+    let builder = ServerConfig::builder();
+    let builder = match client_ca_file {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in rustls_pemfile::certs(&mut BufReader::new(File::open(path)?)) {
+                roots.add(cert?)?;
+            }
+            builder
+                .with_client_cert_verifier(WebPkiClientVerifier::builder(Arc::new(roots)).build()?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(Arc::new(builder.with_single_cert(certs, key)?))
+}
+
+/// Accepts TLS connections on top of the connections accepted by another
+/// listener, typically a `TcpListener`.
+///
+/// Handshakes run in their own tasks, so a client that is slow to complete
+/// its handshake does not hold back other clients.
+pub struct TlsListener<L: Listener> {
+    listener: L,
+    acceptor: TlsAcceptor,
+    /// Handshakes in progress, along with the address of their client
+    handshakes: JoinSet<(io::Result<TlsStream<L::Io>>, String)>,
+}
+
+impl<L: Listener> TlsListener<L> {
+    pub fn new(listener: L, config: Arc<ServerConfig>) -> Self {
+        Self {
+            listener,
+            acceptor: TlsAcceptor::from(config),
+            handshakes: JoinSet::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl<L: Listener> Listener for TlsListener<L> {
+    type Io = TlsStream<L::Io>;
+
+    async fn accept(&mut self) -> io::Result<(Self::Io, String)> {
+        loop {
+            tokio::select! {
+                res = self.listener.accept() => {
+                    let (socket, addr) = res?;
+                    let acceptor = self.acceptor.clone();
+                    self.handshakes.spawn(async move {
+                        let res = match time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+                            Ok(res) => res,
+                            Err(_) => Err(io::ErrorKind::TimedOut.into()),
+                        };
+                        (res, addr)
+                    });
+                }
+                Some(res) = self.handshakes.join_next() => match res {
+                    Ok((Ok(stream), addr)) => return Ok((stream, addr)),
+                    // ハンドシェイクの失敗はクライアント側の問題なので、accept のエラーにはしない
+                    Ok((Err(err), addr)) => {
+                        tracing::warn!("TLS handshake with {} failed: {}", addr, err);
+                    }
+                    Err(err) => tracing::error!("TLS handshake task failed: {}", err),
+                },
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        Ok(format!("tls:{}", self.listener.local_addr()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::server::testing::serve_with;
+    use crate::server::MiniRedisServer;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::oneshot;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+    use tokio_rustls::rustls::ClientConfig;
+    use tokio_rustls::TlsConnector;
+
+    /// Write `contents` to a file named `name` in a directory of the test
+    fn write_pem(test: &str, name: &str, contents: String) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mini-redis-{}-{}", std::process::id(), test));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Serve TLS clients with `config` on a local port, returning its address
+    async fn serve(config: Arc<ServerConfig>) -> (String, oneshot::Sender<()>) {
+        let server = MiniRedisServer::new("127.0.0.1:0".to_string(), Db::default());
+        let (addr, tx, _handle) =
+            serve_with(server, |listener| TlsListener::new(listener, config)).await;
+
+        (addr, tx)
+    }
+
+    /// Send `PING` over TLS, returning the reply
+    async fn ping(addr: &str, config: ClientConfig) -> io::Result<Vec<u8>> {
+        let connector = TlsConnector::from(Arc::new(config));
+        let socket = TcpStream::connect(addr).await?;
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut client = connector.connect(name, socket).await?;
+
+        client.write_all(b"*1\r\n$4\r\nPING\r\n").await?;
+        let mut response = vec![0; 7];
+        client.read_exact(&mut response).await?;
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn serves_tls_clients() {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let cert_file = write_pem("tls", "cert.pem", cert.pem());
+        let key_file = write_pem("tls", "key.pem", key.serialize_pem());
+
+        let config = server_config(&cert_file, &key_file, None).unwrap();
+        let (addr, _shutdown) = serve(config).await;
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.der().clone()).unwrap();
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        assert_eq!(ping(&addr, config).await.unwrap(), b"+PONG\r\n");
+    }
+
+    #[tokio::test]
+    async fn mutual_tls_requires_a_client_certificate() {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let signed_by_ca = |names: Vec<String>| {
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(names)
+                .unwrap()
+                .signed_by(&key, &ca, &ca_key)
+                .unwrap();
+            (cert, key)
+        };
+        let (server_cert, server_key) = signed_by_ca(vec!["localhost".to_string()]);
+        let (client_cert, client_key) = signed_by_ca(vec![]);
+
+        let config = server_config(
+            &write_pem("mtls", "cert.pem", server_cert.pem()),
+            &write_pem("mtls", "key.pem", server_key.serialize_pem()),
+            Some(&write_pem("mtls", "ca.pem", ca.pem())),
+        )
+        .unwrap();
+        let (addr, _shutdown) = serve(config).await;
+
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.der().clone()).unwrap();
+
+        // Without a client certificate, the server aborts the handshake
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots.clone())
+            .with_no_client_auth();
+        assert!(ping(&addr, config).await.is_err());
+
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(
+                vec![CertificateDer::from(client_cert.der().to_vec())],
+                PrivateKeyDer::Pkcs8(client_key.serialize_der().into()),
+            )
+            .unwrap();
+        assert_eq!(ping(&addr, config).await.unwrap(), b"+PONG\r\n");
+    }
+}